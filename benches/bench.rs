@@ -0,0 +1,291 @@
+#![feature(let_chains)]
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use vmod::{
+    ast::expr_types::ExprPossibilities,
+    interpreter::interpreter::Interpreter,
+    parser::parser::Parser,
+    resolver::resolver::Resolver,
+    scanner::{
+        scanner::Scanner,
+        token::{Token, TokenType},
+    },
+    vm::{compiler::Compiler, vm::Vm},
+};
+
+// Usage: cargo bench [-- [--save] [--check] [filter]]
+//   --save   overwrite the tracked baseline with this run
+//   --check  exit with an error if any benchmark regressed past the threshold
+const BASELINE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/baseline.txt");
+const REGRESSION_THRESHOLD: f64 = 1.25;
+const SAMPLES: usize = 15;
+
+const FIB: &str = "
+func fib(n) {
+    if n < 2 {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+let result = fib(15);
+";
+
+const LIST_APPEND: &str = "
+let xs = [];
+for (let i = 0; i < 300; i = i + 1) {
+    env xs {
+        append(i);
+    }
+}
+";
+
+const NESTED_ENV: &str = "
+decenv counter = {
+    let count = 0;
+}
+decenv outer = {
+    let inner = counter;
+    let hits = 0;
+}
+for (let i = 0; i < 200; i = i + 1) {
+    env outer {
+        hits = hits + 1;
+        env inner {
+            count = count + 1;
+        }
+    }
+}
+";
+
+const STRING_CONCAT: &str = "
+let s = \"\";
+let i = 0;
+while i < 1000 {
+    s = s + \"ab\" + i;
+    i = i + 1;
+}
+";
+
+// Evaluates a function and a decenv with a method on every call, so it measures
+// what defining one costs
+const FUNC_DEFS: &str = "
+func make(i) {
+    func step(n) {
+        let a = n + 1;
+        let b = a * 2;
+        if b > 10 {
+            b = b - 10;
+        }
+        while b > 100 {
+            b = b - 100;
+        }
+        return a + b;
+    }
+    decenv point = {
+        let x = i;
+        func to_string() {
+            return \"(\" + x + \")\";
+        }
+    };
+    return step(i);
+}
+let total = 0;
+for (let i = 0; i < 200; i = i + 1) {
+    total = total + make(i);
+}
+";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let save = args.iter().any(|arg| arg == "--save");
+    let check = args.iter().any(|arg| arg == "--check");
+    let filter = args.iter().find(|arg| !arg.starts_with("--"));
+
+    let source = large_source();
+    let tokens = scan(&source);
+    let mut results: Vec<(String, Duration)> = Vec::new();
+    let mut run = |name: &str, case: &mut dyn FnMut()| {
+        if let Some(filter) = filter && !name.contains(filter.as_str()) {
+            return;
+        }
+        results.push((name.to_string(), measure(case)));
+    };
+
+    run("scanner/tokenize_large_file", &mut || {
+        black_box(scan(&source));
+    });
+    run("parser/parse_large_file", &mut || {
+        black_box(parse(tokens.clone()));
+    });
+    for (name, program) in [
+        ("fib", FIB),
+        ("list_append", LIST_APPEND),
+        ("nested_env", NESTED_ENV),
+        ("string_concat", STRING_CONCAT),
+        ("func_defs", FUNC_DEFS),
+    ] {
+        let exprs = prepare(program);
+        run(&format!("interpreter/{}", name), &mut || {
+            let mut interpreter = Interpreter::new();
+            for expr in exprs.iter() {
+                interpreter.interpret(expr);
+            }
+            black_box(interpreter.globals);
+        });
+
+        let chunks: Vec<_> = exprs.iter().map(Compiler::compile).collect();
+        run(&format!("vm/{}", name), &mut || {
+            let mut vm = Vm::new();
+            for chunk in chunks.iter() {
+                vm.interpret(chunk);
+            }
+            black_box(vm.globals);
+        });
+    }
+
+    let baseline = read_baseline();
+    let mut regressed = false;
+    println!("{:<32} {:>14} {:>14} {:>9}", "benchmark", "median", "baseline", "change");
+    for (name, time) in results.iter() {
+        let nanos = time.as_nanos() as f64;
+        match baseline.get(name) {
+            Some(base) => {
+                let ratio = nanos / base;
+                let marker = if ratio > REGRESSION_THRESHOLD {
+                    regressed = true;
+                    "  REGRESSED"
+                } else {
+                    ""
+                };
+                println!(
+                    "{:<32} {:>14} {:>14} {:>+8.1}%{}",
+                    name,
+                    format_nanos(nanos),
+                    format_nanos(*base),
+                    (ratio - 1.0) * 100.0,
+                    marker
+                );
+            }
+            None => println!("{:<32} {:>14} {:>14}", name, format_nanos(nanos), "-"),
+        }
+    }
+
+    if save {
+        let mut merged = baseline;
+        for (name, time) in results.iter() {
+            merged.insert(name.clone(), time.as_nanos() as f64);
+        }
+        write_baseline(&merged);
+        println!("Saved baseline to {}", BASELINE);
+    } else if check && regressed {
+        eprintln!(
+            "Benchmarks regressed by more than {:.0}% against the baseline",
+            (REGRESSION_THRESHOLD - 1.0) * 100.0
+        );
+        std::process::exit(1);
+    }
+}
+
+// Median of several runs after a warm up, so a single slow run doesn't count as a regression
+fn measure(case: &mut dyn FnMut()) -> Duration {
+    case();
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            case();
+            start.elapsed()
+        })
+        .collect();
+    samples.sort();
+    return samples[SAMPLES / 2];
+}
+
+// The example programs repeated until the source is a few hundred kilobytes
+fn large_source() -> String {
+    let mut source = String::new();
+    for (idx, program) in [FIB, LIST_APPEND, NESTED_ENV, STRING_CONCAT]
+        .iter()
+        .cycle()
+        .take(2000)
+        .enumerate()
+    {
+        // Function names are made unique so the file stays a valid program
+        source += &program.replace("fib", &format!("fib{}", idx));
+    }
+    return source;
+}
+
+fn scan(source: &str) -> Vec<Token> {
+    let mut scanner = Scanner::start_scanner();
+    scanner.buff = source.to_string();
+    scanner.tokenize_buff();
+    scanner.token.push(Token {
+        tok: TokenType::EOF,
+        lexeme: String::new(),
+        line: usize::MAX,
+        literal: None,
+    });
+    return scanner.token;
+}
+
+fn parse(tokens: Vec<Token>) -> VecDeque<ExprPossibilities> {
+    let mut parser = Parser::new(tokens);
+    let mut expressions = VecDeque::new();
+    while !parser.is_at_end() {
+        let expr = parser.parse();
+        if let Ok(ExprPossibilities::Scope(scope)) = &expr && let TokenType::FUNC = scope.stmt {
+            expressions.push_front(expr.unwrap());
+        } else if let Ok(expr) = expr {
+            expressions.push_back(expr);
+        } else {
+            break;
+        }
+    }
+    return expressions;
+}
+
+fn prepare(program: &str) -> Vec<ExprPossibilities> {
+    let mut exprs = parse(scan(program));
+    Resolver::new().resolve_program(exprs.make_contiguous(), &HashSet::new());
+    return exprs.into();
+}
+
+fn read_baseline() -> HashMap<String, f64> {
+    let Ok(contents) = std::fs::read_to_string(BASELINE) else {
+        return HashMap::new();
+    };
+    return contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let (name, nanos) = line.split_once(' ')?;
+            Some((name.to_string(), nanos.trim().parse().ok()?))
+        })
+        .collect();
+}
+
+fn write_baseline(baseline: &HashMap<String, f64>) {
+    let mut names: Vec<&String> = baseline.keys().collect();
+    names.sort();
+    let mut contents = String::from("# Median nanoseconds per run, written by `cargo bench -- --save`\n");
+    for name in names {
+        contents += &format!("{} {:.0}\n", name, baseline[name]);
+    }
+    std::fs::write(BASELINE, contents).unwrap();
+}
+
+fn format_nanos(nanos: f64) -> String {
+    if nanos >= 1e9 {
+        return format!("{:.2} s", nanos / 1e9);
+    } else if nanos >= 1e6 {
+        return format!("{:.2} ms", nanos / 1e6);
+    } else if nanos >= 1e3 {
+        return format!("{:.2} us", nanos / 1e3);
+    }
+    return format!("{:.0} ns", nanos);
+}
@@ -1,19 +1,10 @@
-use crate::{
-    error_reporting::{interp_err::InterpException, parsing_err::ParsingException},
-    scanner::token::Primitive,
-};
-
 use super::expr_types::ExprPossibilities;
 
+// Visitors borrow the tree, so walking it never copies a subtree
 pub trait Accept {
-    fn accept<P: Interperable<Result<Primitive, InterpException>>>(
-        expr: ExprPossibilities,
-        visitor: &mut P,
-    ) -> Result<Primitive, InterpException> {
-        return visitor.visit_expr(expr);
-    }
+    fn accept<R, P: Interperable<R>>(&self, visitor: &mut P) -> R;
 }
 
 pub trait Interperable<R> {
-    fn visit_expr(&mut self, expr: ExprPossibilities) -> R;
+    fn visit_expr(&mut self, expr: &ExprPossibilities) -> R;
 }
@@ -1,6 +1,8 @@
+use std::rc::Rc;
+
 use crate::scanner::token::{Primitive, Token, TokenType};
 
-use super::ast_traits::Accept;
+use super::ast_traits::{Accept, Interperable};
 
 #[derive(Clone, Debug)]
 pub struct Ternary {
@@ -40,7 +42,8 @@ pub enum ExprPossibilities {
     Ternary(Ternary),
     Stmt(Stmt),
     Unary(Unary),
-    Scope(Scope),
+    // Shared, so defining a function only takes another reference to its body
+    Scope(Rc<Scope>),
 }
 
 #[derive(Clone, Debug)]
@@ -49,6 +52,13 @@ pub struct Stmt {
     pub ident: Option<Token>,
     pub inner: Option<Box<ExprPossibilities>>,
     pub params: Option<Box<Vec<ExprPossibilities>>>,
+    pub annotation: Option<Token>,
+    // Number of scopes between the use of `ident` and its declaration, filled in by the resolver
+    pub depth: Option<usize>,
+    // Position of the declaration in its scope, unless that's the global scope
+    pub slot: Option<usize>,
+    // Set by the resolver on a `return` of a call that's the last thing its function can run
+    pub tail: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -56,10 +66,23 @@ pub struct Scope {
     pub stmt: TokenType,
     pub ident: Option<Token>,
     pub condition: Option<Box<ExprPossibilities>>,
-    pub params: Option<Vec<Token>>,
+    pub params: Option<Vec<Param>>,
     pub inner: Vec<ExprPossibilities>,
+    pub annotation: Option<Token>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Param {
+    pub ident: Token,
+    pub default: Option<Box<ExprPossibilities>>,
+    pub variadic: bool,
+    pub annotation: Option<Token>,
 }
 
 // impl Accept<Option<String>> for ExprPossibilities {}
 
-impl Accept for ExprPossibilities {}
+impl Accept for ExprPossibilities {
+    fn accept<R, P: Interperable<R>>(&self, visitor: &mut P) -> R {
+        return visitor.visit_expr(self);
+    }
+}
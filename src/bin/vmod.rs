@@ -1,26 +1,94 @@
 #![feature(let_chains)]
 
-use std::{collections::VecDeque, env};
+use std::{
+    collections::VecDeque,
+    env,
+    io::{self, Write},
+    process, thread,
+};
 
 use vmod::ast::expr_types::ExprPossibilities;
+use vmod::error_reporting::error_reporter::Unwindable;
 use vmod::import_sys::import_sys::Importer;
 use vmod::interpreter::interpreter::Interpreter;
+use vmod::lib_functions::random::Rng;
 // use ast::ast_printer::AstPrinter;
 use vmod::parser::parser::Parser;
+use vmod::resolver::resolver::Resolver;
 use vmod::scanner::scanner;
 use vmod::scanner::token::{Token, TokenType};
+use vmod::type_checker::type_checker::TypeChecker;
+use vmod::vm::compiler::Compiler;
+use vmod::vm::vm::Vm;
 
 // static PRINTER: AstPrinter = AstPrinter;
 
+// Every nested vmod call takes several Rust frames, so the program runs on a thread
+// with room for the recursion limit rather than on the main thread's small stack
+const STACK_SIZE: usize = 1 << 30;
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let runner = thread::Builder::new().stack_size(STACK_SIZE).spawn(run).unwrap();
+    let code = runner.join().unwrap_or(1);
+    let _ = io::stdout().flush();
+    process::exit(code);
+}
+
+// Returns the status to exit with, which isn't 0 if the script or its arguments failed
+fn run() -> i32 {
     let mut interpreter = Interpreter::new();
+    // Leaves the other half for the frames below the script's outermost call
+    interpreter.max_stack = STACK_SIZE / 2;
+    // Options come before the script, and everything after it is the script's own
+    let mut args = env::args().skip(1);
+    let mut use_vm = false;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            path = Some(arg);
+            break;
+        }
+        if arg == "--vm" {
+            // Runs the program on the bytecode vm instead of the tree-walker
+            use_vm = true;
+        } else if let Some(max_depth) = arg.strip_prefix("--max-depth=") {
+            match max_depth.parse() {
+                Ok(max_depth) => interpreter.max_depth = max_depth,
+                Err(_) => {
+                    eprintln!("Error: --max-depth expects a number of calls, got '{}'", arg);
+                    return 1;
+                }
+            }
+        } else if let Some(seed) = arg.strip_prefix("--seed=") {
+            // Makes the random module draw the same numbers on every run
+            match seed.parse::<i64>() {
+                Ok(seed) => interpreter.rng = Rng::seeded(seed as u64),
+                Err(_) => {
+                    eprintln!("Error: --seed expects a whole number, got '{}'", arg);
+                    return 1;
+                }
+            }
+        } else {
+            // Everything a script could touch outside of itself is off unless a flag allows it
+            match interpreter.permissions.parse_flag(&arg) {
+                Ok(true) => continue,
+                Ok(false) => (),
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    return 1;
+                }
+            }
+            eprintln!("Error: format: interpreter_follow_along [--vm] [--max-depth=N] [--seed=N] [--allow-read[=paths]] [--allow-write[=paths]] [--allow-env[=names]] [--allow-process] [--allow-all] [filepath [args...]]");
+            return 1;
+        }
+    }
+    interpreter.script_args = args.collect();
     let mut importer = Importer::new();
-    if args.len() > 2 {
-        println!("Error: format: interpreter_follow_along [filepath]")
-    } else if args.len() == 1 {
-        vmod::scanner::scanner::Scanner::accept_input();
-    } else if let Ok(mut scanner) = vmod::scanner::scanner::Scanner::input_file(&args[1]) {
+    let Some(path) = path else {
+        vmod::scanner::scanner::Scanner::accept_input(interpreter);
+        return 0;
+    };
+    if let Ok(mut scanner) = vmod::scanner::scanner::Scanner::input_file(&path) {
         scanner.tokenize_buff();
         scanner.token.push(Token {
             tok: TokenType::EOF,
@@ -30,23 +98,59 @@ fn main() {
         });
         let mut parser = Parser::new(scanner.get_buff());
         let mut expressions: VecDeque<ExprPossibilities> = VecDeque::new();
+        // What parsed before a syntax error still runs, but the script has failed
+        let mut failed = false;
         while !parser.is_at_end() {
             let expr = parser.parse();
-            if let Ok(ExprPossibilities::Scope(scope)) = expr.clone() && let TokenType::FUNC = scope.stmt {
-                expressions.push_front(ExprPossibilities::Scope(scope));
+            if let Ok(ExprPossibilities::Scope(scope)) = &expr && let TokenType::FUNC = scope.stmt {
+                expressions.push_front(expr.unwrap());
             } else if let Ok(expr) = expr {
                 expressions.push_back(expr)
-            } else {
+            } else if let Err(err) = expr {
+                eprintln!("{}", err.get_value());
+                failed = true;
                 break;
             }
         }
 
-        importer.import_files(parser.imports, &mut interpreter, args[1].clone());
+        let type_errors = TypeChecker::new().check_program(expressions.make_contiguous());
+        if !type_errors.is_empty() {
+            for err in type_errors.iter() {
+                eprintln!("{}", err.get_value());
+            }
+            return 1;
+        }
+
+        let warnings =
+            Resolver::new().resolve_program(expressions.make_contiguous(), &parser.imports);
+        for warning in warnings.iter() {
+            eprintln!("{}", warning.get_value());
+        }
+
+        importer.import_files(parser.imports, &mut interpreter, path.clone());
 
-        for expr in expressions.iter() {
-            interpreter.interpret(expr);
+        if use_vm {
+            let chunks: Vec<_> = expressions.iter().map(Compiler::compile).collect();
+            let mut vm = Vm::from_interpreter(interpreter);
+            for chunk in chunks.iter() {
+                vm.interpret(chunk);
+                if let Some(code) = vm.exit_code {
+                    return code;
+                }
+            }
+            failed |= vm.failed;
+        } else {
+            for expr in expressions.iter() {
+                interpreter.interpret(expr);
+                if let Some(code) = interpreter.exit_code {
+                    return code;
+                }
+            }
+            failed |= interpreter.failed;
         }
-    } else if let Err(err) = vmod::scanner::scanner::Scanner::input_file(&args[1]) {
-        println!("{}", err);
+        return if failed { 1 } else { 0 };
+    } else if let Err(err) = vmod::scanner::scanner::Scanner::input_file(&path) {
+        eprintln!("{}", err);
     }
+    return 1;
 }
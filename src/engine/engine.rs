@@ -0,0 +1,154 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::{
+    ast::expr_types::ExprPossibilities,
+    error_reporting::{
+        engine_err::{EngineError, Result},
+        interp_err::InterpException,
+        resolve_err::ResolverWarning,
+    },
+    import_sys::import_sys::Importer,
+    interpreter::{interpreter::Interpreter, operations, sandbox::Limits},
+    lib_functions::runtime::Runtime,
+    parser::parser::Parser,
+    resolver::resolver::Resolver,
+    scanner::{
+        scanner::Scanner,
+        token::{Primitive, Token, TokenType},
+    },
+    type_checker::type_checker::TypeChecker,
+};
+
+// Runs vmod scripts from a Rust host. Globals persist between evaluations, so one
+// script can define functions that later ones, or the host, call.
+pub struct Engine {
+    pub interpreter: Interpreter,
+    importer: Importer,
+    warnings: Vec<ResolverWarning>,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        return Self {
+            interpreter: Interpreter::new(),
+            importer: Importer::new(),
+            warnings: Vec::new(),
+        };
+    }
+
+    // An engine for untrusted scripts, stopped with an error once they exceed a limit
+    pub fn sandboxed(limits: Limits) -> Self {
+        let mut engine = Self::new();
+        engine.interpreter = Interpreter::sandboxed(limits);
+        return engine;
+    }
+
+    // Runs a program, returning what its last statement evaluated to. Imports are
+    // looked up relative to the working directory.
+    pub fn eval_str(&mut self, source: &str) -> Result<Primitive> {
+        return self.eval(source, String::new());
+    }
+
+    pub fn eval_file(&mut self, path: &str) -> Result<Primitive> {
+        let source =
+            std::fs::read_to_string(path).map_err(|err| EngineError::Io(path.to_string(), err))?;
+        return self.eval(&source, path.to_string());
+    }
+
+    // Warnings the resolver raised about the last program evaluated
+    pub fn warnings(&self) -> &[ResolverWarning] {
+        return &self.warnings;
+    }
+
+    pub fn call_function(&mut self, name: &str, args: Vec<Primitive>) -> Result<Primitive> {
+        let callee = self.interpreter.globals.retrieve(name)?;
+        self.restart_budget();
+        // A function returning early leaves its scope behind, which the host shouldn't see
+        return Ok(self.interpreter.invoke(name, callee, args)?);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Primitive> {
+        return self.interpreter.globals.retrieve(name).ok();
+    }
+
+    // Declares the global the same way `let` would, replacing any existing one
+    pub fn set_global(&mut self, name: &str, value: Primitive) -> Result<()> {
+        if self.interpreter.globals.is_constant(name) {
+            return Err(InterpException::AssignToConstant(name.to_string()).into());
+        }
+        operations::declare(
+            &mut self.interpreter.globals,
+            name.to_string(),
+            value,
+            false,
+        )?;
+        return Ok(());
+    }
+
+    fn eval(&mut self, source: &str, filename: String) -> Result<Primitive> {
+        let mut scanner = Scanner::start_scanner();
+        scanner.buff = source.to_string();
+        if let Some((err, tok)) = scanner.tokenize().into_iter().next() {
+            return Err(EngineError::Scanning(err, tok));
+        }
+        scanner.token.push(Token {
+            tok: TokenType::EOF,
+            lexeme: String::new(),
+            line: usize::MAX,
+            literal: None,
+        });
+
+        let mut parser = Parser::new(scanner.token);
+        let mut expressions: VecDeque<ExprPossibilities> = VecDeque::new();
+        while !parser.is_at_end() {
+            let expr = parser.parse().map_err(EngineError::Parsing)?;
+            if let ExprPossibilities::Scope(scope) = &expr && let TokenType::FUNC = scope.stmt {
+                expressions.push_front(expr);
+            } else {
+                expressions.push_back(expr);
+            }
+        }
+
+        let type_errors = TypeChecker::new().check_program(expressions.make_contiguous());
+        if !type_errors.is_empty() {
+            return Err(EngineError::Type(type_errors));
+        }
+
+        // Globals left by earlier evaluations are as good as imported
+        let mut known: HashSet<String> = parser.imports.clone();
+        let mut env = Some(&self.interpreter.globals);
+        while let Some(scope) = env {
+            known.extend(scope.vars.keys().cloned());
+            env = scope.enclosing.as_deref();
+        }
+        self.warnings = Resolver::new().resolve_program(expressions.make_contiguous(), &known);
+
+        if !parser.imports.is_empty() {
+            self.importer
+                .import_files(parser.imports, &mut self.interpreter, filename);
+        }
+
+        self.restart_budget();
+        // A runtime error leaves the scopes of the call or block it came from entered
+        let depth = self.interpreter.globals.depth();
+        let mut result = Primitive::None;
+        for expr in expressions.iter() {
+            match self.interpreter.execute(expr) {
+                Ok(value) => result = value,
+                Err(err) => {
+                    let left_behind = self.interpreter.globals.depth() - depth;
+                    self.interpreter.globals.unwind(left_behind);
+                    return Err(err.into());
+                }
+            }
+        }
+        return Ok(result);
+    }
+
+    // Every evaluation and call gets the sandbox's whole budget to itself
+    fn restart_budget(&mut self) {
+        if let Some(sandbox) = &mut self.interpreter.sandbox {
+            sandbox.reset();
+        }
+    }
+}
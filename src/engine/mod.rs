@@ -0,0 +1,3 @@
+pub mod engine;
+#[cfg(test)]
+pub mod testing;
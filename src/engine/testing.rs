@@ -0,0 +1,62 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::{
+    ast::expr_types::ExprPossibilities,
+    import_sys::import_sys::Importer,
+    interpreter::{interpreter::Interpreter, output::Capture},
+    parser::parser::Parser,
+    resolver::resolver::Resolver,
+    scanner::{
+        scanner::Scanner,
+        token::{Token, TokenType},
+    },
+    vm::{compiler::Compiler, vm::Vm},
+};
+
+// Runs a script the way the vmod binary does, returning what it printed, errors included
+pub fn run(source: &str, use_vm: bool) -> String {
+    let mut scanner = Scanner::start_scanner();
+    scanner.buff = source.to_string();
+    scanner.tokenize_buff();
+    scanner.token.push(Token {
+        tok: TokenType::EOF,
+        lexeme: String::new(),
+        line: usize::MAX,
+        literal: None,
+    });
+    let mut parser = Parser::new(scanner.token);
+    let mut expressions: VecDeque<ExprPossibilities> = VecDeque::new();
+    while !parser.is_at_end() {
+        let expr = parser.parse().unwrap();
+        if let ExprPossibilities::Scope(scope) = &expr && let TokenType::FUNC = scope.stmt {
+            expressions.push_front(expr);
+        } else {
+            expressions.push_back(expr);
+        }
+    }
+    Resolver::new().resolve_program(expressions.make_contiguous(), &HashSet::new());
+
+    let output = Capture::new();
+    let mut interpreter = Interpreter::new();
+    interpreter.stdout = Box::new(output.clone());
+    interpreter.stderr = Box::new(output.clone());
+    Importer::new().import_files(parser.imports, &mut interpreter, String::new());
+    if use_vm {
+        let mut vm = Vm::from_interpreter(interpreter);
+        for expr in expressions.iter() {
+            vm.interpret(&Compiler::compile(expr));
+        }
+    } else {
+        for expr in expressions.iter() {
+            interpreter.interpret(expr);
+        }
+    }
+    return output.contents();
+}
+
+// Runs a script on both engines, which have to agree on what it prints
+pub fn run_both(source: &str) -> String {
+    let interpreted = run(source, false);
+    assert_eq!(interpreted, run(source, true), "the engines disagree on:\n{}", source);
+    return interpreted;
+}
@@ -0,0 +1,55 @@
+use std::fmt::Display;
+
+use crate::scanner::token::Token;
+
+use super::{
+    error_reporter::Unwindable, interp_err::InterpException, parsing_err::ParsingException,
+    scanning_err::ScanningException, type_err::TypeException,
+};
+
+pub type Result<T> = std::result::Result<T, EngineError>;
+
+// Everything that can stop an embedded script, from reading its file to running it
+#[derive(Debug)]
+pub enum EngineError {
+    Io(String, std::io::Error),
+    Scanning(ScanningException, Token),
+    Parsing(ParsingException),
+    Type(Vec<TypeException>),
+    Runtime(InterpException),
+}
+
+impl Unwindable for EngineError {
+    fn get_value(&self) -> String {
+        match self {
+            Self::Io(path, err) => format!("Could not read '{}': {}", path, err),
+            Self::Scanning(err, tok) => format!(
+                "{} on token: {:#} on line {}",
+                err.get_value(),
+                tok,
+                tok.line
+            ),
+            Self::Parsing(err) => err.get_value(),
+            Self::Type(errs) => errs
+                .iter()
+                .map(|err| err.get_value())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::Runtime(err) => err.get_value(),
+        }
+    }
+}
+
+impl Display for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.get_value())
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<InterpException> for EngineError {
+    fn from(err: InterpException) -> Self {
+        return Self::Runtime(err);
+    }
+}
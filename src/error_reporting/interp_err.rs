@@ -1,6 +1,6 @@
 use crate::{
-    ast::expr_types::{Binary, Unary},
-    scanner::token::TokenType,
+    interpreter::permissions::Capability,
+    scanner::token::{Token, TokenType},
 };
 
 use super::error_reporter::Unwindable;
@@ -9,12 +9,39 @@ pub type Result<'a, T> = std::result::Result<T, InterpException>;
 
 #[derive(Clone, Debug)]
 pub enum InterpException {
-    InvalidUnary(Unary),
-    InvalidBinary(Binary),
-    DivideByZero(Binary),
+    InvalidUnary(Token),
+    InvalidBinary(Token),
+    DivideByZero(Token),
     InvalidTernaryExpr(u64),
     IdentifierNoExist(String),
     InvalidIndex(String),
+    InvalidComparison(Token, &'static str, &'static str),
+    NoMatchingOverload(String, usize, usize),
+    AmbiguousOverload(String, usize),
+    InvalidKeywordArg(Token),
+    MissingArgument(String, usize),
+    AssignToConstant(String),
+    StackOverflow(String, usize, usize),
+    NotCallable(String),
+    InvalidConversion(&'static str, &'static str),
+    InvalidArgument(String, usize, &'static str, &'static str),
+    InvalidField(String, String, &'static str, &'static str),
+    WrongArgumentCount(String, usize, usize),
+    OutputFailed(String),
+    StepLimitExceeded(u64),
+    TimeLimitExceeded(u128),
+    HeapLimitExceeded(usize),
+    CollectionTooLarge(usize),
+    Cancelled,
+    PermissionDenied(Capability, String),
+    IoFailed(&'static str, String, String),
+    Exit(i32),
+    InvalidJson(String, usize, usize),
+    NotSerializable(String, &'static str),
+    InvalidDate(String, &'static str),
+    InvalidValue(String, String),
+    InvalidPattern(String, usize, &'static str),
+    NotIterable(&'static str, usize),
     PlaceHolder,
 }
 
@@ -31,14 +58,14 @@ impl InterpException {
 impl Unwindable for InterpException {
     fn get_value(&self) -> String {
         match self {
-            InterpException::InvalidUnary(tok) => {
-                format!("Invalid unary expr on line {}", tok.operator.line)
+            InterpException::InvalidUnary(operator) => {
+                format!("Invalid unary expr on line {}", operator.line)
             }
             InterpException::InvalidTernaryExpr(line) => {
                 format!("Used string in ternary expr on line {}", line)
             }
-            InterpException::InvalidBinary(binary) => {
-                format!("Invalid Expression on line {}", binary.operator.line)
+            InterpException::InvalidBinary(operator) => {
+                format!("Invalid Expression on line {}", operator.line)
             }
             InterpException::PlaceHolder => {
                 String::from("Interp Error: Limitation of rust borrow checker")
@@ -49,7 +76,95 @@ impl Unwindable for InterpException {
             InterpException::InvalidIndex(list) => {
                 format!("Invalid indexing of list {}", list)
             }
-            InterpException::DivideByZero(_) => todo!(),
+            InterpException::InvalidComparison(operator, left, right) => format!(
+                "Cannot compare {} with {} on line {}",
+                left, right, operator.line
+            ),
+            InterpException::NoMatchingOverload(func, arg_count, line) => format!(
+                "No overload of '{}' accepts {} argument(s) on line {}",
+                func, arg_count, line
+            ),
+            InterpException::AmbiguousOverload(func, line) => format!(
+                "Definition of '{}' on line {} accepts the same number of arguments as an existing overload",
+                func, line
+            ),
+            InterpException::InvalidKeywordArg(tok) => format!(
+                "Unexpected or repeated keyword argument '{}' on line {}",
+                tok.lexeme, tok.line
+            ),
+            InterpException::MissingArgument(param, line) => {
+                format!("Missing argument '{}' on line {}", param, line)
+            }
+            InterpException::AssignToConstant(ident) => {
+                format!("Cannot assign to constant '{}'", ident)
+            }
+            InterpException::StackOverflow(func, line, max_depth) => format!(
+                "Stack overflow: call to '{}' on line {} is nested deeper than {} calls",
+                func, line, max_depth
+            ),
+            InterpException::NotCallable(ident) => format!("'{}' is not a function", ident),
+            InterpException::InvalidConversion(expected, found) => {
+                format!("Expected {} but got {}", expected, found)
+            }
+            InterpException::InvalidArgument(func, position, expected, found) => format!(
+                "Argument {} of '{}' expects {} but got {}",
+                position, func, expected, found
+            ),
+            InterpException::InvalidField(ty, field, expected, found) => format!(
+                "Field '{}' of {} expects {} but got {}",
+                field, ty, expected, found
+            ),
+            InterpException::WrongArgumentCount(func, expected, found) => format!(
+                "'{}' takes {} argument(s) but got {}",
+                func, expected, found
+            ),
+            InterpException::OutputFailed(err) => format!("Could not write output: {}", err),
+            InterpException::StepLimitExceeded(max_steps) => {
+                format!("Step limit exceeded: script ran for more than {} steps", max_steps)
+            }
+            InterpException::TimeLimitExceeded(millis) => {
+                format!("Time limit exceeded: script ran for more than {}ms", millis)
+            }
+            InterpException::HeapLimitExceeded(max_bytes) => {
+                format!("Heap limit exceeded: script used more than {} bytes", max_bytes)
+            }
+            InterpException::CollectionTooLarge(max_len) => format!(
+                "Collection limit exceeded: a list, env or string grew past {} values",
+                max_len
+            ),
+            InterpException::Cancelled => String::from("Script was cancelled by the host"),
+            InterpException::PermissionDenied(capability, target) => format!(
+                "Permission denied: {} access to '{}' needs {}",
+                capability,
+                target,
+                capability.flag()
+            ),
+            InterpException::IoFailed(func, path, err) => {
+                format!("{} failed on '{}': {}", func, path, err)
+            }
+            InterpException::Exit(code) => format!("Script exited with code {}", code),
+            InterpException::InvalidJson(msg, line, column) => {
+                format!("Invalid JSON at line {}, column {}: {}", line, column, msg)
+            }
+            InterpException::NotSerializable(path, what) => {
+                format!("Cannot write {} at {} as JSON", what, path)
+            }
+            InterpException::InvalidDate(text, reason) => {
+                format!("Invalid date '{}': {}", text, reason)
+            }
+            InterpException::InvalidValue(func, reason) => {
+                format!("Invalid argument to '{}': {}", func, reason)
+            }
+            InterpException::InvalidPattern(pattern, position, reason) => format!(
+                "Invalid pattern '{}' at position {}: {}",
+                pattern, position, reason
+            ),
+            InterpException::NotIterable(found, line) => {
+                format!("Cannot loop over {} on line {}", found, line)
+            }
+            InterpException::DivideByZero(operator) => {
+                format!("Division by zero on line {}", operator.line)
+            }
         }
     }
 }
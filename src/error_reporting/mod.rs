@@ -1,4 +1,7 @@
+pub mod engine_err;
 pub mod error_reporter;
 pub mod interp_err;
 pub mod parsing_err;
+pub mod resolve_err;
 pub mod scanning_err;
+pub mod type_err;
@@ -18,6 +18,10 @@ pub enum ParsingException {
     InvalidLoop(Token),
     InvalidIndex(Token),
     InvalidAppend(Token),
+    InvalidParams(Token),
+    PositionalAfterKeyword(Token),
+    InvalidAnnotation(Token),
+    AssignToConstant(Token),
     PlaceHolder,
 }
 
@@ -61,6 +65,22 @@ impl Unwindable for ParsingException {
                 tok.lexeme, tok.line
             ),
             Self::InvalidEnvCall(tok) => format!("Invalid environment call on line {}", tok.line),
+            Self::InvalidParams(tok) => format!(
+                "Parsing Error: Invalid parameter list on line {}",
+                tok.line
+            ),
+            Self::PositionalAfterKeyword(tok) => format!(
+                "Parsing Error: positional argument after keyword argument on line {}",
+                tok.line
+            ),
+            Self::InvalidAnnotation(tok) => format!(
+                "Parsing Error: Invalid type annotation on line {}",
+                tok.line
+            ),
+            Self::AssignToConstant(tok) => format!(
+                "Parsing Error: cannot assign to constant '{}' on line {}",
+                tok.lexeme, tok.line
+            ),
             ParsingException::InvalidLoop(tok) => {
                 format!("Invalid {:?} loop on line {}", tok.tok, tok.line)
             }
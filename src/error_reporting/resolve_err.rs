@@ -0,0 +1,34 @@
+use crate::scanner::token::Token;
+
+use super::error_reporter::Unwindable;
+
+#[derive(Clone, Debug)]
+pub enum ResolverWarning {
+    Unused(Token),
+    Shadowed(Token),
+    UsedBeforeDefinition(Token),
+    Undefined(Token),
+}
+
+impl Unwindable for ResolverWarning {
+    fn get_value(&self) -> String {
+        match self {
+            Self::Unused(tok) => format!(
+                "Warning: variable '{}' on line {} is never used",
+                tok.lexeme, tok.line
+            ),
+            Self::Shadowed(tok) => format!(
+                "Warning: '{}' on line {} shadows a variable from an enclosing scope",
+                tok.lexeme, tok.line
+            ),
+            Self::UsedBeforeDefinition(tok) => format!(
+                "Warning: '{}' is used on line {} before it is defined",
+                tok.lexeme, tok.line
+            ),
+            Self::Undefined(tok) => format!(
+                "Warning: '{}' on line {} is never defined",
+                tok.lexeme, tok.line
+            ),
+        }
+    }
+}
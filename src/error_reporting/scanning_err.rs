@@ -2,7 +2,7 @@ use super::error_reporter::Unwindable;
 
 pub type Result<T> = std::result::Result<T, ScanningException>;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum ScanningException {
     Tokenization,
     Newline,
@@ -0,0 +1,51 @@
+use crate::{scanner::token::Token, type_checker::type_checker::Type};
+
+use super::error_reporter::Unwindable;
+
+pub type Result<T> = std::result::Result<T, TypeException>;
+
+#[derive(Clone, Debug)]
+pub enum TypeException {
+    UnknownType(Token),
+    Mismatch(Token, Type, Type),
+    InvalidOperands(Token, Type, Type),
+    InvalidUnary(Token, Type),
+    ArgumentMismatch(Token, Type, Type, usize),
+    ReturnMismatch(Token, Type, Type),
+    NotIterable(Token, Type),
+}
+
+impl Unwindable for TypeException {
+    fn get_value(&self) -> String {
+        match self {
+            Self::UnknownType(tok) => format!(
+                "Type Error: unknown type '{}' on line {}",
+                tok.lexeme, tok.line
+            ),
+            Self::Mismatch(ident, expected, found) => format!(
+                "Type Error: '{}' is declared as {} but assigned {} on line {}",
+                ident.lexeme, expected, found, ident.line
+            ),
+            Self::InvalidOperands(operator, left, right) => format!(
+                "Type Error: invalid operands {} and {} for '{}' on line {}",
+                left, right, operator.lexeme, operator.line
+            ),
+            Self::InvalidUnary(operator, right) => format!(
+                "Type Error: invalid operand {} for '{}' on line {}",
+                right, operator.lexeme, operator.line
+            ),
+            Self::ArgumentMismatch(param, expected, found, line) => format!(
+                "Type Error: argument '{}' expects {} but got {} on line {}",
+                param.lexeme, expected, found, line
+            ),
+            Self::ReturnMismatch(func, expected, found) => format!(
+                "Type Error: '{}' on line {} is declared to return {} but returns {}",
+                func.lexeme, func.line, expected, found
+            ),
+            Self::NotIterable(item, found) => format!(
+                "Type Error: '{}' loops over {}, which isn't a list, set or deque, on line {}",
+                item.lexeme, found, item.line
+            ),
+        }
+    }
+}
@@ -0,0 +1,620 @@
+use std::{
+    cell::{Cell, Ref, RefCell, RefMut},
+    collections::{HashMap, HashSet},
+    fmt, mem,
+    ops::{Deref, DerefMut},
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    interpreter::environment::Environment,
+    lib_functions::collections::{Deque, Set},
+    scanner::token::Primitive,
+};
+
+// Collections run once this many objects were allocated since the last one
+const INITIAL_THRESHOLD: usize = 1024;
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::new());
+    // Objects being formatted or compared, so cycles don't recurse forever
+    static FORMATTING: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+    static COMPARING: RefCell<HashSet<(usize, usize)>> = RefCell::new(HashSet::new());
+    // What objects allocated right now are counted against, if a sandbox is running
+    static METER: RefCell<Option<Rc<Meter>>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObjectKind {
+    Env,
+    List,
+    Set,
+    Deque,
+}
+
+// What the collector needs to know about a type to manage it
+pub trait Trace {
+    const KIND: ObjectKind;
+
+    fn trace(&self, visit: &mut dyn FnMut(&Primitive));
+    // Drops every reference the object holds, breaking any cycle it's part of
+    fn clear(&mut self);
+    // Rough number of bytes each value the object holds adds to its size
+    const SLOT: usize;
+
+    // Rough number of bytes the object takes up
+    fn size(&self) -> usize;
+    // Number of values the object holds
+    fn len(&self) -> usize;
+}
+
+impl Trace for Environment {
+    const KIND: ObjectKind = ObjectKind::Env;
+    const SLOT: usize = mem::size_of::<String>() + mem::size_of::<Primitive>();
+
+    fn trace(&self, visit: &mut dyn FnMut(&Primitive)) {
+        for value in self.vars.values() {
+            visit(value);
+        }
+        if let Some(enclosing) = &self.enclosing {
+            enclosing.trace(visit);
+        }
+    }
+
+    fn clear(&mut self) {
+        let _ = mem::take(&mut self.vars);
+        self.enclosing = None;
+    }
+
+    fn size(&self) -> usize {
+        let mut size = mem::size_of::<Environment>();
+        for (name, value) in self.vars.iter() {
+            size += mem::size_of::<String>() + name.len() + value_size(value);
+        }
+        return size;
+    }
+
+    fn len(&self) -> usize {
+        return self.vars.len();
+    }
+}
+
+impl Trace for Vec<Primitive> {
+    const KIND: ObjectKind = ObjectKind::List;
+    const SLOT: usize = mem::size_of::<Primitive>();
+
+    fn trace(&self, visit: &mut dyn FnMut(&Primitive)) {
+        for value in self.iter() {
+            visit(value);
+        }
+    }
+
+    fn clear(&mut self) {
+        let _ = mem::take(self);
+    }
+
+    fn size(&self) -> usize {
+        return mem::size_of::<Vec<Primitive>>() + self.iter().map(value_size).sum::<usize>();
+    }
+
+    fn len(&self) -> usize {
+        return Vec::len(self);
+    }
+}
+
+impl Trace for Set {
+    const KIND: ObjectKind = ObjectKind::Set;
+    const SLOT: usize = mem::size_of::<Primitive>() * 2;
+
+    fn trace(&self, visit: &mut dyn FnMut(&Primitive)) {
+        for value in self.iter() {
+            visit(value);
+        }
+    }
+
+    fn clear(&mut self) {
+        let _ = mem::take(self);
+    }
+
+    fn size(&self) -> usize {
+        // Every item is also indexed by its key, which is about as big again
+        return mem::size_of::<Set>() + self.iter().map(value_size).sum::<usize>() * 2;
+    }
+
+    fn len(&self) -> usize {
+        return Set::len(self);
+    }
+}
+
+impl Trace for Deque {
+    const KIND: ObjectKind = ObjectKind::Deque;
+    const SLOT: usize = mem::size_of::<Primitive>();
+
+    fn trace(&self, visit: &mut dyn FnMut(&Primitive)) {
+        for value in self.items.iter() {
+            visit(value);
+        }
+    }
+
+    fn clear(&mut self) {
+        let _ = mem::take(&mut self.items);
+    }
+
+    fn size(&self) -> usize {
+        return mem::size_of::<Deque>() + self.items.iter().map(value_size).sum::<usize>();
+    }
+
+    fn len(&self) -> usize {
+        return self.items.len();
+    }
+}
+
+fn value_size(value: &Primitive) -> usize {
+    if let Primitive::String(string) = value {
+        return mem::size_of::<Primitive>() + string.len();
+    }
+    return mem::size_of::<Primitive>();
+}
+
+fn object_id(value: &Primitive) -> Option<usize> {
+    match value {
+        Primitive::Env(env) => Some(env.id()),
+        Primitive::List(list) => Some(list.id()),
+        Primitive::Set(set) => Some(set.id()),
+        Primitive::Deque(deque) => Some(deque.id()),
+        _ => None,
+    }
+}
+
+// Bytes taken up by the objects one sandboxed script allocated, and the most values any
+// of them held. Kept up to date as the objects are created, change size and are freed,
+// so checking it doesn't mean looking through the heap.
+#[derive(Debug, Default)]
+pub struct Meter {
+    bytes: Cell<usize>,
+    largest: Cell<usize>,
+}
+
+impl Meter {
+    pub fn bytes(&self) -> usize {
+        return self.bytes.get();
+    }
+
+    pub fn largest(&self) -> usize {
+        return self.largest.get();
+    }
+
+    // Forgets how big objects have been, leaving only the bytes still in use
+    pub fn reset_largest(&self) {
+        self.largest.set(0);
+    }
+
+    fn resize(&self, from: usize, to: usize, len: usize) {
+        self.bytes.set((self.bytes.get() + to).saturating_sub(from));
+        self.largest.set(self.largest.get().max(len));
+    }
+}
+
+// Objects allocated while this is alive are counted against its meter
+pub struct Metering {
+    previous: Option<Rc<Meter>>,
+}
+
+pub fn metering(meter: &Rc<Meter>) -> Metering {
+    let previous = METER.with(|current| current.replace(Some(meter.clone())));
+    return Metering { previous };
+}
+
+impl Drop for Metering {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        METER.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+struct GcBox<T: Trace + 'static> {
+    value: RefCell<T>,
+    // The meter of the sandbox the object was allocated in, and what it was last counted as
+    meter: Option<Rc<Meter>>,
+    size: Cell<usize>,
+}
+
+impl<T: Trace + 'static> Drop for GcBox<T> {
+    fn drop(&mut self) {
+        if let Some(meter) = &self.meter {
+            meter.resize(self.size.get(), 0, 0);
+        }
+    }
+}
+
+// Shared handle to an object on the heap. Cloning a handle aliases the object rather
+// than copying it; the object is freed once no handle reaches it from outside the heap.
+pub struct Gc<T: Trace + 'static>(Rc<GcBox<T>>);
+
+impl<T: Trace + 'static> Gc<T> {
+    pub fn new(value: T) -> Self {
+        let meter = METER.with(|current| current.borrow().clone());
+        let mut size = 0;
+        if let Some(meter) = &meter {
+            size = value.size();
+            meter.resize(0, size, value.len());
+        }
+        let gc = Gc(Rc::new(GcBox {
+            value: RefCell::new(value),
+            meter,
+            size: Cell::new(size),
+        }));
+        HEAP.with(|heap| {
+            heap.borrow_mut()
+                .register(T::KIND, Box::new(Rc::downgrade(&gc.0)))
+        });
+        return gc;
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        return self.0.value.borrow();
+    }
+
+    pub fn borrow_mut(&self) -> GcMut<'_, T> {
+        let value = self.0.value.borrow_mut();
+        let len = self.0.meter.as_ref().map(|_| value.len());
+        return GcMut {
+            value,
+            owner: &self.0,
+            len,
+        };
+    }
+
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        return Rc::ptr_eq(&self.0, &other.0);
+    }
+
+    pub fn id(&self) -> usize {
+        return Rc::as_ptr(&self.0) as *const () as usize;
+    }
+}
+
+// Mutable borrow of an object, which recounts it against its meter once it's done with.
+// Only the number of values is looked at, so that stays cheap however big the object is.
+pub struct GcMut<'a, T: Trace + 'static> {
+    value: RefMut<'a, T>,
+    owner: &'a GcBox<T>,
+    // How many values the object held when it was borrowed, if it's metered
+    len: Option<usize>,
+}
+
+impl<T: Trace + 'static> Deref for GcMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        return &self.value;
+    }
+}
+
+impl<T: Trace + 'static> DerefMut for GcMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        return &mut self.value;
+    }
+}
+
+impl<T: Trace + 'static> Drop for GcMut<'_, T> {
+    fn drop(&mut self) {
+        if let Some(meter) = &self.owner.meter && let Some(before) = self.len {
+            let len = self.value.len();
+            let from = self.owner.size.get();
+            let to = (from + len * T::SLOT).saturating_sub(before * T::SLOT);
+            self.owner.size.set(to);
+            meter.resize(from, to, len);
+        }
+    }
+}
+
+impl<T: Trace + 'static> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        return Gc(self.0.clone());
+    }
+}
+
+// Objects compare by their contents. A pair already being compared further up is
+// taken to be equal, which is what makes two matching cycles equal.
+impl<T: Trace + PartialEq + 'static> PartialEq for Gc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        if self.ptr_eq(other) {
+            return true;
+        }
+        let pair = (self.id(), other.id());
+        if !COMPARING.with(|pairs| pairs.borrow_mut().insert(pair)) {
+            return true;
+        }
+        let eq = *self.borrow() == *other.borrow();
+        COMPARING.with(|pairs| pairs.borrow_mut().remove(&pair));
+        return eq;
+    }
+}
+
+impl<T: Trace + fmt::Debug + 'static> fmt::Debug for Gc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id = self.id();
+        if !FORMATTING.with(|ids| ids.borrow_mut().insert(id)) {
+            return write!(f, "<cycle>");
+        }
+        let result = match self.0.value.try_borrow() {
+            Ok(value) => value.fmt(f),
+            Err(_) => write!(f, "<borrowed>"),
+        };
+        FORMATTING.with(|ids| ids.borrow_mut().remove(&id));
+        return result;
+    }
+}
+
+// The heap only keeps weak references, so it sees objects without keeping them alive
+trait Object {
+    fn id(&self) -> usize;
+    fn strong_count(&self) -> usize;
+    // Returns false when the object is borrowed and can't be looked into
+    fn trace(&self, visit: &mut dyn FnMut(&Primitive)) -> bool;
+    fn clear(&self);
+    fn size(&self) -> usize;
+    fn len(&self) -> usize;
+}
+
+impl<T: Trace> Object for Weak<GcBox<T>> {
+    fn id(&self) -> usize {
+        return Weak::as_ptr(self) as *const () as usize;
+    }
+
+    fn strong_count(&self) -> usize {
+        return Weak::strong_count(self);
+    }
+
+    fn trace(&self, visit: &mut dyn FnMut(&Primitive)) -> bool {
+        let Some(object) = self.upgrade() else {
+            return true;
+        };
+        let Ok(value) = object.value.try_borrow() else {
+            return false;
+        };
+        value.trace(visit);
+        return true;
+    }
+
+    fn clear(&self) {
+        let Some(object) = self.upgrade() else {
+            return;
+        };
+        let Ok(mut value) = object.value.try_borrow_mut() else {
+            return;
+        };
+        value.clear();
+    }
+
+    fn size(&self) -> usize {
+        let Some(object) = self.upgrade() else {
+            return 0;
+        };
+        let Ok(value) = object.value.try_borrow() else {
+            return 0;
+        };
+        return value.size();
+    }
+
+    fn len(&self) -> usize {
+        let Some(object) = self.upgrade() else {
+            return 0;
+        };
+        let Ok(value) = object.value.try_borrow() else {
+            return 0;
+        };
+        return value.len();
+    }
+}
+
+struct Entry {
+    id: usize,
+    kind: ObjectKind,
+    object: Box<dyn Object>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeapStats {
+    // Objects currently alive, and how many of them are envs and lists
+    pub objects: usize,
+    pub envs: usize,
+    pub lists: usize,
+    pub bytes: usize,
+    // Values held by the biggest env, list, set or deque
+    pub largest: usize,
+    // Totals since the thread started
+    pub allocations: usize,
+    pub collections: usize,
+    pub freed: usize,
+}
+
+// Reference counting frees most objects as soon as they're unreachable. The heap
+// collects what it can't: cycles, found by counting the references objects hold to
+// each other. An object referenced more times than that is held from outside the heap,
+// so it and everything it reaches is alive, and the rest is garbage.
+struct Heap {
+    entries: Vec<Entry>,
+    since_collection: usize,
+    threshold: usize,
+    stats: HeapStats,
+}
+
+impl Heap {
+    fn new() -> Self {
+        return Self {
+            entries: Vec::new(),
+            since_collection: 0,
+            threshold: INITIAL_THRESHOLD,
+            stats: HeapStats::default(),
+        };
+    }
+
+    fn register(&mut self, kind: ObjectKind, object: Box<dyn Object>) {
+        self.entries.push(Entry {
+            id: object.id(),
+            kind,
+            object,
+        });
+        self.stats.allocations += 1;
+        self.since_collection += 1;
+        if self.since_collection >= self.threshold {
+            self.collect();
+        }
+    }
+
+    fn prune(&mut self) {
+        self.entries.retain(|entry| entry.object.strong_count() > 0);
+    }
+
+    fn collect(&mut self) -> usize {
+        self.prune();
+        let before = self.entries.len();
+        let index: HashMap<usize, usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(idx, entry)| (entry.id, idx))
+            .collect();
+
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); before];
+        let mut reached = vec![false; before];
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let traced = entry.object.trace(&mut |value| {
+                if let Some(id) = object_id(value) && let Some(child) = index.get(&id) {
+                    children[idx].push(*child);
+                }
+            });
+            // Something is working on the object right now, so it's in use
+            if !traced {
+                reached[idx] = true;
+            }
+        }
+
+        let mut internal = vec![0; before];
+        for child in children.iter().flatten() {
+            internal[*child] += 1;
+        }
+        let mut pending: Vec<usize> = (0..before)
+            .filter(|idx| {
+                reached[*idx] || self.entries[*idx].object.strong_count() > internal[*idx]
+            })
+            .collect();
+        for idx in pending.iter() {
+            reached[*idx] = true;
+        }
+        while let Some(idx) = pending.pop() {
+            for child in children[idx].iter() {
+                if !reached[*child] {
+                    reached[*child] = true;
+                    pending.push(*child);
+                }
+            }
+        }
+
+        for (idx, entry) in self.entries.iter().enumerate() {
+            if !reached[idx] {
+                entry.object.clear();
+            }
+        }
+        self.prune();
+
+        let freed = before - self.entries.len();
+        self.stats.collections += 1;
+        self.stats.freed += freed;
+        self.since_collection = 0;
+        self.threshold = INITIAL_THRESHOLD.max(self.entries.len() * 2);
+        return freed;
+    }
+
+    fn stats(&mut self) -> HeapStats {
+        self.prune();
+        let mut stats = self.stats;
+        stats.objects = self.entries.len();
+        stats.envs = self.count(ObjectKind::Env);
+        stats.lists = self.count(ObjectKind::List);
+        stats.bytes = self.entries.iter().map(|entry| entry.object.size()).sum();
+        stats.largest = self
+            .entries
+            .iter()
+            .map(|entry| entry.object.len())
+            .max()
+            .unwrap_or(0);
+        return stats;
+    }
+
+    fn count(&self, kind: ObjectKind) -> usize {
+        return self
+            .entries
+            .iter()
+            .filter(|entry| entry.kind == kind)
+            .count();
+    }
+}
+
+// Frees unreachable cycles right away, returning how many objects were freed
+pub fn collect() -> usize {
+    return HEAP.with(|heap| heap.borrow_mut().collect());
+}
+
+pub fn stats() -> HeapStats {
+    return HEAP.with(|heap| heap.borrow_mut().stats());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::testing::run_both;
+
+    #[test]
+    fn envs_and_lists_are_shared_not_copied() {
+        let source = "
+            decenv node = {
+                let value = 1;
+                let next = null;
+            }
+            func link(target, item) {
+                env target {
+                    next = [item];
+                }
+            }
+            let alias = node;
+            link(alias, 2);
+            env node {
+                println(next);
+            }
+            let xs = [1];
+            func grow(list) {
+                env list {
+                    append(2);
+                }
+            }
+            grow(xs);
+            println(xs);
+        ";
+        assert_eq!(run_both(source), "[2]\n[1, 2]\n");
+    }
+
+    #[test]
+    fn envs_pointing_at_each_other_are_collected() {
+        let source = "
+            import gc;
+            func pair() {
+                decenv a = {
+                    let other = null;
+                }
+                decenv b = {
+                    let other = a;
+                }
+                env a {
+                    other = b;
+                }
+            }
+            pair();
+            env gc {
+                println(collect());
+            }
+        ";
+        assert_eq!(run_both(source), "2\n");
+    }
+}
@@ -6,11 +6,12 @@ use std::{
 use crate::{
     ast::expr_types::{ExprPossibilities, Literal, Scope, Stmt},
     interpreter::{environment::Environment, interpreter::Interpreter},
+    heap::heap::Gc,
     lib_functions::BUILTINS,
     parser::parser::Parser,
     scanner::{
         scanner::Scanner,
-        token::{Func, Token, TokenType},
+        token::{Func, Primitive, Token, TokenType},
     },
 };
 
@@ -40,7 +41,10 @@ impl Importer {
         for dir in split_dir.iter() {
             dir_string += (dir.to_string() + "/").as_str();
         }
-        let dir_paths = std::fs::read_dir("./".to_string() + &dir_string).unwrap();
+        if !dir_string.starts_with("/") {
+            dir_string = "./".to_string() + &dir_string;
+        }
+        let dir_paths = std::fs::read_dir(dir_string);
         let lib_path = std::env::current_exe()
             .unwrap()
             .parent()
@@ -52,16 +56,25 @@ impl Importer {
             + "/vmod_lib";
 
         for builtin in BUILTINS.iter() {
-            if files.contains(&builtin.to_string()) {
+            if files.contains(&builtin.to_string()) && let Some(env) = crate::lib_functions::import_lib(builtin) {
                 files.remove(&builtin.to_string());
-                let env = crate::lib_functions::import_lib(&builtin);
-                global_interp.globals.define_env(builtin, env.vars);
+                global_interp.globals.define_const(builtin, Primitive::Env(Gc::new(env)));
+            }
+        }
+
+        for (name, module) in global_interp.modules.iter() {
+            if files.remove(name) {
+                global_interp
+                    .globals
+                    .define_const(name, Primitive::Env(Gc::new(module.clone())));
             }
         }
 
-        for path in dir_paths.chain(std::fs::read_dir(lib_path).unwrap()) {
+        // Hosts embedding vmod usually have no vmod_lib next to their executable
+        let lib_paths = std::fs::read_dir(lib_path);
+        for path in dir_paths.into_iter().chain(lib_paths).flatten().flatten() {
             let mut interpreter = Interpreter::new();
-            let path = path.unwrap().path().into_os_string().into_string().unwrap();
+            let path = path.path().into_os_string().into_string().unwrap();
             let split_path: Vec<&str> = path.split("/").collect();
             let file_name = split_path.last().unwrap().to_string();
             let split_file: Vec<&str> = file_name.split(".").collect();
@@ -78,15 +91,15 @@ impl Importer {
                 self.parser = Parser::new(scanned.token);
                 while !self.parser.is_at_end() {
                     let expr = self.parser.parse().unwrap();
-                    if let ExprPossibilities::Scope(scope) = expr {
+                    if let ExprPossibilities::Scope(scope) = &expr {
                         if TokenType::FUNC == scope.stmt || TokenType::CLOS == scope.stmt {
-                            interpreter.interpret(&ExprPossibilities::Scope(scope.clone()));
+                            interpreter.interpret(&expr);
                         }
                     }
                 }
                 global_interp
                     .globals
-                    .define_env(split_file[0], interpreter.globals.vars);
+                    .define_const(split_file[0], Primitive::Env(Gc::new(interpreter.globals)));
             }
         }
     }
@@ -0,0 +1,182 @@
+use std::collections::HashSet;
+
+use crate::{
+    error_reporting::interp_err::InterpException,
+    heap::heap::Gc,
+    lib_functions::{list_of, runtime::Runtime},
+    scanner::token::{Primitive, Token, TokenType},
+};
+
+use super::environment::Environment;
+
+// The name of the function an env can define to choose how it's shown
+const HOOK: &str = "to_string";
+
+// Text a value is shown as by print, str() and string concatenation. Strings inside a
+// collection are quoted, and envs are shown by their own to_string if they define one.
+pub fn to_string(runtime: &mut dyn Runtime, value: &Primitive) -> Result<String, InterpException> {
+    let mut formatter = Formatter {
+        runtime: Some(runtime),
+        visiting: HashSet::new(),
+    };
+    return formatter.show(value);
+}
+
+// The same text, for when there's no engine to run a to_string with
+pub fn plain(value: &Primitive) -> String {
+    let mut formatter = Formatter {
+        runtime: None,
+        visiting: HashSet::new(),
+    };
+    return formatter.show(value).unwrap_or_default();
+}
+
+// Adding a string to a collection or an env shows it the way print would
+pub fn concat_operands(
+    runtime: &mut dyn Runtime,
+    operator: &Token,
+    left: Primitive,
+    right: Primitive,
+) -> Result<(Primitive, Primitive), InterpException> {
+    if operator.tok != TokenType::PLUS {
+        return Ok((left, right));
+    }
+    match (&left, &right) {
+        (Primitive::String(_), other) if is_collection(other) => {
+            let text = to_string(runtime, other)?;
+            return Ok((left, Primitive::String(text)));
+        }
+        (other, Primitive::String(_)) if is_collection(other) => {
+            let text = to_string(runtime, other)?;
+            return Ok((Primitive::String(text), right));
+        }
+        _ => return Ok((left, right)),
+    }
+}
+
+fn is_collection(value: &Primitive) -> bool {
+    return matches!(
+        value,
+        Primitive::Env(_) | Primitive::List(_) | Primitive::Set(_) | Primitive::Deque(_)
+    );
+}
+
+struct Formatter<'a> {
+    runtime: Option<&'a mut dyn Runtime>,
+    // Collections being shown further up, so a cycle is cut short instead of recursing
+    visiting: HashSet<usize>,
+}
+
+impl<'a> Formatter<'a> {
+    fn show(&mut self, value: &Primitive) -> Result<String, InterpException> {
+        if let Primitive::String(string) = value {
+            return Ok(string.clone());
+        }
+        let mut out = String::new();
+        self.write(value, &mut out)?;
+        return Ok(out);
+    }
+
+    fn write(&mut self, value: &Primitive, out: &mut String) -> Result<(), InterpException> {
+        match value {
+            Primitive::Float(flt) => out.push_str(&flt.to_string()),
+            Primitive::Int(int) => out.push_str(&int.to_string()),
+            Primitive::String(string) => {
+                out.push('"');
+                out.push_str(string);
+                out.push('"');
+            }
+            Primitive::Bool(boolean) => out.push_str(&boolean.to_string()),
+            Primitive::None => out.push_str("null"),
+            Primitive::Func(func) => {
+                let name = func.overloads[0]
+                    .ident
+                    .as_ref()
+                    .map_or("", |ident| &ident.lexeme);
+                out.push_str(&format!("<func {}>", name));
+            }
+            Primitive::NativeFunc(_) => out.push_str("<native func>"),
+            Primitive::List(list) => {
+                let items = list.borrow().clone();
+                return self.items(list.id(), "[", &items, "]", out);
+            }
+            Primitive::Set(set) => {
+                let items: Vec<Primitive> = set.borrow().iter().cloned().collect();
+                return self.items(set.id(), "{", &items, "}", out);
+            }
+            Primitive::Deque(deque) => {
+                let items: Vec<Primitive> = deque.borrow().items.iter().cloned().collect();
+                return self.items(deque.id(), "deque[", &items, "]", out);
+            }
+            Primitive::Env(env) => return self.env(env, out),
+        }
+        return Ok(());
+    }
+
+    fn items(
+        &mut self,
+        id: usize,
+        open: &str,
+        items: &[Primitive],
+        close: &str,
+        out: &mut String,
+    ) -> Result<(), InterpException> {
+        if !self.visiting.insert(id) {
+            out.push_str("<cycle>");
+            return Ok(());
+        }
+        out.push_str(open);
+        for (idx, item) in items.iter().enumerate() {
+            if idx > 0 {
+                out.push_str(", ");
+            }
+            self.write(item, out)?;
+        }
+        out.push_str(close);
+        self.visiting.remove(&id);
+        return Ok(());
+    }
+
+    // Members are sorted by name, so an env always shows the same way
+    fn env(&mut self, env: &Gc<Environment>, out: &mut String) -> Result<(), InterpException> {
+        let value = Primitive::Env(env.clone());
+        if let Some(list) = list_of(&value) {
+            return self.write(&Primitive::List(list), out);
+        }
+        let hook = env.borrow().vars.get(HOOK).cloned();
+        if let Some(hook @ (Primitive::Func(_) | Primitive::NativeFunc(_))) = hook && let Some(runtime) = self.runtime.as_deref_mut() {
+            match runtime.invoke_in(env, HOOK, hook, Vec::new())? {
+                Primitive::String(text) => out.push_str(&text),
+                other => {
+                    return Err(InterpException::InvalidValue(
+                        HOOK.to_string(),
+                        format!(
+                            "expected it to return a string but got {}",
+                            other.type_name()
+                        ),
+                    ))
+                }
+            }
+            return Ok(());
+        }
+
+        if !self.visiting.insert(env.id()) {
+            out.push_str("<cycle>");
+            return Ok(());
+        }
+        let mut members: Vec<(String, Primitive)> = env.borrow().vars.clone().into_iter().collect();
+        members.sort_by(|left, right| left.0.cmp(&right.0));
+        out.push('{');
+        for (idx, (name, member)) in members.iter().enumerate() {
+            if idx > 0 {
+                out.push_str(", ");
+            }
+            out.push_str(name);
+            out.push_str(": ");
+            self.write(member, out)?;
+        }
+        out.push('}');
+        self.visiting.remove(&env.id());
+        return Ok(());
+    }
+}
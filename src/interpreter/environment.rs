@@ -1,74 +1,330 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    vec,
+};
 
 use crate::{
     error_reporting::{interp_err::InterpException, parsing_err::ParsingException},
+    heap::heap::Gc,
+    lib_functions::{
+        native::{HostFunction, IntoNative},
+        LibFunctions, ListLibFunctions,
+    },
     scanner::token::Primitive,
 };
 
-#[derive(Clone, Debug, PartialEq)]
+// The variables of one scope, in the order they were first declared. The resolver hands
+// out the same order as slots, so a resolved variable is found by its position, and its name
+// is only hashed to declare it or to look it up dynamically.
+#[derive(Clone, Debug, Default)]
+pub struct Vars {
+    slots: HashMap<String, usize>,
+    entries: Vec<(String, Primitive)>,
+}
+
+impl Vars {
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Primitive> {
+        let slot = *self.slots.get(name)?;
+        return Some(&self.entries[slot].1);
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        return self.slots.contains_key(name);
+    }
+
+    pub fn insert(&mut self, name: &str, value: Primitive) {
+        match self.slots.get(name) {
+            Some(slot) => self.entries[*slot].1 = value,
+            None => {
+                self.slots.insert(name.to_string(), self.entries.len());
+                self.entries.push((name.to_string(), value));
+            }
+        }
+    }
+
+    // Where the name is, trying the slot the resolver gave it before hashing the name
+    pub fn find(&self, slot: Option<usize>, name: &str) -> Option<usize> {
+        if let Some(slot) = slot && let Some((held, _)) = self.entries.get(slot) && held == name {
+            return Some(slot);
+        }
+        return self.slots.get(name).copied();
+    }
+
+    pub fn at(&self, slot: usize) -> &Primitive {
+        return &self.entries[slot].1;
+    }
+
+    pub fn set(&mut self, slot: usize, value: Primitive) {
+        self.entries[slot].1 = value;
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        return self.entries.iter().map(|(name, _)| name);
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Primitive> {
+        return self.entries.iter().map(|(_, value)| value);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Primitive)> {
+        return self.entries.iter().map(|(name, value)| (name, value));
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut Primitive)> {
+        return self.entries.iter_mut().map(|(name, value)| (&*name, value));
+    }
+}
+
+// Equal when they hold the same values under the same names, in whatever order
+impl PartialEq for Vars {
+    fn eq(&self, other: &Self) -> bool {
+        return self.len() == other.len()
+            && self.iter().all(|(name, value)| other.get(name) == Some(value));
+    }
+}
+
+impl IntoIterator for Vars {
+    type Item = (String, Primitive);
+    type IntoIter = vec::IntoIter<(String, Primitive)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        return self.entries.into_iter();
+    }
+}
+
+impl FromIterator<(String, Primitive)> for Vars {
+    fn from_iter<I: IntoIterator<Item = (String, Primitive)>>(iter: I) -> Self {
+        let mut vars = Vars::default();
+        for (name, value) in iter {
+            vars.insert(&name, value);
+        }
+        return vars;
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Environment {
-    pub vars: HashMap<String, Primitive>,
+    pub vars: Vars,
+    pub constants: HashSet<String>,
     pub enclosing: Option<Box<Environment>>,
+    // Set on the scope an env block runs in, whose variables are the env's own fields
+    pub shared: Option<Gc<Environment>>,
 }
 
-impl PartialOrd for Environment {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let my_len = self.vars.len();
-        let other_len = other.vars.len();
-        if my_len > other_len {
-            return Some(std::cmp::Ordering::Greater);
-        } else if my_len < other_len {
-            return Some(std::cmp::Ordering::Less);
-        } else {
-            return Some(std::cmp::Ordering::Equal);
-        }
+// Environments compare by their own fields, not by the scope they were called from
+impl PartialEq for Environment {
+    fn eq(&self, other: &Self) -> bool {
+        self.vars == other.vars
     }
 }
 
 impl Environment {
     pub fn new() -> Self {
         return Environment {
-            vars: HashMap::new(),
+            vars: Vars::default(),
+            constants: HashSet::new(),
             enclosing: None,
+            shared: None,
         };
     }
 
+    // A scope reaching the fields of an env, rather than holding copies of them
+    pub fn shared(env: &Gc<Environment>) -> Self {
+        let mut scope = Environment::new();
+        scope.shared = Some(env.clone());
+        return scope;
+    }
+
+    pub fn with_vars(vars: HashMap<String, Primitive>) -> Self {
+        let mut env = Environment::new();
+        env.vars = vars.into_iter().collect();
+        return env;
+    }
+
     pub fn define(&mut self, name: &str, value: Primitive) {
-        self.vars.insert(name.to_string(), value);
+        self.vars.insert(name, value);
     }
 
-    pub fn define_env(&mut self, name: &str, vars: HashMap<String, Primitive>) {
-        let env: Environment = Environment {
-            vars,
-            enclosing: None,
-        };
-        self.vars.insert(name.to_string(), Primitive::Env(env));
+    pub fn define_const(&mut self, name: &str, value: Primitive) {
+        self.define(name, value);
+        self.constants.insert(name.to_string());
+    }
+
+    // Host functions are constant, like the builtins
+    pub fn define_native<Args>(&mut self, name: &str, func: impl IntoNative<Args>) {
+        let func = HostFunction::new(name, func);
+        self.define_const(name, Primitive::NativeFunc(LibFunctions::Host(func)));
+    }
+
+    pub fn define_native_variadic(
+        &mut self,
+        name: &str,
+        func: impl Fn(Vec<Primitive>) -> Result<Primitive, InterpException> + 'static,
+    ) {
+        let func = HostFunction::variadic(name, func);
+        self.define_const(name, Primitive::NativeFunc(LibFunctions::Host(func)));
+    }
+
+    // Used by `let`, which may shadow constants from enclosing scopes but not replace one
+    pub fn declare(&mut self, name: &str, value: Primitive) -> Result<(), InterpException> {
+        if self.constants.contains(name) {
+            return Err(InterpException::AssignToConstant(name.to_string()));
+        }
+        self.define(name, value);
+        return Ok(());
+    }
+
+    // Moves an env onto the heap, detached from the scope it was built in
+    pub fn define_env(&mut self, name: &str, env: Environment) {
+        let mut env = env;
+        env.enclosing = None;
+        self.vars.insert(name, Primitive::Env(Gc::new(env)));
+    }
+
+    // Chains can be as long as there are calls waiting on each other, so they're walked in a loop
+    pub fn is_constant(&self, name: &str) -> bool {
+        let mut env = self;
+        loop {
+            if let Some(shared) = &env.shared {
+                let shared = shared.borrow();
+                if shared.vars.contains_key(name) {
+                    return shared.constants.contains(name);
+                }
+            } else if env.vars.contains_key(name) {
+                return env.constants.contains(name);
+            }
+            match env.enclosing.as_deref() {
+                Some(higher) => env = higher,
+                None => return false,
+            }
+        }
     }
 
     pub fn retrieve(&self, name: &str) -> Result<Primitive, InterpException> {
-        if let Some(val) = self.vars.get(name) {
-            return Ok(val.clone());
-        } else if let Some(higher) = &self.enclosing {
-            return higher.as_ref().retrieve(name);
-        } else {
-            return Err(InterpException::IdentifierNoExist(name.to_string()));
+        let mut env = self;
+        loop {
+            if let Some(val) = env.get_here(name) {
+                return Ok(val);
+            }
+            match env.enclosing.as_deref() {
+                Some(higher) => env = higher,
+                None => return Err(InterpException::IdentifierNoExist(name.to_string())),
+            }
         }
     }
 
+    // What a name holds in this scope, not looking further out
+    fn get_here(&self, name: &str) -> Option<Primitive> {
+        if let Some(shared) = &self.shared {
+            return shared.borrow().get_here(name);
+        } else if let Some(val) = self.vars.get(name) {
+            return Some(val.clone());
+        } else if let Some(method) = ListLibFunctions::from_name(name) && self.vars.contains_key("list") {
+            // Shared by every list rather than copied into each list's env
+            return Some(Primitive::NativeFunc(LibFunctions::List(method)));
+        }
+        return None;
+    }
+
+    pub fn ancestor(&self, depth: usize) -> Option<&Environment> {
+        let mut env = self;
+        for _ in 0..depth {
+            env = env.enclosing.as_deref()?;
+        }
+        return Some(env);
+    }
+
+    // Number of scopes enclosing this one
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut env = self;
+        while let Some(enclosing) = env.enclosing.as_deref() {
+            env = enclosing;
+            depth += 1;
+        }
+        return depth;
+    }
+
+    // Leaves the innermost `count` scopes, keeping whatever they changed further out
+    pub fn unwind(&mut self, count: usize) {
+        for _ in 0..count {
+            *self = *self.enclosing.take().unwrap();
+        }
+    }
+
+    // Resolved lookups go straight to the declaring scope and slot, falling back to a full
+    // search when the scope chain no longer matches what the resolver saw
+    pub fn retrieve_at(
+        &self,
+        depth: usize,
+        slot: Option<usize>,
+        name: &str,
+    ) -> Result<Primitive, InterpException> {
+        if let Some(env) = self.ancestor(depth) && let Some(slot) = env.vars.find(slot, name) {
+            return Ok(env.vars.at(slot).clone());
+        }
+        return self.retrieve(name);
+    }
+
+    pub fn redefine_at(
+        &mut self,
+        depth: usize,
+        slot: Option<usize>,
+        name: &str,
+        value: Primitive,
+    ) -> Result<(), InterpException> {
+        let Some(slot) = self.ancestor(depth).and_then(|env| env.vars.find(slot, name)) else {
+            return self.redefine(name, value);
+        };
+
+        let mut env = self;
+        for _ in 0..depth {
+            env = env.enclosing.as_deref_mut().unwrap();
+        }
+        if !env.constants.is_empty() && env.constants.contains(name) {
+            return Err(InterpException::AssignToConstant(name.to_string()));
+        }
+        env.vars.set(slot, value);
+        return Ok(());
+    }
+
     pub fn redefine(&mut self, name: &str, value: Primitive) -> Result<(), InterpException> {
-        if let Some(_) = self.vars.get(name) {
-            if let Primitive::Env(env) = value {
-                self.define_env(name, env.vars.clone());
-            } else {
-                self.define(name, value);
+        let mut env = self;
+        loop {
+            if let Some(shared) = &env.shared {
+                let holds = shared.borrow().vars.contains_key(name);
+                if holds {
+                    return shared.borrow_mut().redefine(name, value);
+                }
+            } else if env.vars.contains_key(name) {
+                if env.constants.contains(name) {
+                    return Err(InterpException::AssignToConstant(name.to_string()));
+                }
+                env.define(name, value);
+                return Ok(());
             }
-            return Ok(());
-        } else if let Some(mut enc) = self.enclosing.clone() {
-            enc.as_mut().redefine(name, value)?;
-            self.enclosing = Some(enc);
-            return Ok(());
-        } else {
-            return Err(InterpException::IdentifierNoExist(name.to_string()));
+            match env.enclosing.as_deref_mut() {
+                Some(higher) => env = higher,
+                None => return Err(InterpException::IdentifierNoExist(name.to_string())),
+            }
+        }
+    }
+}
+
+// Dropped a scope at a time, rather than recursing down a chain that may be long
+impl Drop for Environment {
+    fn drop(&mut self) {
+        let mut enclosing = self.enclosing.take();
+        while let Some(mut env) = enclosing {
+            enclosing = env.enclosing.take();
         }
     }
 }
@@ -1,38 +1,119 @@
-use std::{collections::HashMap, ops::Deref, rc::Rc};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+    mem,
+    ops::Deref,
+};
 
 use crate::{
     ast::{
         ast_traits::{Accept, Interperable},
-        expr_types::ExprPossibilities,
+        expr_types::{ExprPossibilities, Stmt},
     },
     error_reporting::{
         error_reporter::Unwindable, interp_err::InterpException, parsing_err::ParsingException,
     },
+    heap::heap::Gc,
     lib_functions::{
-        list_ops::{append, len, set, slice},
-        math::Math,
-        LibFunctions,
+        call_native, native::IntoNative, random::Rng, runtime::Runtime, LibFunctions,
+        GLOBAL_FUNCTIONS,
     },
-    scanner::token::{Func, Primitive, TokenType},
+    scanner::token::{Func, Primitive, Token, TokenType},
+};
+
+use super::{
+    display,
+    environment::Environment,
+    operations::{self, TailFrames},
+    permissions::Permissions,
+    sandbox::{CancelHandle, Limits, Sandbox},
 };
 
-use super::environment::Environment;
+// How deeply calls can nest before raising a stack overflow, unless the host sets its own
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+// Bytes of native stack nested calls can take up before raising a stack overflow. A call
+// takes several Rust frames, and a lot more of them in a debug build, so the depth alone
+// can't keep a script from overflowing the stack. This fits in the 2 MiB a spawned
+// thread gets with room to spare; hosts running on a bigger stack can raise it.
+pub const DEFAULT_MAX_STACK: usize = 1 << 20;
 
 pub struct Interpreter {
     pub globals: Environment,
+    pub max_depth: usize,
+    pub max_stack: usize,
+    // Modules registered by the host, imported by name like the builtin ones
+    pub modules: HashMap<String, Environment>,
+    // Where print statements and runtime errors are written
+    pub stdout: Box<dyn Write>,
+    pub stderr: Box<dyn Write>,
+    pub stdin: Box<dyn BufRead>,
+    // Arguments given to the script after its path
+    pub script_args: Vec<String>,
+    // Set once the script calls sys.exit
+    pub exit_code: Option<i32>,
+    // Set once a statement stops with an error, though the ones after it still run
+    pub failed: bool,
+    // Limits on untrusted scripts, if they're run in a sandbox
+    pub sandbox: Option<Sandbox>,
+    // What native functions doing I/O are allowed to touch
+    pub permissions: Permissions,
+    // Seeded from the clock unless the host or the script picks a seed
+    pub rng: Rng,
+    depth: usize,
+    // Where the stack was when the outermost call running was made
+    stack_base: usize,
+    tail_call: Option<TailCall>,
+}
+
+// A call in return position, waiting for the function it replaces to be left
+struct TailCall {
+    func: Func,
+    ident: Token,
+    positional: Vec<Primitive>,
+    keywords: Vec<(Token, Primitive)>,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
         let mut globals = Environment::new();
-        globals.define("len", Primitive::NativeFunc(LibFunctions::Len));
-        globals.define("int", Primitive::NativeFunc(LibFunctions::Int));
-        globals.define("float", Primitive::NativeFunc(LibFunctions::Float));
-        globals.define("str", Primitive::NativeFunc(LibFunctions::String));
-        return Self { globals };
+        for (name, func) in GLOBAL_FUNCTIONS {
+            globals.define_const(name, Primitive::NativeFunc(func));
+        }
+        return Self {
+            globals,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_stack: DEFAULT_MAX_STACK,
+            modules: HashMap::new(),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            stdin: Box::new(BufReader::new(io::stdin())),
+            script_args: Vec::new(),
+            exit_code: None,
+            failed: false,
+            sandbox: None,
+            permissions: Permissions::none(),
+            rng: Rng::from_time(),
+            depth: 0,
+            stack_base: 0,
+            tail_call: None,
+        };
+    }
+
+    pub fn sandboxed(limits: Limits) -> Self {
+        let mut interpreter = Self::new();
+        if let Some(max_depth) = limits.max_depth {
+            interpreter.max_depth = max_depth;
+        }
+        interpreter.sandbox = Some(Sandbox::new(limits));
+        return interpreter;
+    }
+
+    pub fn cancel_handle(&self) -> Option<CancelHandle> {
+        return self.sandbox.as_ref().map(Sandbox::cancel_handle);
     }
 
     pub fn interpret(&mut self, expr: &ExprPossibilities) {
+        let _metering = self.sandbox.as_ref().map(Sandbox::metering);
         let eval = self.evaluate(expr);
         if let Ok(prim) = eval {
             // match prim {
@@ -42,184 +123,97 @@ impl Interpreter {
             //     Primitive::Bool(boolean) => println!("{}", boolean),
             //     Primitive::None => print!(""),
             // }
+        } else if let Err(InterpException::Exit(code)) = eval {
+            self.exit_code = Some(code);
         } else if let Err(err) = eval {
-            println!("{}", err.get_value())
+            self.failed = true;
+            let _ = writeln!(self.stderr, "{}", err.get_value());
         }
     }
 
-    fn evaluate(&mut self, expr: &ExprPossibilities) -> Result<Primitive, InterpException> {
-        return ExprPossibilities::accept(expr.clone(), self);
+    // Makes a Rust closure callable from scripts under the given name
+    pub fn register<Args>(&mut self, name: &str, func: impl IntoNative<Args>) {
+        self.globals.define_native(name, func);
+    }
+
+    pub fn register_module(&mut self, name: &str, module: Environment) {
+        self.modules.insert(name.to_string(), module);
     }
 
-    fn convert_bool(b: bool) -> isize {
-        if b {
-            return 1;
-        } else {
-            return 0;
+    // Like interpret, but hands the result back instead of printing errors
+    pub fn execute(&mut self, expr: &ExprPossibilities) -> Result<Primitive, InterpException> {
+        let _metering = self.sandbox.as_ref().map(Sandbox::metering);
+        return self.evaluate(expr);
+    }
+
+    // Calls a function value from outside of any script, as a call expression would
+    pub fn call_value(
+        &mut self,
+        name: &str,
+        callee: Primitive,
+        args: Vec<Primitive>,
+    ) -> Result<Primitive, InterpException> {
+        let _metering = self.sandbox.as_ref().map(Sandbox::metering);
+        match callee {
+            Primitive::Func(func) => {
+                let ident = Token::new(TokenType::IDENTIFIER, name.to_string(), 0, None);
+                return self.call(func, ident, args, Vec::new());
+            }
+            Primitive::NativeFunc(func) => {
+                return call_native(&func, args, self)
+            }
+            _ => return Err(InterpException::NotCallable(name.to_string())),
         }
     }
+
+    fn evaluate(&mut self, expr: &ExprPossibilities) -> Result<Primitive, InterpException> {
+        if let Some(sandbox) = &mut self.sandbox {
+            sandbox.step()?;
+        }
+        return expr.accept(self);
+    }
 }
 
 impl Interperable<Result<Primitive, InterpException>> for Interpreter {
     fn visit_expr(
         &mut self,
-        expr: crate::ast::expr_types::ExprPossibilities,
+        expr: &ExprPossibilities,
     ) -> Result<Primitive, InterpException> {
         match expr {
             crate::ast::expr_types::ExprPossibilities::Binary(bin) => {
                 let left = self.evaluate(&bin.left)?;
                 let right = self.evaluate(&bin.right)?;
-
-                match bin.operator.tok {
-                    TokenType::AND => {
-                        if let Primitive::Bool(bool1) = left && let Primitive::Bool(bool2) = right {
-                            return Ok(Primitive::Bool(bool1 && bool2));
-                        } else {
-                            return Err(InterpException::PlaceHolder);
-                        }
-                    },
-                    TokenType::OR => {
-                        if let Primitive::Bool(bool1) = left && let Primitive::Bool(bool2) = right {
-                            return Ok(Primitive::Bool(bool1 || bool2));
-                        } else {
-                            return Err(InterpException::PlaceHolder);
-                        }
-                    }
-                    TokenType::SLASH => {
-                        if Primitive::Int(0) != right && let Primitive::Int(divisor) = left && let Primitive::Int(dividend) = right {
-                            return Ok(Primitive::Int(divisor / dividend));
-                        } else if Primitive::Float(0.) != left && let Primitive::Float(divisor) = left && let Primitive::Float(dividend) = right {
-                            return Ok(Primitive::Float(divisor / dividend));
-                        } else if Primitive::Int(0) == left || Primitive::Float(0.0) == left {
-                            return Err(InterpException::DivideByZero(bin));
-                        }
-                        else {
-                            return Err(InterpException::InvalidBinary(bin))
-                        }
-                    },
-                    TokenType::STAR => {
-                        if let Primitive::Int(num1) = left {
-                            match right {
-                                Primitive::Float(num2) => return Ok(Primitive::Float(num1 as f32 * num2)),
-                                Primitive::Int(num2) => return Ok(Primitive::Int(num1 * num2)),
-                                Primitive::String(_strng) => return Err(InterpException::InvalidBinary(bin)),
-                                Primitive::Bool(boolean) => return Ok(Primitive::Int(num1 * Interpreter::convert_bool(boolean))),
-                                _ => return Err(InterpException::InvalidBinary(bin)),
-                            }
-                        } else if let Primitive::Float(num1) = left && let Primitive::Float(num2) = right {
-                            match right {
-                                Primitive::Float(num2) => return Ok(Primitive::Float(num1 * num2)),
-                                Primitive::Int(num2) => return Ok(Primitive::Float(num1 * num2 as f32)),
-                                Primitive::String(_strng) => return Err(InterpException::InvalidBinary(bin)),
-                                Primitive::Bool(boolean) => return Ok(Primitive::Float(num1 * Interpreter::convert_bool(boolean) as f32)),
-                                _ => return Err(InterpException::InvalidBinary(bin)),
-                            }
-                        } else {
-                            return Err(InterpException::InvalidBinary(bin))
-                        }
-                    },
-                    TokenType::MINUS => {
-                        if let Primitive::Int(num1) = left && let Primitive::Int(num2) = right {
-                            return Ok(Primitive::Int(num1 - num2));
-                        } else if let Primitive::Float(num1) = left && let Primitive::Float(num2) = right {
-                            return Ok(Primitive::Float(num1 - num2));
-                        } else {
-                            return Err(InterpException::InvalidBinary(bin))
-                        }
-                    },
-                    TokenType::MODULO => {
-                        if let Primitive::Int(num1) = left && let Primitive::Int(num2) = right {
-                            return Ok(Primitive::Int(num1 % num2));
-                        } else if let Primitive::Float(num1) = left && let Primitive::Float(num2) = right {
-                            return Ok(Primitive::Float(num1 % num2));
-                        } else {
-                            return Err(InterpException::InvalidBinary(bin))
-                        }
-                    },
-                    TokenType::PLUS => {
-                        if let Primitive::Int(num1) = left {
-                            match right {
-                                Primitive::Float(num2) => return Ok(Primitive::Float(num1 as f32 + num2)),
-                                Primitive::Int(num2) => return Ok(Primitive::Int(num1 + num2)),
-                                Primitive::String(strng) => return Ok(Primitive::String(num1.to_string() + &strng)),
-                                Primitive::Bool(boolean) => return Ok(Primitive::Int(num1 + Interpreter::convert_bool(boolean))),
-                                _ => return Err(InterpException::InvalidBinary(bin)),
-                            }
-                        } else if let Primitive::Float(num1) = left {
-                            match right {
-                                Primitive::Float(num2) => return Ok(Primitive::Float(num1 + num2)),
-                                Primitive::Int(num2) => return Ok(Primitive::Float(num1 + num2 as f32)),
-                                Primitive::String(strng) => return Ok(Primitive::String(num1.to_string() + &strng)),
-                                Primitive::Bool(boolean) => return Ok(Primitive::Float(num1 + Interpreter::convert_bool(boolean) as f32)),
-                                _ => return Err(InterpException::InvalidBinary(bin)),
-                            }
-                        } else if let Primitive::String(str1) = left {
-                            match right {
-                                Primitive::Float(flt) => return Ok(Primitive::String(str1 + &flt.to_string())),
-                                Primitive::Int(int) => return Ok(Primitive::String(str1 + &int.to_string())),
-                                Primitive::String(str2) => return Ok(Primitive::String(str1 + str2.as_str())),
-                                Primitive::Bool(boolean) => return Ok(Primitive::String(str1 + &boolean.to_string())),
-                                _ => return Err(InterpException::InvalidBinary(bin)),
-                            }
-                        } else {
-                            return Err(InterpException::InvalidBinary(bin))
-                        }
-                    },
-                    TokenType::EQUAL_EQUAL => {
-                        return Ok(Primitive::Bool(right == left));
-                    },
-                    TokenType::BANG_EQUAL => {
-                        return Ok(Primitive::Bool(right != left))
-                    },
-                    TokenType::GREATER => {
-                        return Ok(Primitive::Bool(left > right))
-                    },
-                    TokenType::GREATER_EQUAL => {
-                        return Ok(Primitive::Bool(left >= right))
-                    },
-                    TokenType::LESS_EQUAL => {
-                        return Ok(Primitive::Bool(left <= right))
-                    },
-                    TokenType::LESS => {
-                        return Ok(Primitive::Bool(left < right))
-                    }
-
-                    _ => return Err(InterpException::InvalidBinary(bin))
+                let (left, right) = display::concat_operands(self, &bin.operator, left, right)?;
+                let value = operations::binary(&bin.operator, left, right)?;
+                if let Some(sandbox) = &self.sandbox {
+                    sandbox.check_value(&value)?;
                 }
+                return Ok(value);
             }
             crate::ast::expr_types::ExprPossibilities::Grouping(group) => {
                 return self.evaluate(&group.expr[0]);
             }
             crate::ast::expr_types::ExprPossibilities::Literal(lit) => {
-                return Ok(lit.literal);
+                return Ok(lit.literal.clone());
             }
             crate::ast::expr_types::ExprPossibilities::Ternary(ternary) => {
                 let operator_res = self.evaluate(&ternary.condition)?;
-                let bool_val = match operator_res {
-                    Primitive::Float(flt) => flt != 0.0,
-                    Primitive::Int(num) => num != 0,
-                    Primitive::String(string) => string.len() != 0,
-                    Primitive::Bool(bool_val) => bool_val,
-                    Primitive::None => false,
-                    _ => return Err(InterpException::PlaceHolder),
-                };
+                let bool_val = operations::truthy(operator_res)?;
 
-                if bool_val && let Some(expr) = ternary.true_cond {
-                    return self.evaluate(&expr);
-                } else if !bool_val && let Some(expr) = ternary.false_cond {
-                    return self.evaluate(&expr);
+                if bool_val && let Some(expr) = &ternary.true_cond {
+                    return self.evaluate(expr);
+                } else if !bool_val && let Some(expr) = &ternary.false_cond {
+                    return self.evaluate(expr);
                 } else {
                     return Ok(Primitive::None);
                 }
             }
             ExprPossibilities::Stmt(stmt) => match stmt.stmt {
                 TokenType::LEFT_SQUARE => unsafe {
-                    let value = self
-                        .globals
-                        .retrieve(&stmt.ident.as_ref().unwrap_unchecked().lexeme)?
-                        .clone();
+                    let value = self.lookup(stmt)?;
                     if let Primitive::List(vec) = value {
                         let idx = self.evaluate(stmt.inner.as_deref().unwrap_unchecked())?;
+                        let vec = vec.borrow();
                         if let Primitive::Int(int) = idx && (int as usize) < vec.len() {
                             return Ok(vec[int as usize].clone());
                         } else {
@@ -228,187 +222,68 @@ impl Interperable<Result<Primitive, InterpException>> for Interpreter {
                     }
 
                     return Err(InterpException::IdentifierNoExist(
-                        stmt.ident.unwrap_unchecked().to_string(),
+                        stmt.ident.as_ref().unwrap_unchecked().to_string(),
                     ));
                 },
                 TokenType::RETURN => unsafe {
-                    let expr = *stmt.inner.unwrap_unchecked();
-                    return self.evaluate(&expr);
-                },
-                TokenType::PRINT => unsafe {
-                    let expr = self.evaluate(&stmt.inner.unwrap_unchecked())?;
-                    match expr {
-                        Primitive::Float(flt) => print!("{}", flt),
-                        Primitive::Int(int) => print!("{}", int),
-                        Primitive::String(strng) => print!("{}", strng),
-                        Primitive::Bool(boolean) => print!("{}", boolean),
-                        Primitive::Env(env) => print!("{:?}", env),
-                        Primitive::None => print!("null"),
-                        Primitive::List(vec) => print!("{:?}", vec),
-                        _ => return Err(InterpException::PlaceHolder),
+                    let inner = stmt.inner.as_deref().unwrap_unchecked();
+                    if stmt.tail && let ExprPossibilities::Stmt(call) = inner && let Primitive::Func(func) = self.lookup(call)? {
+                        let (positional, keywords) = self.evaluate_args(call.params.as_deref().unwrap_unchecked())?;
+                        self.tail_call = Some(TailCall {
+                            func,
+                            ident: call.ident.clone().unwrap_unchecked(),
+                            positional,
+                            keywords,
+                        });
+                        return Ok(Primitive::None);
                     }
-
-                    return Ok(Primitive::None);
+                    return self.evaluate(inner);
                 },
-                TokenType::PRINTLN => {
-                    unsafe {
-                        let expr = self.evaluate(&stmt.inner.unwrap_unchecked())?;
-                        match expr {
-                            Primitive::Float(flt) => println!("{}", flt),
-                            Primitive::Int(int) => println!("{}", int),
-                            Primitive::String(strng) => println!("{}", strng),
-                            Primitive::Bool(boolean) => println!("{}", boolean),
-                            Primitive::Env(env) => println!("{:?}", env),
-                            Primitive::None => println!("null"),
-                            Primitive::List(vec) => println!("{:?}", vec),
-                            _ => return Err(InterpException::PlaceHolder),
-                        }
-                    }
-
+                TokenType::PRINT | TokenType::PRINTLN => unsafe {
+                    let expr = self.evaluate(stmt.inner.as_deref().unwrap_unchecked())?;
+                    let output = display::to_string(self, &expr)?;
+                    operations::print(&mut *self.stdout, &output, stmt.stmt == TokenType::PRINTLN)?;
                     return Ok(Primitive::None);
-                }
-                TokenType::LET => unsafe {
-                    let expr = self.evaluate(&stmt.inner.clone().unwrap_unchecked())?;
-                    if let Primitive::List(list) = expr {
-                        self.define_list(&stmt.clone(), list);
-                    } else {
-                        self.globals
-                            .define(&stmt.ident.unwrap_unchecked().lexeme, expr);
-                    }
+                },
+                TokenType::LET | TokenType::CONST => unsafe {
+                    let expr = self.evaluate(stmt.inner.as_deref().unwrap_unchecked())?;
+                    let ident = stmt.ident.as_ref().unwrap_unchecked().lexeme.clone();
+                    operations::declare(&mut self.globals, ident, expr, stmt.stmt == TokenType::CONST)?;
                     return Ok(Primitive::None);
                 },
                 TokenType::IDENTIFIER => unsafe {
                     match stmt.inner {
-                        Some(value) => {
-                            let primitive = self.evaluate(&value)?.clone();
-
-                            self.globals
-                                .redefine(&stmt.ident.unwrap_unchecked().lexeme, primitive)?;
+                        Some(ref value) => {
+                            let primitive = self.evaluate(value)?.clone();
+                            let ident = &stmt.ident.as_ref().unwrap_unchecked().lexeme;
+                            match stmt.depth {
+                                Some(depth) => {
+                                    self.globals.redefine_at(depth, stmt.slot, ident, primitive)?
+                                }
+                                None => self.globals.redefine(ident, primitive)?,
+                            }
                             return Ok(Primitive::None);
                         }
-                        None => {
-                            return self.globals.retrieve(&stmt.ident.unwrap_unchecked().lexeme)
-                        }
+                        None => return self.lookup(stmt),
                     }
                 },
                 TokenType::FUNC => unsafe {
-                    let ident = stmt.ident.unwrap_unchecked();
-                    let func_data = self.globals.retrieve(&ident.lexeme)?;
+                    let func_data = self.lookup(stmt)?;
+                    let ident = stmt.ident.as_ref().unwrap_unchecked();
 
                     if let Primitive::Func(func) = func_data {
-                        let mut func_scope = self.enclose();
-                        let inputted_params = stmt.params.unwrap_unchecked();
-                        match func.func_map.get(&inputted_params.clone().len()) {
-                            Some((params, code)) => {
-                                for (idx, param_name) in params.iter().enumerate() {
-                                    let prim = self.evaluate(&inputted_params[idx])?;
-                                    if let Primitive::Env(env) = prim {
-                                        func_scope.define_env(&param_name.lexeme, env.vars);
-                                    } else {
-                                        func_scope.define(&param_name.lexeme, prim);
-                                    }
-                                }
-                                self.globals = func_scope.clone();
-                                for line in code.inner.iter() {
-                                    let prim = self.evaluate(line)?;
-                                    if Primitive::None != prim {
-                                        return Ok(prim);
-                                    }
-                                }
-
-                                let mut enc =
-                                    self.globals.enclosing.clone().unwrap_unchecked().clone();
-
-                                for key in enc.clone().vars.keys() {
-                                    let value = self.globals.retrieve(key).unwrap_unchecked();
-                                    if let Primitive::Env(env) = value {
-                                        enc.define_env(key, env.vars);
-                                    } else {
-                                        enc.define(key, value);
-                                    }
-                                }
-
-                                self.globals = *enc;
-                                return Ok(Primitive::None);
-                            }
-                            None => {
-                                self.globals = *self.globals.enclosing.clone().unwrap_unchecked();
-                                return Err(InterpException::PlaceHolder);
-                            }
+                        let inputted_params = stmt.params.as_deref().unwrap_unchecked();
+                        if func.resolve(inputted_params.len()).is_none() {
+                            return Err(InterpException::NoMatchingOverload(ident.lexeme.clone(), inputted_params.len(), ident.line));
                         }
+                        let (positional, keywords) = self.evaluate_args(inputted_params)?;
+                        return self.call(func, ident.clone(), positional, keywords);
                     } else if let Primitive::NativeFunc(func) = func_data {
-                        let params = &stmt.params.unwrap_unchecked();
-                        match func {
-                            LibFunctions::Append => {
-                                if let Primitive::String(ident) = self.globals.retrieve("list")? {
-                                    let list = self.globals.retrieve(&ident)?;
-                                    if let Primitive::List(mut list_uw) = list {
-                                        for param in params.iter() {
-                                            append(&mut list_uw, self.evaluate(&param)?);
-                                        }
-                                        self.globals.redefine(&ident, Primitive::List(list_uw))?;
-                                    }
-                                };
-                            }
-                            LibFunctions::Set => {
-                                if let Primitive::String(ident) = self.globals.retrieve("list")? {
-                                    let list = self.globals.retrieve(&ident)?;
-                                    if let Primitive::List(mut list_uw) = list {
-                                        set(
-                                            &mut list_uw,
-                                            self.evaluate(&params[0])?,
-                                            self.evaluate(&params[1])?,
-                                        );
-                                        self.globals.redefine(&ident, Primitive::List(list_uw))?;
-                                    }
-                                };
-                            }
-                            LibFunctions::Len => {
-                                if params.len() == 1 {
-                                    if let Primitive::Env(env) = self.evaluate(&params[0])? && let Primitive::String(string) = env.retrieve("list")? && let Primitive::List(list_uw) = env.retrieve(&string)? {
-                                        return Ok(len(&list_uw));
-                                    } else if let Primitive::List(list) = self.evaluate(&params[0])? {
-                                        return Ok(len(&list))
-                                    }
-                                }
-                            }
-                            LibFunctions::Slice => {
-                                if params.len() == 2 {
-                                    if let Primitive::String(ident) =
-                                        self.globals.retrieve("list")?
-                                    {
-                                        let list = self.globals.retrieve(&ident)?;
-                                        if let Primitive::List(mut list_uw) = list {
-                                            if let Primitive::Int(idx1) = self.evaluate(&params[0])? && let Primitive::Int(idx2) = self.evaluate(&params[1])? {
-                                                let vec = slice(&mut list_uw, idx1 as usize, idx2 as usize);
-                                                self.globals.redefine(&ident, Primitive::List(list_uw))?;
-                                                return Ok(Primitive::List(vec));
-                                            }
-                                        }
-                                    };
-                                }
-                            }
-                            LibFunctions::Math(var) => {
-                                let params_parsed: Vec<Result<Primitive, InterpException>> = params
-                                    .as_ref()
-                                    .iter()
-                                    .map(|val| self.evaluate(val))
-                                    .collect();
-                                return Math::do_func(var, params_parsed);
-                            }
-                            LibFunctions::Int => {
-                                return Ok(crate::lib_functions::cast_ops::int(
-                                    self.evaluate(&params[0])?,
-                                ))
-                            }
-                            LibFunctions::String => {
-                                return Ok(crate::lib_functions::cast_ops::string(
-                                    self.evaluate(&params[0])?,
-                                ))
-                            }
-                            LibFunctions::Float => todo!(),
+                        let mut args = Vec::new();
+                        for param in stmt.params.as_deref().unwrap_unchecked().iter() {
+                            args.push(self.evaluate(param)?);
                         }
-                        return Ok(Primitive::None);
+                        return call_native(&func, args, self);
                     } else {
                         return Err(InterpException::PlaceHolder);
                     }
@@ -417,22 +292,7 @@ impl Interperable<Result<Primitive, InterpException>> for Interpreter {
             },
             crate::ast::expr_types::ExprPossibilities::Unary(unary) => {
                 let right = self.evaluate(unary.right.as_ref())?;
-
-                match unary.operator.tok {
-                    TokenType::MINUS | TokenType::BANG => match right {
-                        Primitive::Float(float) => return Ok(Primitive::Float(-float)),
-                        Primitive::Int(int) => return Ok(Primitive::Int(-int)),
-                        Primitive::String(_string) => {
-                            return Err(InterpException::InvalidUnary(unary))
-                        }
-                        Primitive::Bool(boolean) => return Ok(Primitive::Bool(!boolean)),
-                        Primitive::None => return Ok(Primitive::Bool(true)),
-                        _ => return Err(InterpException::InvalidUnary(unary)),
-                    },
-                    _ => {
-                        return Err(InterpException::PlaceHolder);
-                    }
-                }
+                return operations::unary(&unary.operator, right);
             }
 
             crate::ast::expr_types::ExprPossibilities::Scope(scope) => {
@@ -442,147 +302,106 @@ impl Interperable<Result<Primitive, InterpException>> for Interpreter {
                         for var in scope.inner.iter() {
                             ret_vec.push(self.evaluate(var)?);
                         }
-                        return Ok(Primitive::List(ret_vec));
+                        return Ok(Primitive::List(Gc::new(ret_vec)));
                     }
-                    TokenType::FUNC => unsafe {
-                        let close_ident = scope.ident.clone().unwrap_unchecked().clone();
-                        if let Ok(Primitive::Func(func)) =
-                            self.globals.retrieve(&close_ident.lexeme)
-                        {
-                            let mut func = func.clone();
-                            let args = scope.params.clone().unwrap_unchecked();
-                            let arg_len = args.len();
-                            if let None = func.func_map.get(&arg_len) {
-                                func.func_map
-                                    .insert(arg_len.clone(), (args, Box::new(scope)));
-                            } else {
-                                return Err(InterpException::PlaceHolder);
-                            }
-                        } else {
-                            let mut func_map = HashMap::new();
-                            let params = scope.params.clone().unwrap_unchecked();
-                            func_map.insert(params.len(), (params, Box::new(scope)));
-                            self.globals
-                                .define(&close_ident.lexeme, Primitive::Func(Func { func_map }));
-                        }
+                    TokenType::FUNC => {
+                        operations::define_func(&mut self.globals, scope.clone())?;
                         return Ok(Primitive::None);
-                    },
+                    }
                     TokenType::CLOS => unsafe {
-                        let clos_ident = scope.ident.unwrap_unchecked().lexeme;
+                        let clos_ident = &scope.ident.as_ref().unwrap_unchecked().lexeme;
 
-                        let mut clos_data: HashMap<String, Primitive> = HashMap::new();
-                        for var in scope.inner {
+                        let mut clos_data = Environment::new();
+                        for var in scope.inner.iter() {
                             if let ExprPossibilities::Stmt(var) = var {
-                                let var_ident = var.ident.unwrap_unchecked().lexeme;
-                                let val = self.evaluate(&var.inner.unwrap_unchecked())?;
-                                clos_data.insert(var_ident, val);
+                                let var_ident = &var.ident.as_ref().unwrap_unchecked().lexeme;
+                                let val = self.evaluate(var.inner.as_deref().unwrap_unchecked())?;
+                                if let TokenType::CONST = var.stmt {
+                                    clos_data.define_const(&var_ident, val);
+                                } else {
+                                    clos_data.define(&var_ident, val);
+                                }
+                            }
+                        }
+                        for method in scope.inner.iter() {
+                            if let ExprPossibilities::Scope(method) = method {
+                                operations::define_func(&mut clos_data, method.clone())?;
                             }
                         }
-                        self.globals.define_env(&clos_ident, clos_data);
+                        self.globals.define_env(clos_ident, clos_data);
 
                         return Ok(Primitive::None);
                     },
                     TokenType::CLOSCALL => {
-                        unsafe {
-                            // println!("{:?}", scope);
-                            let clos_ident = scope.ident.unwrap_unchecked().lexeme;
-                            let data = self.globals.retrieve(&clos_ident)?;
-                            if let Primitive::Env(env) = data {
-                                let mut env = env;
-                                env.enclosing = Some(Box::new(self.globals.clone()));
-                                self.globals = env.clone();
-                                for line in scope.inner.iter() {
-                                    let prim = self.evaluate(line)?;
-                                    if Primitive::None != prim {
-                                        return Ok(prim);
-                                    }
-                                }
-
-                                for key in env.clone().vars.keys() {
-                                    let value = self.globals.retrieve(key)?;
-                                    if let Primitive::Env(env2) = value {
-                                        env.define_env(key, env2.vars);
-                                    } else {
-                                        env.define(key, value);
-                                    }
-                                }
-
-                                self.globals = *self.globals.enclosing.clone().unwrap_unchecked();
-
-                                self.globals.define_env(&clos_ident, env.vars);
-
-                                return Ok(Primitive::None);
-                            } else {
-                                return Err(InterpException::PlaceHolder);
-                            }
+                        let clos_ident = &scope.ident.as_ref().unwrap().lexeme;
+                        let Primitive::Env(env) = self.globals.retrieve(clos_ident)? else {
+                            return Err(InterpException::PlaceHolder);
+                        };
+                        operations::enter_env(&mut self.globals, &env);
+                        if let Some(prim) = self.execute_block(&scope.inner)? {
+                            return Ok(prim);
                         }
+
+                        operations::leave_env(&mut self.globals);
+                        return Ok(Primitive::None);
                     }
                     TokenType::IF => {
-                        let _ = self.enclose();
-                        for line in scope.inner.iter() {
-                            let prim = self.evaluate(&line)?;
-                            if prim != Primitive::None {
-                                return Ok(prim);
-                            }
+                        self.enclose();
+                        if let Some(prim) = self.execute_block(&scope.inner)? {
+                            return Ok(prim);
                         }
 
-                        unsafe {
-                            self.globals = *self.globals.enclosing.clone().unwrap_unchecked();
-                            return Ok(Primitive::None);
-                        }
+                        self.globals.unwind(1);
+                        return Ok(Primitive::None);
                     }
 
                     TokenType::WHILE => {
-                        let _ = self.enclose();
+                        self.enclose();
                         unsafe {
                             while let Primitive::Bool(true) =
-                                self.evaluate(&scope.clone().condition.unwrap_unchecked())?
+                                self.evaluate(scope.condition.as_deref().unwrap_unchecked())?
                             {
-                                for line in scope.inner.iter() {
-                                    let prim = self.evaluate(line)?;
-                                    if Primitive::None != prim {
-                                        return Ok(prim);
-                                    }
-                                }
-                            }
-
-                            let mut enc = self.globals.enclosing.clone().unwrap_unchecked();
-
-                            for key in enc.clone().vars.keys() {
-                                let value = self.globals.retrieve(key).unwrap_unchecked();
-                                if let Primitive::Env(env) = value {
-                                    enc.define_env(key, env.vars);
-                                } else {
-                                    enc.define(key, value);
+                                if let Some(prim) = self.execute_block(&scope.inner)? {
+                                    return Ok(prim);
                                 }
                             }
-
-                            self.globals = *enc;
                         }
 
+                        operations::leave_scope(&mut self.globals);
                         return Ok(Primitive::None);
                     }
                     TokenType::FOR => {
-                        let _ = self.enclose();
+                        self.enclose();
                         unsafe {
-                            let cond = *scope.condition.unwrap_unchecked().clone();
+                            let cond = scope.condition.as_deref().unwrap_unchecked();
                             if let ExprPossibilities::Grouping(group) = cond {
                                 self.evaluate(&group.expr[0])?;
                                 while let Primitive::Bool(true) = self.evaluate(&group.expr[1])? {
-                                    for line in scope.inner.iter() {
-                                        let prim = self.evaluate(line)?;
-                                        if Primitive::None != prim {
-                                            return Ok(prim);
-                                        }
+                                    if let Some(prim) = self.execute_block(&scope.inner)? {
+                                        return Ok(prim);
                                     }
 
                                     self.evaluate(&group.expr[2])?;
                                 }
                             }
+                        }
 
-                            self.globals = *self.globals.clone().enclosing.unwrap_unchecked();
+                        self.globals.unwind(1);
+                        return Ok(Primitive::None);
+                    }
+                    TokenType::IN => {
+                        let ident = scope.ident.as_ref().unwrap();
+                        let items = self.evaluate(scope.condition.as_deref().unwrap())?;
+                        let items = operations::iterate(ident, items)?;
+                        self.enclose();
+                        for item in items {
+                            self.globals.define(&ident.lexeme, item);
+                            if let Some(prim) = self.execute_block(&scope.inner)? {
+                                return Ok(prim);
+                            }
                         }
 
+                        self.globals.unwind(1);
                         return Ok(Primitive::None);
                     }
                     _ => return Err(InterpException::PlaceHolder),
@@ -593,37 +412,244 @@ impl Interperable<Result<Primitive, InterpException>> for Interpreter {
 }
 
 impl Interpreter {
-    unsafe fn define_list(&mut self, stmt: &crate::ast::expr_types::Stmt, list: Vec<Primitive>) {
-        let mut vars = HashMap::new();
-        let ident = stmt.ident.clone().unwrap_unchecked().lexeme;
-        vars.insert(String::from("list"), Primitive::String(ident.clone()));
-        vars.insert(String::from(ident), Primitive::List(list));
-        vars.insert(
-            String::from("set"),
-            Primitive::NativeFunc(LibFunctions::Set),
-        );
-        vars.insert(
-            String::from("len"),
-            Primitive::NativeFunc(LibFunctions::Len),
-        );
-        vars.insert(
-            String::from("append"),
-            Primitive::NativeFunc(LibFunctions::Append),
-        );
-        vars.insert(
-            String::from("slice"),
-            Primitive::NativeFunc(LibFunctions::Slice),
-        );
-        self.globals
-            .define_env(&stmt.ident.clone().unwrap_unchecked().lexeme, vars);
+    fn lookup(&self, stmt: &Stmt) -> Result<Primitive, InterpException> {
+        let ident = &stmt.ident.as_ref().unwrap().lexeme;
+        match stmt.depth {
+            Some(depth) => return self.globals.retrieve_at(depth, stmt.slot, ident),
+            None => return self.globals.retrieve(ident),
+        }
+    }
+
+    // Evaluates call arguments in the caller's scope, keeping keyword arguments apart
+    fn evaluate_args(
+        &mut self,
+        args: &[ExprPossibilities],
+    ) -> Result<(Vec<Primitive>, Vec<(Token, Primitive)>), InterpException> {
+        let mut positional = Vec::new();
+        let mut keywords = Vec::new();
+        for arg in args.iter() {
+            if let ExprPossibilities::Stmt(kwarg) = arg && let TokenType::EQUAL = kwarg.stmt {
+                let value = self.evaluate(kwarg.inner.as_ref().unwrap())?;
+                keywords.push((kwarg.ident.clone().unwrap(), value));
+            } else {
+                positional.push(self.evaluate(arg)?);
+            }
+        }
+        return Ok((positional, keywords));
+    }
+
+    fn call(
+        &mut self,
+        func: Func,
+        ident: Token,
+        positional: Vec<Primitive>,
+        keywords: Vec<(Token, Primitive)>,
+    ) -> Result<Primitive, InterpException> {
+        let position = operations::stack_position();
+        if self.depth == 0 {
+            self.stack_base = position;
+        }
+        if self.depth >= self.max_depth || self.stack_base.abs_diff(position) > self.max_stack {
+            return Err(InterpException::StackOverflow(ident.lexeme, ident.line, self.depth));
+        }
+        self.depth += 1;
+        let result = self.run_call(func, ident, positional, keywords);
+        self.depth -= 1;
+        return result;
+    }
+
+    // Tail calls loop here instead of recursing, so they run in constant stack space
+    fn run_call(
+        &mut self,
+        mut func: Func,
+        mut ident: Token,
+        mut positional: Vec<Primitive>,
+        mut keywords: Vec<(Token, Primitive)>,
+    ) -> Result<Primitive, InterpException> {
+        let base = self.globals.depth();
+        let mut frames = TailFrames::new();
+        // Depth of the scope of the function that made the tail call being run
+        let mut caller = None;
+        loop {
+            let arg_count = positional.len() + keywords.len();
+            let Some(overload) = func.resolve_index(arg_count) else {
+                return Err(InterpException::NoMatchingOverload(ident.lexeme, arg_count, ident.line));
+            };
+            let code = func.overloads[overload].clone();
+            let params = code.params.as_deref().unwrap_or_default();
+            let bound = Func::bind_args(&ident, params, positional, keywords)?;
+            if let Some(caller) = caller {
+                frames.enter(&mut self.globals, caller, params);
+            }
+
+            // Parameters go straight into the entered scope, so a default sees the ones before it
+            self.enclose();
+            let scope = self.globals.depth();
+            for (param, prim) in params.iter().zip(bound) {
+                let prim = match prim {
+                    Some(prim) => prim,
+                    None => self.evaluate(param.default.as_deref().unwrap())?,
+                };
+                self.globals.define(&param.ident.lexeme, prim);
+            }
+
+            let Some(prim) = self.execute_block(&code.inner)? else {
+                operations::leave_scope(&mut self.globals);
+                frames.leave(&mut self.globals);
+                return Ok(Primitive::None);
+            };
+            let Some(tail_call) = self.tail_call.take() else {
+                // Returning a value leaves the function's scope, and any block it returned from
+                let depth = self.globals.depth();
+                self.globals.unwind(depth - base);
+                return Ok(prim);
+            };
+            caller = Some(scope);
+            func = tail_call.func;
+            ident = tail_call.ident;
+            positional = tail_call.positional;
+            keywords = tail_call.keywords;
+        }
+    }
+
+    // Runs statements until one evaluates to a value or leaves a tail call waiting,
+    // either of which ends the block along with the function it's in
+    fn execute_block(
+        &mut self,
+        inner: &[ExprPossibilities],
+    ) -> Result<Option<Primitive>, InterpException> {
+        for line in inner.iter() {
+            let prim = self.evaluate(line)?;
+            if Primitive::None != prim || self.tail_call.is_some() {
+                return Ok(Some(prim));
+            }
+        }
+        return Ok(None);
+    }
+
+    fn enclose(&mut self) {
+        // Moves the current scope in rather than copying the whole chain
+        let enclosing = mem::replace(&mut self.globals, Environment::new());
+        self.globals.enclosing = Some(Box::new(enclosing));
     }
 }
 
-impl Interpreter {
-    fn enclose(&mut self) -> Environment {
-        let mut env: Environment = Environment::new();
-        env.enclosing = Some(Box::new(self.globals.clone()));
-        self.globals = env.clone();
-        return env;
+impl Runtime for Interpreter {
+    fn globals(&mut self) -> &mut Environment {
+        return &mut self.globals;
+    }
+
+    fn permissions(&self) -> &Permissions {
+        return &self.permissions;
+    }
+
+    fn script_args(&self) -> &[String] {
+        return &self.script_args;
+    }
+
+    fn stdin(&mut self) -> &mut dyn BufRead {
+        return &mut *self.stdin;
+    }
+
+    fn sandbox(&mut self) -> Option<&mut Sandbox> {
+        return self.sandbox.as_mut();
+    }
+
+    fn rng(&mut self) -> &mut Rng {
+        return &mut self.rng;
+    }
+
+    fn invoke(
+        &mut self,
+        name: &str,
+        callee: Primitive,
+        args: Vec<Primitive>,
+    ) -> Result<Primitive, InterpException> {
+        let depth = self.globals.depth();
+        let result = self.call_value(name, callee, args);
+        let left_behind = self.globals.depth() - depth;
+        self.globals.unwind(left_behind);
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::testing::run_both;
+
+    #[test]
+    fn tail_call_sees_the_callers_variables() {
+        let source = "
+            func inner() { return a; }
+            func outer(a) { return inner(); }
+            println(outer(7));
+        ";
+        assert_eq!(run_both(source), "7\n");
+    }
+
+    #[test]
+    fn tail_call_returning_nothing_continues_the_caller() {
+        let source = "
+            func quiet() { println(\"quiet\"); }
+            func outer() {
+                return quiet();
+                println(\"after\");
+            }
+            outer();
+        ";
+        assert_eq!(run_both(source), "quiet\nafter\n");
+    }
+
+    #[test]
+    fn tail_call_writes_back_when_it_runs_to_its_end() {
+        let source = "
+            let total = 0;
+            func add(n) { total = total + n; }
+            func twice(n) {
+                let total = total + n;
+                return add(n);
+            }
+            twice(4);
+            println(total);
+        ";
+        assert_eq!(run_both(source), "8\n");
+    }
+
+    #[test]
+    fn env_block_shares_its_fields_with_other_handles() {
+        let source = "
+            decenv e = {
+                let x = 1;
+            }
+            func bump(target) {
+                env target { x = x + 10; }
+            }
+            func early() {
+                env e {
+                    x = x + 100;
+                    return x;
+                }
+            }
+            env e {
+                bump(e);
+                x = x + 1;
+            }
+            env e { println(x); }
+            early();
+            env e { println(x); }
+        ";
+        assert_eq!(run_both(source), "12\n112\n");
+    }
+
+    #[test]
+    fn deep_tail_recursion_runs_in_constant_space() {
+        let source = "
+            func count(n, acc) {
+                if n == 0 { return acc; }
+                return count(n - 1, acc + 1);
+            }
+            println(count(100000, 0));
+        ";
+        assert_eq!(run_both(source), "100000\n");
     }
 }
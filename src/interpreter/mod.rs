@@ -1,2 +1,7 @@
+pub mod display;
 pub mod environment;
 pub mod interpreter;
+pub mod operations;
+pub mod output;
+pub mod permissions;
+pub mod sandbox;
@@ -0,0 +1,365 @@
+use std::{io::Write, mem, rc::Rc};
+
+use crate::{
+    ast::expr_types::{Param, Scope},
+    error_reporting::interp_err::InterpException,
+    heap::heap::Gc,
+    lib_functions::{items_of, list_env},
+    scanner::token::{Func, Primitive, Token, TokenType},
+};
+
+use super::environment::{Environment, Vars};
+
+// Value semantics shared by the tree-walking interpreter and the bytecode vm,
+// so both engines agree on every operator, truth test and print format
+
+fn convert_bool(b: bool) -> isize {
+    if b {
+        return 1;
+    } else {
+        return 0;
+    }
+}
+
+pub fn binary(
+    operator: &Token,
+    left: Primitive,
+    right: Primitive,
+) -> Result<Primitive, InterpException> {
+    match operator.tok {
+        TokenType::AND => {
+            if let Primitive::Bool(bool1) = left && let Primitive::Bool(bool2) = right {
+                return Ok(Primitive::Bool(bool1 && bool2));
+            } else {
+                return Err(InterpException::PlaceHolder);
+            }
+        },
+        TokenType::OR => {
+            if let Primitive::Bool(bool1) = left && let Primitive::Bool(bool2) = right {
+                return Ok(Primitive::Bool(bool1 || bool2));
+            } else {
+                return Err(InterpException::PlaceHolder);
+            }
+        }
+        TokenType::SLASH => {
+            if let Primitive::Int(num1) = left && let Primitive::Int(num2) = right {
+                if num2 == 0 {
+                    return Err(InterpException::DivideByZero(operator.clone()));
+                }
+                return Ok(Primitive::Int(num1.wrapping_div(num2)));
+            } else if let Primitive::Float(num1) = left && let Primitive::Float(num2) = right {
+                if num2 == 0.0 {
+                    return Err(InterpException::DivideByZero(operator.clone()));
+                }
+                return Ok(Primitive::Float(num1 / num2));
+            } else {
+                return Err(InterpException::InvalidBinary(operator.clone()))
+            }
+        },
+        TokenType::STAR => {
+            if let Primitive::Int(num1) = left {
+                match right {
+                    Primitive::Float(num2) => return Ok(Primitive::Float(num1 as f32 * num2)),
+                    Primitive::Int(num2) => return Ok(Primitive::Int(num1 * num2)),
+                    Primitive::String(_strng) => return Err(InterpException::InvalidBinary(operator.clone())),
+                    Primitive::Bool(boolean) => return Ok(Primitive::Int(num1 * convert_bool(boolean))),
+                    _ => return Err(InterpException::InvalidBinary(operator.clone())),
+                }
+            } else if let Primitive::Float(num1) = left && let Primitive::Float(_) = right {
+                match right {
+                    Primitive::Float(num2) => return Ok(Primitive::Float(num1 * num2)),
+                    Primitive::Int(num2) => return Ok(Primitive::Float(num1 * num2 as f32)),
+                    Primitive::String(_strng) => return Err(InterpException::InvalidBinary(operator.clone())),
+                    Primitive::Bool(boolean) => return Ok(Primitive::Float(num1 * convert_bool(boolean) as f32)),
+                    _ => return Err(InterpException::InvalidBinary(operator.clone())),
+                }
+            } else {
+                return Err(InterpException::InvalidBinary(operator.clone()))
+            }
+        },
+        TokenType::MINUS => {
+            if let Primitive::Int(num1) = left && let Primitive::Int(num2) = right {
+                return Ok(Primitive::Int(num1 - num2));
+            } else if let Primitive::Float(num1) = left && let Primitive::Float(num2) = right {
+                return Ok(Primitive::Float(num1 - num2));
+            } else {
+                return Err(InterpException::InvalidBinary(operator.clone()))
+            }
+        },
+        TokenType::MODULO => {
+            if let Primitive::Int(num1) = left && let Primitive::Int(num2) = right {
+                if num2 == 0 {
+                    return Err(InterpException::DivideByZero(operator.clone()));
+                }
+                return Ok(Primitive::Int(num1.wrapping_rem(num2)));
+            } else if let Primitive::Float(num1) = left && let Primitive::Float(num2) = right {
+                if num2 == 0.0 {
+                    return Err(InterpException::DivideByZero(operator.clone()));
+                }
+                return Ok(Primitive::Float(num1 % num2));
+            } else {
+                return Err(InterpException::InvalidBinary(operator.clone()))
+            }
+        },
+        TokenType::PLUS => {
+            if let Primitive::Int(num1) = left {
+                match right {
+                    Primitive::Float(num2) => return Ok(Primitive::Float(num1 as f32 + num2)),
+                    Primitive::Int(num2) => return Ok(Primitive::Int(num1 + num2)),
+                    Primitive::String(strng) => return Ok(Primitive::String(num1.to_string() + &strng)),
+                    Primitive::Bool(boolean) => return Ok(Primitive::Int(num1 + convert_bool(boolean))),
+                    _ => return Err(InterpException::InvalidBinary(operator.clone())),
+                }
+            } else if let Primitive::Float(num1) = left {
+                match right {
+                    Primitive::Float(num2) => return Ok(Primitive::Float(num1 + num2)),
+                    Primitive::Int(num2) => return Ok(Primitive::Float(num1 + num2 as f32)),
+                    Primitive::String(strng) => return Ok(Primitive::String(num1.to_string() + &strng)),
+                    Primitive::Bool(boolean) => return Ok(Primitive::Float(num1 + convert_bool(boolean) as f32)),
+                    _ => return Err(InterpException::InvalidBinary(operator.clone())),
+                }
+            } else if let Primitive::String(str1) = left {
+                match right {
+                    Primitive::Float(flt) => return Ok(Primitive::String(str1 + &flt.to_string())),
+                    Primitive::Int(int) => return Ok(Primitive::String(str1 + &int.to_string())),
+                    Primitive::String(str2) => return Ok(Primitive::String(str1 + str2.as_str())),
+                    Primitive::Bool(boolean) => return Ok(Primitive::String(str1 + &boolean.to_string())),
+                    _ => return Err(InterpException::InvalidBinary(operator.clone())),
+                }
+            } else {
+                return Err(InterpException::InvalidBinary(operator.clone()))
+            }
+        },
+        TokenType::EQUAL_EQUAL => {
+            return Ok(Primitive::Bool(right == left));
+        },
+        TokenType::BANG_EQUAL => {
+            return Ok(Primitive::Bool(right != left))
+        },
+        TokenType::GREATER | TokenType::GREATER_EQUAL | TokenType::LESS_EQUAL | TokenType::LESS => {
+            let Some(ord) = left.compare(&right) else {
+                return Err(InterpException::InvalidComparison(operator.clone(), left.type_name(), right.type_name()));
+            };
+            match operator.tok {
+                TokenType::GREATER => return Ok(Primitive::Bool(ord.is_gt())),
+                TokenType::GREATER_EQUAL => return Ok(Primitive::Bool(ord.is_ge())),
+                TokenType::LESS_EQUAL => return Ok(Primitive::Bool(ord.is_le())),
+                _ => return Ok(Primitive::Bool(ord.is_lt())),
+            }
+        }
+
+        _ => return Err(InterpException::InvalidBinary(operator.clone()))
+    }
+}
+
+pub fn unary(operator: &Token, right: Primitive) -> Result<Primitive, InterpException> {
+    match operator.tok {
+        TokenType::MINUS | TokenType::BANG => match right {
+            Primitive::Float(float) => return Ok(Primitive::Float(-float)),
+            Primitive::Int(int) => return Ok(Primitive::Int(-int)),
+            Primitive::String(_string) => {
+                return Err(InterpException::InvalidUnary(operator.clone()))
+            }
+            Primitive::Bool(boolean) => return Ok(Primitive::Bool(!boolean)),
+            Primitive::None => return Ok(Primitive::Bool(true)),
+            _ => return Err(InterpException::InvalidUnary(operator.clone())),
+        },
+        _ => {
+            return Err(InterpException::PlaceHolder);
+        }
+    }
+}
+
+// Truthiness of an if condition. Loops only continue on a literal true.
+pub fn truthy(prim: Primitive) -> Result<bool, InterpException> {
+    match prim {
+        Primitive::Float(flt) => return Ok(flt != 0.0),
+        Primitive::Int(num) => return Ok(num != 0),
+        Primitive::String(string) => return Ok(string.len() != 0),
+        Primitive::Bool(bool_val) => return Ok(bool_val),
+        Primitive::None => return Ok(false),
+        _ => return Err(InterpException::PlaceHolder),
+    }
+}
+
+// Values are turned into text first, see `display::to_string`
+pub fn print(out: &mut dyn Write, output: &str, newline: bool) -> Result<(), InterpException> {
+    let written = if newline {
+        writeln!(out, "{}", output)
+    } else {
+        write!(out, "{}", output)
+    };
+    return written.map_err(|err| InterpException::OutputFailed(err.to_string()));
+}
+
+pub fn iterate(ident: &Token, items: Primitive) -> Result<Vec<Primitive>, InterpException> {
+    return items_of(&items).ok_or(InterpException::NotIterable(items.type_name(), ident.line));
+}
+
+// Roughly where the native stack is, to measure how much nested calls take up of it
+#[inline(never)]
+pub fn stack_position() -> usize {
+    let marker = 0u8;
+    return std::hint::black_box(&marker) as *const u8 as usize;
+}
+
+// Leaves a block or function that ran to its end, copying the variables it shares with the
+// scope it was entered from back into that scope
+pub fn leave_scope(globals: &mut Environment) {
+    let mut enclosing = globals.enclosing.take().unwrap();
+    match &enclosing.shared {
+        Some(env) => copy_back(&globals.vars, &mut env.borrow_mut().vars),
+        None => copy_back(&globals.vars, &mut enclosing.vars),
+    }
+    *globals = *enclosing;
+}
+
+fn copy_back(scope: &Vars, enclosing: &mut Vars) {
+    for (name, value) in enclosing.iter_mut() {
+        if let Some(changed) = scope.get(name) {
+            *value = changed.clone();
+        }
+    }
+}
+
+// Enters an env block. It reaches the env's fields through the env itself, so it sees what
+// anything else does to them while it runs and the other way around, and what the block
+// declares goes in a scope of its own above them.
+pub fn enter_env(globals: &mut Environment, env: &Gc<Environment>) {
+    push_scope(globals, Environment::shared(env));
+    push_scope(globals, Environment::new());
+}
+
+// Leaves an env block that ran to its end
+pub fn leave_env(globals: &mut Environment) {
+    leave_scope(globals);
+    globals.unwind(1);
+}
+
+fn push_scope(globals: &mut Environment, mut scope: Environment) {
+    scope.enclosing = Some(Box::new(mem::replace(globals, Environment::new())));
+    *globals = scope;
+}
+
+// Functions waiting on the tail call that took their place, by the depth of their scope.
+// A value returned by the last one called is theirs too, and when it runs to its end
+// instead, each of them runs to its own end after it.
+#[derive(Default)]
+pub struct TailFrames {
+    frames: Vec<usize>,
+    // Depth of the scope carrying what dropped frames would write back, while it's the last frame
+    summary: Option<usize>,
+}
+
+impl TailFrames {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    // Makes way for a tail call from the function whose scope is at `frame`. Names are looked up
+    // dynamically, so its scopes are only dropped when the callee's parameters hide every name
+    // in them, keeping what leaving them at the end would write back. Otherwise the callee runs
+    // on top of them, the same as any other call.
+    pub fn enter(&mut self, globals: &mut Environment, frame: usize, params: &[Param]) {
+        let depth = globals.depth();
+        let hidden = (0..=depth - frame).all(|up| {
+            let scope = globals.ancestor(up).unwrap();
+            return scope
+                .vars
+                .keys()
+                .all(|name| params.iter().any(|param| &param.ident.lexeme == name));
+        });
+        if !hidden {
+            self.frames.push(frame);
+            return;
+        }
+
+        let scope = globals.ancestor(depth - frame).unwrap();
+        let below = globals.ancestor(depth - frame + 1).unwrap();
+        let carried: Vec<(String, Primitive)> = scope
+            .vars
+            .iter()
+            .filter(|(name, _)| below.vars.contains_key(*name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let in_block = depth > frame;
+        globals.unwind(depth - frame + 1);
+
+        if !carried.is_empty() {
+            if self.summary == Some(frame - 1) && carried.len() == globals.vars.len() {
+                for (name, value) in carried {
+                    globals.define(&name, value);
+                }
+            } else {
+                self.frames.push(frame);
+                self.summary = Some(frame);
+                push_scope(globals, Environment::with_vars(carried.into_iter().collect()));
+            }
+            // The callee writes back into it, as it would have into the function it replaced
+            if !in_block {
+                return;
+            }
+        }
+        // A block the call was made from would have taken what the callee writes back instead
+        if !globals.vars.is_empty() {
+            self.frames.push(globals.depth() + 1);
+            push_scope(globals, Environment::new());
+        }
+    }
+
+    // The function called last ran to its end, so the ones waiting on it do too
+    pub fn leave(&mut self, globals: &mut Environment) {
+        while let Some(frame) = self.frames.pop() {
+            let depth = globals.depth();
+            globals.unwind(depth - frame);
+            leave_scope(globals);
+        }
+    }
+}
+
+// Lists are declared as an env wrapping the list, so they can carry their methods
+pub fn declare(
+    globals: &mut Environment,
+    ident: String,
+    value: Primitive,
+    is_const: bool,
+) -> Result<(), InterpException> {
+    if let Primitive::List(list) = value {
+        let env = list_env(&ident, list);
+        globals.declare(&ident, Primitive::Env(Gc::new(env)))?;
+    } else {
+        globals.declare(&ident, value)?;
+    }
+    if is_const {
+        globals.constants.insert(ident);
+    }
+    return Ok(());
+}
+
+// Defining a function under a name that already holds one adds an overload to it
+pub fn define_func(globals: &mut Environment, scope: Rc<Scope>) -> Result<(), InterpException> {
+    let ident = scope.ident.clone().unwrap().lexeme;
+    if globals.is_constant(&ident) {
+        return Err(InterpException::AssignToConstant(ident));
+    }
+    if let Ok(Primitive::Func(mut func)) = globals.retrieve(&ident) {
+        func.add_overload(scope)?;
+        globals.redefine(&ident, Primitive::Func(func))?;
+    } else {
+        globals.define(&ident, Primitive::Func(Func::new(scope)));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::testing::run_both;
+
+    #[test]
+    fn dividing_by_zero_is_an_error() {
+        for source in ["println(0 / 0);", "println(1 / 0);", "println(1 % 0);", "println(1.0 / 0.0);"] {
+            assert_eq!(run_both(source), "Division by zero on line 1\n");
+        }
+        assert_eq!(run_both("println(0.0 / 2.0); println(7 / 2);"), "0\n3\n");
+    }
+}
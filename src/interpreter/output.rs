@@ -0,0 +1,35 @@
+use std::{cell::RefCell, io, rc::Rc};
+
+// An in-memory sink for what scripts print. Clones share one buffer, so the host keeps
+// a handle while the interpreter writes through another.
+#[derive(Clone, Default)]
+pub struct Capture {
+    buff: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn contents(&self) -> String {
+        return String::from_utf8_lossy(&self.buff.borrow()).into_owned();
+    }
+
+    // Returns what was written so far and empties the buffer
+    pub fn take(&self) -> String {
+        let buff = std::mem::take(&mut *self.buff.borrow_mut());
+        return String::from_utf8_lossy(&buff).into_owned();
+    }
+}
+
+impl io::Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buff.borrow_mut().extend_from_slice(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
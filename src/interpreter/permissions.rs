@@ -0,0 +1,200 @@
+use std::{
+    fmt, fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::error_reporting::interp_err::InterpException;
+
+// Things outside of the interpreter a native function may need to touch
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    Read,
+    Write,
+    Env,
+    // Reserved for running other programs. Ending the script itself needs no permission.
+    Process,
+}
+
+impl Capability {
+    pub fn flag(&self) -> &'static str {
+        match self {
+            Capability::Read => "--allow-read",
+            Capability::Write => "--allow-write",
+            Capability::Env => "--allow-env",
+            Capability::Process => "--allow-process",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Capability::Read => "read",
+            Capability::Write => "write",
+            Capability::Env => "env",
+            Capability::Process => "process",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Grant {
+    #[default]
+    Denied,
+    All,
+    // Paths for read and write, under which everything is allowed, or variable names for env
+    Only(Vec<String>),
+}
+
+// What a script may do besides computing. Nothing is allowed unless it's granted.
+#[derive(Clone, Debug, Default)]
+pub struct Permissions {
+    pub read: Grant,
+    pub write: Grant,
+    pub env: Grant,
+    pub process: Grant,
+}
+
+impl Permissions {
+    pub fn none() -> Self {
+        return Self::default();
+    }
+
+    pub fn all() -> Self {
+        return Self {
+            read: Grant::All,
+            write: Grant::All,
+            env: Grant::All,
+            process: Grant::All,
+        };
+    }
+
+    pub fn allow(&mut self, capability: Capability) {
+        *self.grant_mut(capability) = Grant::All;
+    }
+
+    // Adds to what's allowed, unless everything already is
+    pub fn allow_only(&mut self, capability: Capability, targets: Vec<String>) {
+        let grant = self.grant_mut(capability);
+        match grant {
+            Grant::All => (),
+            Grant::Only(allowed) => allowed.extend(targets),
+            Grant::Denied => *grant = Grant::Only(targets),
+        }
+    }
+
+    // Reads a command line flag like --allow-read or --allow-read=data,out. Returns
+    // false for anything else, and an error for a flag with an empty target, which
+    // would otherwise allow the whole working directory.
+    pub fn parse_flag(&mut self, arg: &str) -> Result<bool, String> {
+        if arg == "--allow-all" {
+            *self = Self::all();
+            return Ok(true);
+        }
+        for capability in [
+            Capability::Read,
+            Capability::Write,
+            Capability::Env,
+            Capability::Process,
+        ] {
+            let Some(rest) = arg.strip_prefix(capability.flag()) else {
+                continue;
+            };
+            if rest.is_empty() {
+                self.allow(capability);
+                return Ok(true);
+            }
+            if let Some(targets) = rest.strip_prefix('=') {
+                let targets: Vec<String> = targets.split(',').map(str::to_string).collect();
+                if targets.iter().any(String::is_empty) {
+                    return Err(format!(
+                        "{} expects a comma separated list without empty entries, got '{}'",
+                        capability.flag(),
+                        arg
+                    ));
+                }
+                self.allow_only(capability, targets);
+                return Ok(true);
+            }
+        }
+        return Ok(false);
+    }
+
+    pub fn check(&self, capability: Capability, target: &str) -> Result<(), InterpException> {
+        let allowed = match self.grant(capability) {
+            Grant::Denied => false,
+            Grant::All => true,
+            Grant::Only(allowed) => match capability {
+                Capability::Read | Capability::Write => match resolve(target) {
+                    Some(target) => allowed
+                        .iter()
+                        .filter(|path| !path.is_empty())
+                        .filter_map(|path| resolve(path))
+                        .any(|path| target.starts_with(path)),
+                    None => false,
+                },
+                _ => allowed.iter().any(|name| name == target),
+            },
+        };
+        if allowed {
+            return Ok(());
+        }
+        return Err(InterpException::PermissionDenied(
+            capability,
+            target.to_string(),
+        ));
+    }
+
+    fn grant(&self, capability: Capability) -> &Grant {
+        match capability {
+            Capability::Read => &self.read,
+            Capability::Write => &self.write,
+            Capability::Env => &self.env,
+            Capability::Process => &self.process,
+        }
+    }
+
+    fn grant_mut(&mut self, capability: Capability) -> &mut Grant {
+        match capability {
+            Capability::Read => &mut self.read,
+            Capability::Write => &mut self.write,
+            Capability::Env => &mut self.env,
+            Capability::Process => &mut self.process,
+        }
+    }
+}
+
+// Where a path really leads: the longest part of it that exists is canonicalized, so a
+// symlink inside an allowed directory can't pass for it, and the rest, which doesn't
+// exist yet, has . and .. taken out. None for a dangling symlink, since writing to one
+// would create whatever it points at.
+fn resolve(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().unwrap_or_default().join(path)
+    };
+    let components: Vec<Component> = path.components().collect();
+    for existing in (0..=components.len()).rev() {
+        let prefix: PathBuf = components[..existing].iter().collect();
+        let Ok(mut resolved) = prefix.canonicalize() else {
+            if existing > 0 && fs::symlink_metadata(&prefix).is_ok() {
+                return None;
+            }
+            continue;
+        };
+        for component in &components[existing..] {
+            match component {
+                Component::CurDir => (),
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                component => resolved.push(component),
+            }
+        }
+        return Some(resolved);
+    }
+    return None;
+}
@@ -0,0 +1,129 @@
+use std::{
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error_reporting::interp_err::InterpException,
+    heap::heap::{self, Meter, Metering},
+    scanner::token::Primitive,
+};
+
+// The clock is only looked at every this many steps
+const CHECK_INTERVAL: u64 = 256;
+
+// What a script run in a sandbox may use. Anything left as None is unlimited.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    // Expressions evaluated by the interpreter, or instructions run by the vm
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    // Approximate bytes taken up by the envs, lists, sets and deques the script made
+    pub max_heap_bytes: Option<usize>,
+    // Values in a single env, list, set or deque, or characters in a string
+    pub max_collection_len: Option<usize>,
+    pub max_depth: Option<usize>,
+}
+
+// Stops a sandboxed script from another thread. A cancelled script stays cancelled,
+// however many times it's run again, until the host calls rearm.
+#[derive(Clone, Debug, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.cancelled.load(Ordering::Relaxed);
+    }
+
+    pub fn rearm(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+}
+
+// Keeps count of what a script has used against its limits
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    pub limits: Limits,
+    cancel: CancelHandle,
+    meter: Rc<Meter>,
+    steps: u64,
+    started: Instant,
+}
+
+impl Sandbox {
+    pub fn new(limits: Limits) -> Self {
+        return Self {
+            limits,
+            cancel: CancelHandle::default(),
+            meter: Rc::new(Meter::default()),
+            steps: 0,
+            started: Instant::now(),
+        };
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        return self.cancel.clone();
+    }
+
+    // Objects allocated while the script runs are counted against the sandbox
+    pub fn metering(&self) -> Metering {
+        return heap::metering(&self.meter);
+    }
+
+    // Starts the step and time budgets over. Objects the script still holds keep
+    // counting against the heap limit.
+    pub fn reset(&mut self) {
+        self.steps = 0;
+        self.started = Instant::now();
+        self.meter.reset_largest();
+    }
+
+    pub fn step(&mut self) -> Result<(), InterpException> {
+        self.steps += 1;
+        if let Some(max_steps) = self.limits.max_steps && self.steps > max_steps {
+            return Err(InterpException::StepLimitExceeded(max_steps));
+        }
+        if let Some(max_bytes) = self.limits.max_heap_bytes && self.meter.bytes() > max_bytes {
+            return Err(InterpException::HeapLimitExceeded(max_bytes));
+        }
+        if let Some(max_len) = self.limits.max_collection_len && self.meter.largest() > max_len {
+            return Err(InterpException::CollectionTooLarge(max_len));
+        }
+        if self.cancel.is_cancelled() {
+            return Err(InterpException::Cancelled);
+        }
+        if self.steps % CHECK_INTERVAL != 0 {
+            return Ok(());
+        }
+        return self.interrupted();
+    }
+
+    // Whether the host cancelled the script or its time ran out
+    pub fn interrupted(&self) -> Result<(), InterpException> {
+        if self.cancel.is_cancelled() {
+            return Err(InterpException::Cancelled);
+        }
+        if let Some(timeout) = self.limits.timeout && self.started.elapsed() > timeout {
+            return Err(InterpException::TimeLimitExceeded(timeout.as_millis()));
+        }
+        return Ok(());
+    }
+
+    // Strings aren't on the heap, and doubling one outgrows any limit between two checks
+    pub fn check_value(&self, value: &Primitive) -> Result<(), InterpException> {
+        if let Some(max_len) = self.limits.max_collection_len && let Primitive::String(string) = value && string.len() > max_len {
+            return Err(InterpException::CollectionTooLarge(max_len));
+        }
+        return Ok(());
+    }
+}
@@ -4,9 +4,16 @@
 #![feature(iterator_try_collect)]
 
 pub mod ast;
+pub mod engine;
 pub mod error_reporting;
+pub mod heap;
 pub mod import_sys;
 pub mod interpreter;
 pub mod lib_functions;
 pub mod parser;
+pub mod resolver;
 pub mod scanner;
+pub mod type_checker;
+pub mod vm;
+
+pub use engine::engine::Engine;
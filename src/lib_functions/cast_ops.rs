@@ -1,4 +1,9 @@
-use crate::scanner::token::Primitive;
+use crate::{
+    error_reporting::interp_err::InterpException, interpreter::display,
+    scanner::token::Primitive,
+};
+
+use super::runtime::Runtime;
 
 pub fn int(other: Primitive) -> Primitive {
     match other {
@@ -16,20 +21,62 @@ pub fn int(other: Primitive) -> Primitive {
         Primitive::Func(_) => return Primitive::None,
         Primitive::NativeFunc(_) => return Primitive::None,
         Primitive::List(_) => return Primitive::None,
+        Primitive::Set(_) => return Primitive::None,
+        Primitive::Deque(_) => return Primitive::None,
         Primitive::None => return Primitive::None,
     }
 }
 
-pub fn string(other: Primitive) -> Primitive {
+pub fn float(other: Primitive) -> Primitive {
     match other {
-        Primitive::Float(flt) => return Primitive::String(flt.to_string()),
-        Primitive::Int(int) => return Primitive::String(int.to_string()),
-        Primitive::String(_) => return other,
-        Primitive::Bool(boolean) => return Primitive::String(boolean.to_string()),
-        Primitive::Env(env) => return Primitive::String(format!("{:?}", env)),
+        Primitive::Float(_) => return other,
+        Primitive::Int(int) => return Primitive::Float(int as f32),
+        Primitive::String(string) => {
+            let parsed_res: Result<f32, _> = string.trim().parse();
+            match parsed_res {
+                Ok(val) => return Primitive::Float(val),
+                Err(_) => return Primitive::None,
+            }
+        }
+        Primitive::Bool(boolean) => return Primitive::Float(boolean as isize as f32),
+        Primitive::Env(_) => return Primitive::None,
         Primitive::Func(_) => return Primitive::None,
         Primitive::NativeFunc(_) => return Primitive::None,
-        Primitive::List(list) => return Primitive::String(format!("{:?}", list)),
-        Primitive::None => return Primitive::String("null".to_string()),
+        Primitive::List(_) => return Primitive::None,
+        Primitive::Set(_) => return Primitive::None,
+        Primitive::Deque(_) => return Primitive::None,
+        Primitive::None => return Primitive::None,
+    }
+}
+
+// Collections and envs become the text print would show for them
+pub fn string(other: Primitive, runtime: &mut dyn Runtime) -> Result<Primitive, InterpException> {
+    match other {
+        Primitive::Float(flt) => return Ok(Primitive::String(flt.to_string())),
+        Primitive::Int(int) => return Ok(Primitive::String(int.to_string())),
+        Primitive::String(_) => return Ok(other),
+        Primitive::Bool(boolean) => return Ok(Primitive::String(boolean.to_string())),
+        Primitive::Func(_) => return Ok(Primitive::None),
+        Primitive::NativeFunc(_) => return Ok(Primitive::None),
+        Primitive::Env(_) | Primitive::List(_) | Primitive::Set(_) | Primitive::Deque(_) => {
+            return Ok(Primitive::String(display::to_string(runtime, &other)?))
+        }
+        Primitive::None => return Ok(Primitive::String("null".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::testing::run_both;
+
+    #[test]
+    fn casts() {
+        let source = "
+            println(float(1));
+            println(float(\"2.5\"));
+            println(int(2.7));
+            println(int());
+        ";
+        assert_eq!(run_both(source), "1\n2.5\n2\n'int' takes 1 argument(s) but got 0\n");
     }
 }
@@ -0,0 +1,366 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, slice,
+};
+
+use crate::{
+    error_reporting::interp_err::InterpException, heap::heap::Gc,
+    interpreter::environment::Environment,
+    scanner::token::{exact_int, Primitive},
+};
+
+use super::{items_of, native::expect_args, CollectionsLibFunctions, LibFunctions};
+
+// What a set item is hashed as. Floats holding a whole number share the int's key,
+// since the two compare equal.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Key {
+    Int(isize),
+    Float(u32),
+    String(String),
+    Bool(bool),
+    Null,
+}
+
+impl Key {
+    fn of(value: &Primitive) -> Option<Self> {
+        match value {
+            Primitive::Int(int) => return Some(Key::Int(*int)),
+            Primitive::Float(flt) => match exact_int(*flt) {
+                Some(int) => return Some(Key::Int(int)),
+                None => return Some(Key::Float(flt.to_bits())),
+            },
+            Primitive::String(string) => return Some(Key::String(string.clone())),
+            Primitive::Bool(boolean) => return Some(Key::Bool(*boolean)),
+            Primitive::None => return Some(Key::Null),
+            _ => return None,
+        }
+    }
+}
+
+// Hash set that keeps its items in the order they were added, so printing and looping
+// over one always gives the same order. Only values that can't change are hashable.
+#[derive(Clone, Default)]
+pub struct Set {
+    items: Vec<Primitive>,
+    index: HashMap<Key, usize>,
+}
+
+impl Set {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.items.len();
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, Primitive> {
+        return self.items.iter();
+    }
+
+    pub fn contains(&self, value: &Primitive) -> bool {
+        return Key::of(value).is_some_and(|key| self.index.contains_key(&key));
+    }
+
+    // Returns whether the value wasn't in the set yet
+    pub fn insert(&mut self, name: &str, value: Primitive) -> Result<bool, InterpException> {
+        let Some(key) = Key::of(&value) else {
+            return Err(InterpException::InvalidValue(
+                name.to_string(),
+                format!(
+                    "sets can only hold ints, floats, strings, bools and null, not {}",
+                    value.type_name()
+                ),
+            ));
+        };
+        if self.index.contains_key(&key) {
+            return Ok(false);
+        }
+        self.index.insert(key, self.items.len());
+        self.items.push(value);
+        return Ok(true);
+    }
+
+    // Returns whether the value was in the set
+    pub fn remove(&mut self, value: &Primitive) -> bool {
+        let Some(idx) = Key::of(value).and_then(|key| self.index.remove(&key)) else {
+            return false;
+        };
+        self.items.remove(idx);
+        for position in self.index.values_mut() {
+            if *position > idx {
+                *position -= 1;
+            }
+        }
+        return true;
+    }
+}
+
+// Sets are equal when they hold the same items, whatever order they were added in
+impl PartialEq for Set {
+    fn eq(&self, other: &Self) -> bool {
+        return self.len() == other.len() && self.iter().all(|item| other.contains(item));
+    }
+}
+
+impl fmt::Debug for Set {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Deque {
+    pub items: VecDeque<Primitive>,
+}
+
+pub struct Collections;
+
+impl Collections {
+    pub fn new() -> Environment {
+        let mut ret_env = Environment::new();
+        for (name, func) in [
+            ("set", CollectionsLibFunctions::Set),
+            ("deque", CollectionsLibFunctions::Deque),
+            ("add", CollectionsLibFunctions::Add),
+            ("remove", CollectionsLibFunctions::Remove),
+            ("contains", CollectionsLibFunctions::Contains),
+            ("union", CollectionsLibFunctions::Union),
+            ("intersection", CollectionsLibFunctions::Intersection),
+            ("difference", CollectionsLibFunctions::Difference),
+            ("push_front", CollectionsLibFunctions::PushFront),
+            ("push_back", CollectionsLibFunctions::PushBack),
+            ("pop_front", CollectionsLibFunctions::PopFront),
+            ("pop_back", CollectionsLibFunctions::PopBack),
+            ("front", CollectionsLibFunctions::Front),
+            ("back", CollectionsLibFunctions::Back),
+            ("to_list", CollectionsLibFunctions::ToList),
+        ] {
+            ret_env.define(name, Primitive::NativeFunc(LibFunctions::Collections(func)));
+        }
+        return ret_env;
+    }
+
+    pub fn do_func(
+        func_name: CollectionsLibFunctions,
+        args: Vec<Primitive>,
+    ) -> Result<Primitive, InterpException> {
+        let name = func_name.name();
+        match func_name {
+            // Both take nothing, or a list, set or deque to start out with
+            CollectionsLibFunctions::Set => {
+                let mut set = Set::new();
+                for item in initial_items(name, &args)? {
+                    set.insert(name, item)?;
+                }
+                return Ok(Primitive::Set(Gc::new(set)));
+            }
+            CollectionsLibFunctions::Deque => {
+                let items = initial_items(name, &args)?;
+                return Ok(Primitive::Deque(Gc::new(Deque {
+                    items: items.into(),
+                })));
+            }
+            CollectionsLibFunctions::Add => {
+                expect_args(name, &args, 2)?;
+                let set = set_argument(name, &args, 0)?;
+                set.borrow_mut().insert(name, args[1].clone())?;
+            }
+            // Deques drop the first item equal to the value. Comparing may look into the deque
+            // itself, so it's only borrowed mutably once the item is found.
+            CollectionsLibFunctions::Remove => {
+                expect_args(name, &args, 2)?;
+                if let Primitive::Deque(deque) = &args[0] {
+                    let position = deque.borrow().items.iter().position(|item| *item == args[1]);
+                    if let Some(position) = position {
+                        deque.borrow_mut().items.remove(position);
+                    }
+                    return Ok(Primitive::Bool(position.is_some()));
+                }
+                let set = set_argument(name, &args, 0)?;
+                return Ok(Primitive::Bool(set.borrow_mut().remove(&args[1])));
+            }
+            CollectionsLibFunctions::Contains => {
+                expect_args(name, &args, 2)?;
+                if let Primitive::Deque(deque) = &args[0] {
+                    return Ok(Primitive::Bool(deque.borrow().items.contains(&args[1])));
+                }
+                let set = set_argument(name, &args, 0)?;
+                return Ok(Primitive::Bool(set.borrow().contains(&args[1])));
+            }
+            CollectionsLibFunctions::Union
+            | CollectionsLibFunctions::Intersection
+            | CollectionsLibFunctions::Difference => {
+                expect_args(name, &args, 2)?;
+                let (left, right) = (set_argument(name, &args, 0)?, set_argument(name, &args, 1)?);
+                let (left, right) = (left.borrow().clone(), right.borrow().clone());
+                let set = match func_name {
+                    CollectionsLibFunctions::Union => {
+                        let mut set = left;
+                        for item in right.iter() {
+                            set.insert(name, item.clone())?;
+                        }
+                        set
+                    }
+                    _ => {
+                        let keep_shared = func_name == CollectionsLibFunctions::Intersection;
+                        let mut set = Set::new();
+                        for item in left.iter() {
+                            if right.contains(item) == keep_shared {
+                                set.insert(name, item.clone())?;
+                            }
+                        }
+                        set
+                    }
+                };
+                return Ok(Primitive::Set(Gc::new(set)));
+            }
+            CollectionsLibFunctions::PushFront | CollectionsLibFunctions::PushBack => {
+                expect_args(name, &args, 2)?;
+                let deque = deque_argument(name, &args, 0)?;
+                let mut deque = deque.borrow_mut();
+                if func_name == CollectionsLibFunctions::PushFront {
+                    deque.items.push_front(args[1].clone());
+                } else {
+                    deque.items.push_back(args[1].clone());
+                }
+            }
+            CollectionsLibFunctions::PopFront | CollectionsLibFunctions::PopBack => {
+                expect_args(name, &args, 1)?;
+                let deque = deque_argument(name, &args, 0)?;
+                let mut deque = deque.borrow_mut();
+                let item = if func_name == CollectionsLibFunctions::PopFront {
+                    deque.items.pop_front()
+                } else {
+                    deque.items.pop_back()
+                };
+                return item.ok_or_else(|| {
+                    InterpException::InvalidValue(
+                        name.to_string(),
+                        String::from("cannot pop from an empty deque"),
+                    )
+                });
+            }
+            // Null when the deque is empty
+            CollectionsLibFunctions::Front | CollectionsLibFunctions::Back => {
+                expect_args(name, &args, 1)?;
+                let deque = deque_argument(name, &args, 0)?;
+                let deque = deque.borrow();
+                let item = if func_name == CollectionsLibFunctions::Front {
+                    deque.items.front()
+                } else {
+                    deque.items.back()
+                };
+                return Ok(item.cloned().unwrap_or(Primitive::None));
+            }
+            CollectionsLibFunctions::ToList => {
+                expect_args(name, &args, 1)?;
+                if let Primitive::Set(_) | Primitive::Deque(_) = &args[0] && let Some(items) = items_of(&args[0]) {
+                    return Ok(Primitive::List(Gc::new(items)));
+                }
+                return Err(InterpException::InvalidArgument(
+                    name.to_string(),
+                    1,
+                    "set or deque",
+                    args[0].type_name(),
+                ));
+            }
+        }
+        return Ok(Primitive::None);
+    }
+}
+
+fn initial_items(name: &str, args: &[Primitive]) -> Result<Vec<Primitive>, InterpException> {
+    match args {
+        [] => return Ok(Vec::new()),
+        [items] => {
+            return items_of(items).ok_or_else(|| {
+                InterpException::InvalidArgument(
+                    name.to_string(),
+                    1,
+                    "list, set or deque",
+                    items.type_name(),
+                )
+            })
+        }
+        _ => {
+            return Err(InterpException::WrongArgumentCount(
+                name.to_string(),
+                1,
+                args.len(),
+            ))
+        }
+    }
+}
+
+fn set_argument(
+    name: &str,
+    args: &[Primitive],
+    position: usize,
+) -> Result<Gc<Set>, InterpException> {
+    match args.get(position) {
+        Some(Primitive::Set(set)) => return Ok(set.clone()),
+        arg => {
+            let found = arg.map_or("null", Primitive::type_name);
+            return Err(InterpException::InvalidArgument(
+                name.to_string(),
+                position + 1,
+                "set",
+                found,
+            ));
+        }
+    }
+}
+
+fn deque_argument(
+    name: &str,
+    args: &[Primitive],
+    position: usize,
+) -> Result<Gc<Deque>, InterpException> {
+    match args.get(position) {
+        Some(Primitive::Deque(deque)) => return Ok(deque.clone()),
+        arg => {
+            let found = arg.map_or("null", Primitive::type_name);
+            return Err(InterpException::InvalidArgument(
+                name.to_string(),
+                position + 1,
+                "deque",
+                found,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::testing::run_both;
+
+    #[test]
+    fn remove_from_a_deque_holding_itself() {
+        let source = "
+            import collections;
+            env collections {
+                let d = deque();
+                push_back(d, d);
+                println(remove(d, deque()));
+                println(remove(d, d));
+                println(len(d));
+            }
+        ";
+        assert_eq!(run_both(source), "false\ntrue\n0\n");
+    }
+
+    #[test]
+    fn whole_floats_share_the_ints_key() {
+        let source = "
+            import collections;
+            env collections {
+                println(len(set([1, 1.0, 2.5])));
+                println(len(set([16777217, 16777216.0])));
+                println(contains(set([16777217]), 16777216.0));
+            }
+        ";
+        assert_eq!(run_both(source), "2\n2\nfalse\n");
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use crate::{
+    error_reporting::interp_err::InterpException, heap::heap::Gc,
+    interpreter::environment::Environment, scanner::token::Primitive,
+};
+
+use super::list_of;
+
+// Derives mapping a struct with named fields to an env holding a variable per field
+pub use vmod_derive::{FromPrimitive, IntoPrimitive};
+
+// Conversions host code uses to pass values into scripts and read them back out.
+// Passing a value in can't fail, so a u64 or usize past isize::MAX arrives clamped
+// to isize::MAX; check it first if the exact number matters.
+pub trait IntoPrimitive {
+    fn into_primitive(self) -> Primitive;
+}
+
+pub trait FromPrimitive: Sized {
+    fn from_primitive(prim: Primitive) -> Result<Self, InterpException>;
+}
+
+impl IntoPrimitive for Primitive {
+    fn into_primitive(self) -> Primitive {
+        return self;
+    }
+}
+
+impl FromPrimitive for Primitive {
+    fn from_primitive(prim: Primitive) -> Result<Self, InterpException> {
+        return Ok(prim);
+    }
+}
+
+impl IntoPrimitive for () {
+    fn into_primitive(self) -> Primitive {
+        return Primitive::None;
+    }
+}
+
+// Clamping beats wrapping around to an unrelated number. Converting back out fails
+// instead, since there the host can handle the error.
+macro_rules! int_conversions {
+    ($($int:ty),*) => {
+        $(
+            impl IntoPrimitive for $int {
+                fn into_primitive(self) -> Primitive {
+                    let int = isize::try_from(self).unwrap_or(if self > 0 { isize::MAX } else { isize::MIN });
+                    return Primitive::Int(int);
+                }
+            }
+
+            impl FromPrimitive for $int {
+                fn from_primitive(prim: Primitive) -> Result<Self, InterpException> {
+                    if let Primitive::Int(int) = prim {
+                        return <$int>::try_from(int)
+                            .map_err(|_| InterpException::InvalidConversion(stringify!($int), "int"));
+                    }
+                    return Err(InterpException::InvalidConversion(stringify!($int), prim.type_name()));
+                }
+            }
+        )*
+    };
+}
+
+int_conversions!(isize, i64, i32, usize, u64, u32, u8);
+
+impl IntoPrimitive for f32 {
+    fn into_primitive(self) -> Primitive {
+        return Primitive::Float(self);
+    }
+}
+
+impl IntoPrimitive for f64 {
+    fn into_primitive(self) -> Primitive {
+        return Primitive::Float(self as f32);
+    }
+}
+
+// Ints are accepted wherever a float is expected
+impl FromPrimitive for f32 {
+    fn from_primitive(prim: Primitive) -> Result<Self, InterpException> {
+        match prim {
+            Primitive::Float(flt) => return Ok(flt),
+            Primitive::Int(int) => return Ok(int as f32),
+            _ => {
+                return Err(InterpException::InvalidConversion(
+                    "float",
+                    prim.type_name(),
+                ))
+            }
+        }
+    }
+}
+
+impl FromPrimitive for f64 {
+    fn from_primitive(prim: Primitive) -> Result<Self, InterpException> {
+        return f32::from_primitive(prim).map(|flt| flt as f64);
+    }
+}
+
+impl IntoPrimitive for String {
+    fn into_primitive(self) -> Primitive {
+        return Primitive::String(self);
+    }
+}
+
+impl IntoPrimitive for &str {
+    fn into_primitive(self) -> Primitive {
+        return Primitive::String(self.to_string());
+    }
+}
+
+impl FromPrimitive for String {
+    fn from_primitive(prim: Primitive) -> Result<Self, InterpException> {
+        if let Primitive::String(string) = prim {
+            return Ok(string);
+        }
+        return Err(InterpException::InvalidConversion(
+            "string",
+            prim.type_name(),
+        ));
+    }
+}
+
+impl IntoPrimitive for bool {
+    fn into_primitive(self) -> Primitive {
+        return Primitive::Bool(self);
+    }
+}
+
+impl FromPrimitive for bool {
+    fn from_primitive(prim: Primitive) -> Result<Self, InterpException> {
+        if let Primitive::Bool(boolean) = prim {
+            return Ok(boolean);
+        }
+        return Err(InterpException::InvalidConversion("bool", prim.type_name()));
+    }
+}
+
+impl<T: IntoPrimitive> IntoPrimitive for Option<T> {
+    fn into_primitive(self) -> Primitive {
+        match self {
+            Some(value) => return value.into_primitive(),
+            None => return Primitive::None,
+        }
+    }
+}
+
+impl<T: FromPrimitive> FromPrimitive for Option<T> {
+    fn from_primitive(prim: Primitive) -> Result<Self, InterpException> {
+        if let Primitive::None = prim {
+            return Ok(None);
+        }
+        return T::from_primitive(prim).map(Some);
+    }
+}
+
+impl<T: IntoPrimitive> IntoPrimitive for Vec<T> {
+    fn into_primitive(self) -> Primitive {
+        let list = self.into_iter().map(IntoPrimitive::into_primitive).collect();
+        return Primitive::List(Gc::new(list));
+    }
+}
+
+impl<T: FromPrimitive> FromPrimitive for Vec<T> {
+    fn from_primitive(prim: Primitive) -> Result<Self, InterpException> {
+        let Some(list) = list_of(&prim) else {
+            return Err(InterpException::InvalidConversion("list", prim.type_name()));
+        };
+        let items = list.borrow().clone();
+        return items.into_iter().map(T::from_primitive).collect();
+    }
+}
+
+impl<T: IntoPrimitive> IntoPrimitive for HashMap<String, T> {
+    fn into_primitive(self) -> Primitive {
+        let vars = self
+            .into_iter()
+            .map(|(key, value)| (key, value.into_primitive()))
+            .collect();
+        return Environment::with_vars(vars).into_primitive();
+    }
+}
+
+impl<T: FromPrimitive> FromPrimitive for HashMap<String, T> {
+    fn from_primitive(prim: Primitive) -> Result<Self, InterpException> {
+        let Primitive::Env(env) = prim else {
+            return Err(InterpException::InvalidConversion("env", prim.type_name()));
+        };
+        let vars = env.borrow().vars.clone();
+        return vars
+            .into_iter()
+            .map(|(key, value)| Ok((key, T::from_primitive(value)?)))
+            .collect();
+    }
+}
+
+impl IntoPrimitive for Environment {
+    fn into_primitive(self) -> Primitive {
+        let mut env = self;
+        env.enclosing = None;
+        return Primitive::Env(Gc::new(env));
+    }
+}
+
+impl IntoPrimitive for Gc<Environment> {
+    fn into_primitive(self) -> Primitive {
+        return Primitive::Env(self);
+    }
+}
+
+impl FromPrimitive for Gc<Environment> {
+    fn from_primitive(prim: Primitive) -> Result<Self, InterpException> {
+        if let Primitive::Env(env) = prim {
+            return Ok(env);
+        }
+        return Err(InterpException::InvalidConversion("env", prim.type_name()));
+    }
+}
+
+// Used by the derives, to say which field of a struct couldn't be converted
+pub fn field_error(ty: &str, field: &str, err: InterpException) -> InterpException {
+    if let InterpException::InvalidConversion(expected, found) = err {
+        return InterpException::InvalidField(ty.to_string(), field.to_string(), expected, found);
+    }
+    return err;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FromPrimitive, IntoPrimitive};
+    use crate::scanner::token::Primitive;
+
+    #[test]
+    fn big_ints_clamp_going_in_and_fail_coming_out() {
+        assert!(u64::MAX.into_primitive() == Primitive::Int(isize::MAX));
+        assert!(usize::MAX.into_primitive() == Primitive::Int(isize::MAX));
+        assert!(u64::from_primitive(Primitive::Int(-1)).is_err());
+        assert!(u8::from_primitive(Primitive::Int(256)).is_err());
+        assert_eq!(u64::from_primitive(Primitive::Int(isize::MAX)).ok(), Some(isize::MAX as u64));
+    }
+}
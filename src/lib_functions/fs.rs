@@ -0,0 +1,163 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    error_reporting::interp_err::InterpException,
+    heap::heap::Gc,
+    interpreter::{
+        environment::Environment,
+        permissions::{Capability, Permissions},
+    },
+    scanner::token::Primitive,
+};
+
+use super::{
+    convert::IntoPrimitive,
+    native::{argument, expect_args},
+    FsLibFunctions, LibFunctions,
+};
+
+// Files and directories, as far as the interpreter's permissions reach
+pub struct FileSystem;
+
+impl FileSystem {
+    pub fn new() -> Environment {
+        let mut ret_env = Environment::new();
+        for (name, func) in [
+            ("read", FsLibFunctions::Read),
+            ("write", FsLibFunctions::Write),
+            ("append", FsLibFunctions::Append),
+            ("read_bytes", FsLibFunctions::ReadBytes),
+            ("write_bytes", FsLibFunctions::WriteBytes),
+            ("append_bytes", FsLibFunctions::AppendBytes),
+            ("list_dir", FsLibFunctions::ListDir),
+            ("exists", FsLibFunctions::Exists),
+            ("join", FsLibFunctions::Join),
+            ("mkdir", FsLibFunctions::Mkdir),
+            ("remove", FsLibFunctions::Remove),
+            ("metadata", FsLibFunctions::Metadata),
+        ] {
+            ret_env.define(name, Primitive::NativeFunc(LibFunctions::Fs(func)));
+        }
+        return ret_env;
+    }
+
+    pub fn do_func(
+        func_name: FsLibFunctions,
+        args: Vec<Primitive>,
+        permissions: &Permissions,
+    ) -> Result<Primitive, InterpException> {
+        let name = func_name.name();
+        // Joining paths is the only thing here that doesn't go to the disk
+        if let FsLibFunctions::Join = func_name {
+            let mut joined = std::path::PathBuf::new();
+            for position in 0..args.len() {
+                joined.push(argument::<String>(name, &args, position)?);
+            }
+            return Ok(joined.to_string_lossy().into_owned().into_primitive());
+        }
+
+        let arity = match func_name {
+            FsLibFunctions::Write
+            | FsLibFunctions::Append
+            | FsLibFunctions::WriteBytes
+            | FsLibFunctions::AppendBytes => 2,
+            _ => 1,
+        };
+        expect_args(name, &args, arity)?;
+        let path: String = argument(name, &args, 0)?;
+        let capability = match func_name {
+            FsLibFunctions::Write
+            | FsLibFunctions::Append
+            | FsLibFunctions::WriteBytes
+            | FsLibFunctions::AppendBytes
+            | FsLibFunctions::Mkdir
+            | FsLibFunctions::Remove => Capability::Write,
+            _ => Capability::Read,
+        };
+        permissions.check(capability, &path)?;
+        let io_error =
+            |err: io::Error| InterpException::IoFailed(name, path.clone(), err.to_string());
+
+        match func_name {
+            FsLibFunctions::Read => {
+                return fs::read_to_string(&path)
+                    .map(IntoPrimitive::into_primitive)
+                    .map_err(io_error);
+            }
+            FsLibFunctions::ReadBytes => {
+                return fs::read(&path)
+                    .map(IntoPrimitive::into_primitive)
+                    .map_err(io_error);
+            }
+            FsLibFunctions::Write | FsLibFunctions::Append => {
+                let text: String = argument(name, &args, 1)?;
+                let append = func_name == FsLibFunctions::Append;
+                write_file(&path, text.as_bytes(), append).map_err(io_error)?;
+            }
+            FsLibFunctions::WriteBytes | FsLibFunctions::AppendBytes => {
+                let bytes: Vec<u8> = argument(name, &args, 1)?;
+                let append = func_name == FsLibFunctions::AppendBytes;
+                write_file(&path, &bytes, append).map_err(io_error)?;
+            }
+            FsLibFunctions::ListDir => {
+                let mut names = Vec::new();
+                for entry in fs::read_dir(&path).map_err(io_error)? {
+                    let entry = entry.map_err(io_error)?;
+                    names.push(entry.file_name().to_string_lossy().into_owned());
+                }
+                names.sort();
+                return Ok(names.into_primitive());
+            }
+            FsLibFunctions::Exists => {
+                return Ok(Primitive::Bool(Path::new(&path).exists()));
+            }
+            FsLibFunctions::Mkdir => {
+                fs::create_dir_all(&path).map_err(io_error)?;
+            }
+            // Directories are removed along with everything in them
+            FsLibFunctions::Remove => {
+                if Path::new(&path).is_dir() {
+                    fs::remove_dir_all(&path).map_err(io_error)?;
+                } else {
+                    fs::remove_file(&path).map_err(io_error)?;
+                }
+            }
+            FsLibFunctions::Metadata => {
+                let metadata = fs::metadata(&path).map_err(io_error)?;
+                let mut ret_env = Environment::new();
+                ret_env.define("size", metadata.len().into_primitive());
+                ret_env.define("is_file", Primitive::Bool(metadata.is_file()));
+                ret_env.define("is_dir", Primitive::Bool(metadata.is_dir()));
+                ret_env.define(
+                    "readonly",
+                    Primitive::Bool(metadata.permissions().readonly()),
+                );
+                // Seconds since the epoch, or null where the platform doesn't record it
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|since| since.as_secs());
+                ret_env.define("modified", modified.into_primitive());
+                return Ok(Primitive::Env(Gc::new(ret_env)));
+            }
+            FsLibFunctions::Join => unreachable!(),
+        }
+        return Ok(Primitive::None);
+    }
+}
+
+fn write_file(path: &str, contents: &[u8], append: bool) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)?;
+    return file.write_all(contents);
+}
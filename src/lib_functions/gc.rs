@@ -0,0 +1,80 @@
+use crate::{
+    error_reporting::interp_err::InterpException,
+    heap::heap::{self, Gc},
+    interpreter::environment::Environment,
+    scanner::token::Primitive,
+};
+
+use super::{GcLibFunctions, LibFunctions};
+
+// Lets scripts look at and run the collector for the heap their envs and lists live on
+pub struct Collector;
+
+impl Collector {
+    pub fn new() -> Environment {
+        let mut ret_env = Environment::new();
+
+        ret_env.define(
+            "collect",
+            Primitive::NativeFunc(LibFunctions::Gc(GcLibFunctions::Collect)),
+        );
+        ret_env.define(
+            "stats",
+            Primitive::NativeFunc(LibFunctions::Gc(GcLibFunctions::Stats)),
+        );
+        return ret_env;
+    }
+
+    pub fn do_func(func_name: GcLibFunctions) -> Result<Primitive, InterpException> {
+        match func_name {
+            GcLibFunctions::Collect => return Ok(Primitive::Int(heap::collect() as isize)),
+            GcLibFunctions::Stats => {
+                let stats = heap::stats();
+                let mut ret_env = Environment::new();
+                ret_env.define("objects", Primitive::Int(stats.objects as isize));
+                ret_env.define("envs", Primitive::Int(stats.envs as isize));
+                ret_env.define("lists", Primitive::Int(stats.lists as isize));
+                ret_env.define("bytes", Primitive::Int(stats.bytes as isize));
+                ret_env.define("largest", Primitive::Int(stats.largest as isize));
+                ret_env.define("allocations", Primitive::Int(stats.allocations as isize));
+                ret_env.define("collections", Primitive::Int(stats.collections as isize));
+                ret_env.define("freed", Primitive::Int(stats.freed as isize));
+                return Ok(Primitive::Env(Gc::new(ret_env)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::testing::run_both;
+
+    #[test]
+    fn collect_frees_cycles_left_behind() {
+        let source = "
+            import gc;
+            func cycle() {
+                let xs = [];
+                env xs {
+                    append(xs);
+                }
+            }
+            env gc {
+                let live = 0;
+                let before = stats();
+                env before {
+                    live = lists;
+                }
+                cycle();
+                cycle();
+                println(collect());
+                println(collect());
+                let after = stats();
+                env after {
+                    println(lists - live);
+                }
+            }
+        ";
+        assert_eq!(run_both(source), "2\n0\n0\n");
+    }
+}
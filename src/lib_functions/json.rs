@@ -0,0 +1,491 @@
+use std::collections::HashSet;
+
+use crate::{
+    error_reporting::interp_err::InterpException, heap::heap::Gc,
+    interpreter::environment::Environment, scanner::token::Primitive,
+};
+
+use super::{
+    items_of,
+    native::{argument, expect_args},
+    JsonLibFunctions, LibFunctions,
+};
+
+// Deeper documents are refused rather than risking the stack
+const MAX_NESTING: usize = 512;
+
+// Reads JSON into lists and envs, and writes any value made of data back out
+pub struct Json;
+
+impl Json {
+    pub fn new() -> Environment {
+        let mut ret_env = Environment::new();
+        ret_env.define(
+            "parse",
+            Primitive::NativeFunc(LibFunctions::Json(JsonLibFunctions::Parse)),
+        );
+        ret_env.define(
+            "stringify",
+            Primitive::NativeFunc(LibFunctions::Json(JsonLibFunctions::Stringify)),
+        );
+        return ret_env;
+    }
+
+    pub fn do_func(
+        func_name: JsonLibFunctions,
+        args: Vec<Primitive>,
+    ) -> Result<Primitive, InterpException> {
+        let name = func_name.name();
+        match func_name {
+            JsonLibFunctions::Parse => {
+                expect_args(name, &args, 1)?;
+                let source: String = argument(name, &args, 0)?;
+                return Parser::new(&source).parse_document();
+            }
+            // Pretty output puts every member on its own line, indented by two spaces
+            JsonLibFunctions::Stringify => {
+                if args.is_empty() || args.len() > 2 {
+                    return Err(InterpException::WrongArgumentCount(
+                        name.to_string(),
+                        2,
+                        args.len(),
+                    ));
+                }
+                let pretty: bool = if args.len() == 2 {
+                    argument(name, &args, 1)?
+                } else {
+                    false
+                };
+                let mut writer = Writer {
+                    out: String::new(),
+                    pretty,
+                    visiting: HashSet::new(),
+                };
+                writer.value(&args[0], &mut String::from("$"), 0)?;
+                return Ok(Primitive::String(writer.out));
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    bytes: &'a [u8],
+    current: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        return Self {
+            source,
+            bytes: source.as_bytes(),
+            current: 0,
+        };
+    }
+
+    fn parse_document(&mut self) -> Result<Primitive, InterpException> {
+        let value = self.value(0)?;
+        self.skip_whitespace();
+        if self.current < self.bytes.len() {
+            return Err(self.error("unexpected data after the document"));
+        }
+        return Ok(value);
+    }
+
+    fn value(&mut self, nesting: usize) -> Result<Primitive, InterpException> {
+        if nesting > MAX_NESTING {
+            return Err(self.error("too deeply nested"));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => return self.object(nesting),
+            Some(b'[') => return self.array(nesting),
+            Some(b'"') => return Ok(Primitive::String(self.string()?)),
+            Some(b't') => return self.literal("true", Primitive::Bool(true)),
+            Some(b'f') => return self.literal("false", Primitive::Bool(false)),
+            Some(b'n') => return self.literal("null", Primitive::None),
+            Some(b'-' | b'0'..=b'9') => return self.number(),
+            Some(_) => return Err(self.error("expected a value")),
+            None => return Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self, nesting: usize) -> Result<Primitive, InterpException> {
+        self.current += 1;
+        let mut env = Environment::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.current += 1;
+            return Ok(Primitive::Env(Gc::new(env)));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':', "expected ':' after a key")?;
+            let value = self.value(nesting + 1)?;
+            env.define(&key, value);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.current += 1,
+                Some(b'}') => {
+                    self.current += 1;
+                    return Ok(Primitive::Env(Gc::new(env)));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self, nesting: usize) -> Result<Primitive, InterpException> {
+        self.current += 1;
+        let mut list = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.current += 1;
+            return Ok(Primitive::List(Gc::new(list)));
+        }
+        loop {
+            list.push(self.value(nesting + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.current += 1,
+                Some(b']') => {
+                    self.current += 1;
+                    return Ok(Primitive::List(Gc::new(list)));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, InterpException> {
+        self.current += 1;
+        let mut string = String::new();
+        loop {
+            let start = self.current;
+            while let Some(byte) = self.peek() && byte != b'"' && byte != b'\\' && byte >= 0x20 {
+                self.current += 1;
+            }
+            string.push_str(&self.source[start..self.current]);
+            match self.peek() {
+                Some(b'"') => {
+                    self.current += 1;
+                    return Ok(string);
+                }
+                Some(b'\\') => {
+                    self.current += 1;
+                    string.push(self.escape()?);
+                }
+                Some(_) => return Err(self.error("control character in string")),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, InterpException> {
+        let escaped = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.current += 1;
+                let high = self.hex()?;
+                if !(0xD800..0xDC00).contains(&high) {
+                    return char::from_u32(high).ok_or_else(|| self.error("invalid escape"));
+                }
+                // Characters outside the basic plane are written as two escapes
+                if !self.source[self.current..].starts_with("\\u") {
+                    return Err(self.error("unpaired surrogate"));
+                }
+                self.current += 2;
+                let low = self.hex()?;
+                if !(0xDC00..0xE000).contains(&low) {
+                    return Err(self.error("unpaired surrogate"));
+                }
+                let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                return char::from_u32(code).ok_or_else(|| self.error("invalid escape"));
+            }
+            _ => return Err(self.error("invalid escape")),
+        };
+        self.current += 1;
+        return Ok(escaped);
+    }
+
+    fn hex(&mut self) -> Result<u32, InterpException> {
+        let digits = self
+            .source
+            .get(self.current..self.current + 4)
+            .filter(|digits| digits.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        let Some(code) = digits.and_then(|digits| u32::from_str_radix(digits, 16).ok()) else {
+            return Err(self.error("expected four hex digits"));
+        };
+        self.current += 4;
+        return Ok(code);
+    }
+
+    // Whole numbers that fit are ints, everything else is a float
+    fn number(&mut self) -> Result<Primitive, InterpException> {
+        let start = self.current;
+        if self.peek() == Some(b'-') {
+            self.current += 1;
+        }
+        if !self.digits() {
+            return Err(self.error("expected a digit"));
+        }
+        let mut integral = true;
+        if self.peek() == Some(b'.') {
+            integral = false;
+            self.current += 1;
+            if !self.digits() {
+                return Err(self.error("expected a digit after '.'"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            integral = false;
+            self.current += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.current += 1;
+            }
+            if !self.digits() {
+                return Err(self.error("expected a digit in the exponent"));
+            }
+        }
+        let text = &self.source[start..self.current];
+        if integral && let Ok(int) = text.parse() {
+            return Ok(Primitive::Int(int));
+        }
+        return Ok(Primitive::Float(text.parse().unwrap()));
+    }
+
+    fn digits(&mut self) -> bool {
+        let start = self.current;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.current += 1;
+        }
+        return self.current > start;
+    }
+
+    fn literal(&mut self, word: &str, value: Primitive) -> Result<Primitive, InterpException> {
+        if !self.source[self.current..].starts_with(word) {
+            return Err(self.error("expected a value"));
+        }
+        self.current += word.len();
+        return Ok(value);
+    }
+
+    fn expect(&mut self, byte: u8, msg: &str) -> Result<(), InterpException> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(msg));
+        }
+        self.current += 1;
+        return Ok(());
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.current += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        return self.bytes.get(self.current).copied();
+    }
+
+    fn error(&self, msg: &str) -> InterpException {
+        let before = &self.source[..self.current];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        return InterpException::InvalidJson(msg.to_string(), line, column);
+    }
+}
+
+struct Writer {
+    out: String,
+    pretty: bool,
+    // Envs and lists being written, to tell a cycle from a value that's shared
+    visiting: HashSet<usize>,
+}
+
+impl Writer {
+    // The path, like $.servers[0].port, says where a value that can't be written is
+    fn value(
+        &mut self,
+        value: &Primitive,
+        path: &mut String,
+        indent: usize,
+    ) -> Result<(), InterpException> {
+        match value {
+            Primitive::None => self.out.push_str("null"),
+            Primitive::Bool(boolean) => self.out.push_str(&boolean.to_string()),
+            Primitive::Int(int) => self.out.push_str(&int.to_string()),
+            Primitive::Float(flt) => {
+                if !flt.is_finite() {
+                    return Err(InterpException::NotSerializable(
+                        path.clone(),
+                        "non-finite float",
+                    ));
+                }
+                let text = flt.to_string();
+                self.out.push_str(&text);
+                // Keeps it a float when it's read back
+                if !text.contains('.') {
+                    self.out.push_str(".0");
+                }
+            }
+            Primitive::String(string) => self.string(string),
+            Primitive::List(_) | Primitive::Env(_) | Primitive::Set(_) | Primitive::Deque(_) => {
+                return self.collection(value, path, indent)
+            }
+            Primitive::Func(_) | Primitive::NativeFunc(_) => {
+                return Err(InterpException::NotSerializable(path.clone(), "func"));
+            }
+        }
+        return Ok(());
+    }
+
+    fn collection(
+        &mut self,
+        value: &Primitive,
+        path: &mut String,
+        indent: usize,
+    ) -> Result<(), InterpException> {
+        let id = match value {
+            Primitive::Env(env) => env.id(),
+            Primitive::List(list) => list.id(),
+            Primitive::Set(set) => set.id(),
+            Primitive::Deque(deque) => deque.id(),
+            _ => unreachable!(),
+        };
+        if !self.visiting.insert(id) {
+            return Err(InterpException::NotSerializable(path.clone(), "cycle"));
+        }
+        let path_len = path.len();
+
+        // Lists declared with let live in an env, but are written as the list they hold.
+        // Sets and deques are written as arrays.
+        if let Some(items) = items_of(value) {
+            self.out.push('[');
+            for (idx, item) in items.iter().enumerate() {
+                self.separator(idx, indent + 1);
+                path.push_str(&format!("[{}]", idx));
+                self.value(item, path, indent + 1)?;
+                path.truncate(path_len);
+            }
+            self.close(items.is_empty(), indent, ']');
+        } else if let Primitive::Env(env) = value {
+            let mut members: Vec<(String, Primitive)> =
+                env.borrow().vars.clone().into_iter().collect();
+            members.sort_by(|left, right| left.0.cmp(&right.0));
+            self.out.push('{');
+            for (idx, (key, member)) in members.iter().enumerate() {
+                self.separator(idx, indent + 1);
+                self.string(key);
+                self.out.push_str(if self.pretty { ": " } else { ":" });
+                path.push('.');
+                path.push_str(key);
+                self.value(member, path, indent + 1)?;
+                path.truncate(path_len);
+            }
+            self.close(members.is_empty(), indent, '}');
+        }
+
+        self.visiting.remove(&id);
+        return Ok(());
+    }
+
+    fn separator(&mut self, idx: usize, indent: usize) {
+        if idx > 0 {
+            self.out.push(',');
+        }
+        self.newline(indent);
+    }
+
+    fn close(&mut self, empty: bool, indent: usize, bracket: char) {
+        if !empty {
+            self.newline(indent);
+        }
+        self.out.push(bracket);
+    }
+
+    fn newline(&mut self, indent: usize) {
+        if self.pretty {
+            self.out.push('\n');
+            self.out.push_str(&"  ".repeat(indent));
+        }
+    }
+
+    fn string(&mut self, string: &str) {
+        self.out.push('"');
+        for chr in string.chars() {
+            match chr {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                chr if (chr as u32) < 0x20 => self.out.push_str(&format!("\\u{:04x}", chr as u32)),
+                chr => self.out.push(chr),
+            }
+        }
+        self.out.push('"');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::testing::run_both;
+
+    #[test]
+    fn values_survive_a_round_trip() {
+        let source = r#"
+            import json;
+            decenv point = {
+                let y = [1, 2.5];
+                let x = "left";
+                let none = null;
+            }
+            env json {
+                println(stringify(parse("[1, 2.5, true, null, [3, []]]")));
+                let text = stringify(point);
+                println(text);
+                let doc = parse(text);
+                env doc {
+                    println(x);
+                    println(y);
+                }
+                println(stringify([1, [2]], true));
+            }
+        "#;
+        assert_eq!(
+            run_both(source),
+            "[1,2.5,true,null,[3,[]]]\n{\"none\":null,\"x\":\"left\",\"y\":[1,2.5]}\nleft\n[1, 2.5]\n[\n  1,\n  [\n    2\n  ]\n]\n"
+        );
+    }
+
+    #[test]
+    fn errors_say_where() {
+        let source = r#"
+            import json;
+            func f() { return 1; }
+            env json {
+                println(parse("[1, tru]"));
+            }
+            env json {
+                println(stringify([1, [f]]));
+            }
+        "#;
+        assert_eq!(
+            run_both(source),
+            "Invalid JSON at line 1, column 5: expected a value\nCannot write func at $[1][0] as JSON\n"
+        );
+    }
+}
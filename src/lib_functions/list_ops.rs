@@ -1,13 +1,27 @@
-use crate::{error_reporting::interp_err::InterpException, scanner::token::Primitive};
+use std::cmp::Ordering;
+
+use crate::{
+    error_reporting::interp_err::InterpException, heap::heap::Gc, interpreter::operations,
+    scanner::token::Primitive,
+};
+
+use super::{
+    native::{argument, expect_args, function_argument, list_argument},
+    runtime::Runtime,
+    ListLibFunctions,
+};
 
 pub fn append(list: &mut Vec<Primitive>, new: Primitive) {
     list.push(new);
 }
 
-pub fn set(list: &mut Vec<Primitive>, idx: Primitive, primitive: Primitive) {
-    if let Primitive::Int(idx_uw) = idx {
-        list[idx_uw as usize] = primitive;
-    }
+pub fn set(list: &mut Vec<Primitive>, idx: usize, primitive: Primitive) -> Result<(), InterpException> {
+    let len = list.len();
+    let Some(slot) = list.get_mut(idx) else {
+        return Err(out_of_range("set", idx, len));
+    };
+    *slot = primitive;
+    return Ok(());
 }
 
 pub fn len(list: &Vec<Primitive>) -> Primitive {
@@ -17,3 +31,333 @@ pub fn len(list: &Vec<Primitive>) -> Primitive {
 pub fn slice(list: &Vec<Primitive>, index1: usize, index2: usize) -> Vec<Primitive> {
     return list[index1..index2].to_vec();
 }
+
+// The methods lists have besides the four above. Those taking a function work on a copy
+// of the list, so the function is free to change the list while it runs.
+pub fn call_method(
+    method: &ListLibFunctions,
+    list: Gc<Vec<Primitive>>,
+    args: Vec<Primitive>,
+    runtime: &mut dyn Runtime,
+) -> Result<Primitive, InterpException> {
+    let name = method.name();
+    match method {
+        ListLibFunctions::Map => {
+            expect_args(name, &args, 1)?;
+            let func = function_argument(name, &args, 0)?;
+            let mut mapped = Vec::new();
+            for item in items(&list) {
+                mapped.push(runtime.invoke(name, func.clone(), vec![item])?);
+            }
+            return Ok(Primitive::List(Gc::new(mapped)));
+        }
+        ListLibFunctions::Filter => {
+            expect_args(name, &args, 1)?;
+            let predicate = function_argument(name, &args, 0)?;
+            let mut kept = Vec::new();
+            for item in items(&list) {
+                if test(runtime, name, &predicate, item.clone())? {
+                    kept.push(item);
+                }
+            }
+            return Ok(Primitive::List(Gc::new(kept)));
+        }
+        // Starts from the first item when there's no starting value
+        ListLibFunctions::Reduce => {
+            if args.len() != 1 {
+                expect_args(name, &args, 2)?;
+            }
+            let func = function_argument(name, &args, 0)?;
+            let mut items = items(&list).into_iter();
+            let mut acc = match args.get(1) {
+                Some(initial) => initial.clone(),
+                None => items.next().ok_or_else(|| {
+                    InterpException::InvalidValue(
+                        name.to_string(),
+                        String::from("cannot reduce an empty list without a starting value"),
+                    )
+                })?,
+            };
+            for item in items {
+                acc = runtime.invoke(name, func.clone(), vec![acc, item])?;
+            }
+            return Ok(acc);
+        }
+        ListLibFunctions::Any | ListLibFunctions::All => {
+            expect_args(name, &args, 1)?;
+            let predicate = function_argument(name, &args, 0)?;
+            let want = matches!(method, ListLibFunctions::Any);
+            for item in items(&list) {
+                if test(runtime, name, &predicate, item)? == want {
+                    return Ok(Primitive::Bool(want));
+                }
+            }
+            return Ok(Primitive::Bool(!want));
+        }
+        ListLibFunctions::Find => {
+            expect_args(name, &args, 1)?;
+            let predicate = function_argument(name, &args, 0)?;
+            for item in items(&list) {
+                if test(runtime, name, &predicate, item.clone())? {
+                    return Ok(item);
+                }
+            }
+        }
+        // Sorts in place, keeping equal items in the order they were in. The comparator
+        // returns whether its first argument goes before its second, or a number below,
+        // at or above zero like a subtraction would.
+        ListLibFunctions::Sort => {
+            let mut comparator = None;
+            if !args.is_empty() {
+                expect_args(name, &args, 1)?;
+                comparator = Some(function_argument(name, &args, 0)?);
+            }
+            let sorted = merge_sort(items(&list), &mut |left, right| {
+                return ordering(runtime, name, comparator.as_ref(), left, right);
+            })?;
+            *list.borrow_mut() = sorted;
+        }
+        ListLibFunctions::Reverse => {
+            expect_args(name, &args, 0)?;
+            list.borrow_mut().reverse();
+        }
+        // Pairs up items until the shorter list runs out
+        ListLibFunctions::Zip => {
+            expect_args(name, &args, 1)?;
+            let other = items(&list_argument(name, &args, 0)?);
+            let pairs = items(&list)
+                .into_iter()
+                .zip(other)
+                .map(|(left, right)| Primitive::List(Gc::new(vec![left, right])))
+                .collect();
+            return Ok(Primitive::List(Gc::new(pairs)));
+        }
+        ListLibFunctions::Enumerate => {
+            expect_args(name, &args, 0)?;
+            let pairs = items(&list)
+                .into_iter()
+                .enumerate()
+                .map(|(idx, item)| {
+                    Primitive::List(Gc::new(vec![Primitive::Int(idx as isize), item]))
+                })
+                .collect();
+            return Ok(Primitive::List(Gc::new(pairs)));
+        }
+        ListLibFunctions::Contains => {
+            expect_args(name, &args, 1)?;
+            return Ok(Primitive::Bool(list.borrow().contains(&args[0])));
+        }
+        // Null when the value isn't in the list
+        ListLibFunctions::IndexOf => {
+            expect_args(name, &args, 1)?;
+            if let Some(idx) = list.borrow().iter().position(|item| *item == args[0]) {
+                return Ok(Primitive::Int(idx as isize));
+            }
+        }
+        ListLibFunctions::Insert => {
+            expect_args(name, &args, 2)?;
+            let idx: usize = argument(name, &args, 0)?;
+            let len = list.borrow().len();
+            if idx > len {
+                return Err(out_of_range(name, idx, len));
+            }
+            list.borrow_mut().insert(idx, args[1].clone());
+        }
+        // Takes out the first item equal to the value, returning whether there was one. The
+        // list is only borrowed mutably once it's found, as comparing may look into the list.
+        ListLibFunctions::Remove => {
+            expect_args(name, &args, 1)?;
+            let found = list.borrow().iter().position(|item| *item == args[0]);
+            let Some(idx) = found else {
+                return Ok(Primitive::Bool(false));
+            };
+            list.borrow_mut().remove(idx);
+            return Ok(Primitive::Bool(true));
+        }
+        // Takes out and returns the item at the index, or the last one
+        ListLibFunctions::Pop => {
+            let mut list = list.borrow_mut();
+            let idx = match args.len() {
+                0 => list.len().saturating_sub(1),
+                _ => {
+                    expect_args(name, &args, 1)?;
+                    argument(name, &args, 0)?
+                }
+            };
+            if idx >= list.len() {
+                return Err(out_of_range(name, idx, list.len()));
+            }
+            return Ok(list.remove(idx));
+        }
+    }
+    return Ok(Primitive::None);
+}
+
+// range(end), range(start, end) or range(start, end, step), leaving out the end
+pub fn range(
+    args: Vec<Primitive>,
+    runtime: &mut dyn Runtime,
+) -> Result<Primitive, InterpException> {
+    let name = "range";
+    if args.len() != 1 && args.len() != 2 {
+        expect_args(name, &args, 3)?;
+    }
+    let (start, end): (i64, i64) = match args.len() {
+        1 => (0, argument(name, &args, 0)?),
+        _ => (argument(name, &args, 0)?, argument(name, &args, 1)?),
+    };
+    let step: i64 = match args.get(2) {
+        Some(_) => argument(name, &args, 2)?,
+        None => 1,
+    };
+    if step == 0 {
+        return Err(InterpException::InvalidValue(
+            name.to_string(),
+            String::from("step cannot be zero"),
+        ));
+    }
+    // Worked out in i128, since the distance between two i64s can overflow one
+    let (start, end, step) = (start as i128, end as i128, step as i128);
+    let span = if step > 0 { end - start } else { start - end };
+    let len = if span > 0 {
+        (span - 1) / step.abs() + 1
+    } else {
+        0
+    };
+    let Ok(len) = usize::try_from(len) else {
+        return Err(InterpException::InvalidValue(
+            name.to_string(),
+            format!("a range of {} items is too long", len),
+        ));
+    };
+    // Built all at once, so it's checked against the sandbox before it's allocated
+    if let Some(sandbox) = runtime.sandbox() && let Some(max_len) = sandbox.limits.max_collection_len && len > max_len {
+        return Err(InterpException::CollectionTooLarge(max_len));
+    }
+    let values = (0..len)
+        .map(|idx| Primitive::Int((start + idx as i128 * step) as isize))
+        .collect();
+    return Ok(Primitive::List(Gc::new(values)));
+}
+
+fn items(list: &Gc<Vec<Primitive>>) -> Vec<Primitive> {
+    return list.borrow().clone();
+}
+
+fn test(
+    runtime: &mut dyn Runtime,
+    name: &str,
+    predicate: &Primitive,
+    item: Primitive,
+) -> Result<bool, InterpException> {
+    let result = runtime.invoke(name, predicate.clone(), vec![item])?;
+    return operations::truthy(result);
+}
+
+fn ordering(
+    runtime: &mut dyn Runtime,
+    name: &str,
+    comparator: Option<&Primitive>,
+    left: &Primitive,
+    right: &Primitive,
+) -> Result<Ordering, InterpException> {
+    let Some(comparator) = comparator else {
+        return left.compare(right).ok_or_else(|| {
+            InterpException::InvalidValue(
+                name.to_string(),
+                format!(
+                    "cannot order {} and {}",
+                    left.type_name(),
+                    right.type_name()
+                ),
+            )
+        });
+    };
+    let mut compare = |left: &Primitive, right: &Primitive| {
+        return runtime.invoke(name, comparator.clone(), vec![left.clone(), right.clone()]);
+    };
+    match compare(left, right)? {
+        Primitive::Bool(true) => return Ok(Ordering::Less),
+        Primitive::Bool(false) => match compare(right, left)? {
+            Primitive::Bool(true) => return Ok(Ordering::Greater),
+            _ => return Ok(Ordering::Equal),
+        },
+        Primitive::Int(diff) => return Ok(diff.cmp(&0)),
+        Primitive::Float(diff) => return Ok(diff.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+        other => {
+            return Err(InterpException::InvalidValue(
+                name.to_string(),
+                format!(
+                    "the comparator returned {} instead of a bool or a number",
+                    other.type_name()
+                ),
+            ))
+        }
+    }
+}
+
+// Stable, and unlike the standard sorts it can't panic on a comparator that isn't
+// consistent, which a script's might not be
+fn merge_sort(
+    mut items: Vec<Primitive>,
+    compare: &mut dyn FnMut(&Primitive, &Primitive) -> Result<Ordering, InterpException>,
+) -> Result<Vec<Primitive>, InterpException> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, compare)?;
+    let right = merge_sort(right, compare)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(first), Some(second)) = (left.peek(), right.peek()) {
+        if compare(second, first)? == Ordering::Less {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    return Ok(merged);
+}
+
+fn out_of_range(name: &str, idx: usize, len: usize) -> InterpException {
+    return InterpException::InvalidValue(
+        name.to_string(),
+        format!("index {} is out of range for a list of {}", idx, len),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::testing::run_both;
+
+    #[test]
+    fn remove_from_a_list_holding_itself() {
+        let source = "
+            let xs = [1, 2];
+            env xs {
+                append(xs);
+                println(remove([9]));
+                println(remove(1));
+                println(len(xs));
+            }
+        ";
+        assert_eq!(run_both(source), "false\ntrue\n2\n");
+    }
+
+    #[test]
+    fn set_out_of_range_is_an_error() {
+        let source = "
+            let xs = [1];
+            env xs { set(3, 1); }
+            println(xs);
+        ";
+        assert_eq!(
+            run_both(source),
+            "Invalid argument to 'set': index 3 is out of range for a list of 1\n[1]\n"
+        );
+    }
+}
@@ -30,7 +30,7 @@ impl Math {
 
     pub fn do_func(
         func_name: MathLibFunctions,
-        param: Vec<Result<Primitive, InterpException>>,
+        param: Vec<Primitive>,
     ) -> Result<Primitive, InterpException> {
         match func_name {
             MathLibFunctions::Sin => Math::trig_op(param, func_name),
@@ -40,16 +40,16 @@ impl Math {
     }
 
     fn trig_op(
-        params: Vec<Result<Primitive, InterpException>>,
+        params: Vec<Primitive>,
         func_name: MathLibFunctions,
     ) -> Result<Primitive, InterpException> {
-        if let Primitive::Int(angle) = params[0].clone()? {
+        if let Primitive::Int(angle) = params[0] {
             match func_name {
                 MathLibFunctions::Sin => return Ok(Primitive::Float((angle as f32).sin())),
                 MathLibFunctions::Cos => return Ok(Primitive::Float((angle as f32).cos())),
                 MathLibFunctions::Tan => return Ok(Primitive::Float((angle as f32).tan())),
             }
-        } else if let Primitive::Float(angle) = params[0].clone()? {
+        } else if let Primitive::Float(angle) = params[0] {
             match func_name {
                 MathLibFunctions::Sin => return Ok(Primitive::Float(angle.sin())),
                 MathLibFunctions::Cos => return Ok(Primitive::Float(angle.cos())),
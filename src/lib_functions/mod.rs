@@ -1,37 +1,533 @@
-use crate::interpreter::environment::Environment;
+use std::collections::HashMap;
 
-use self::math::Math;
+use crate::{
+    error_reporting::interp_err::InterpException,
+    heap::heap::Gc,
+    interpreter::environment::Environment,
+    scanner::token::Primitive,
+};
+
+use self::{
+    collections::Collections,
+    fs::FileSystem,
+    gc::Collector,
+    json::Json,
+    list_ops::{append, call_method, len, range, set, slice},
+    math::Math,
+    native::{argument, expect_args, HostFunction},
+    random::Random,
+    regex::Regex,
+    runtime::Runtime,
+    sys::System,
+    time::Time,
+};
 
 pub mod cast_ops;
+pub mod collections;
+pub mod convert;
+pub mod fs;
+pub mod gc;
+pub mod json;
 pub mod list_ops;
 pub mod math;
+pub mod native;
+pub mod random;
+pub mod regex;
+pub mod runtime;
+pub mod sys;
+pub mod time;
+
+// Functions every script can call without importing anything. The interpreter defines them
+// as constants, and the passes before it treat the names as declared.
+pub const GLOBAL_FUNCTIONS: [(&str, LibFunctions); 5] = [
+    ("len", LibFunctions::Len),
+    ("int", LibFunctions::Int),
+    ("float", LibFunctions::Float),
+    ("str", LibFunctions::String),
+    ("range", LibFunctions::Range),
+];
 
-pub const BUILTINS: [&str; 1] = ["math"];
+pub const BUILTINS: [&str; 9] = [
+    "math",
+    "gc",
+    "fs",
+    "sys",
+    "json",
+    "time",
+    "random",
+    "regex",
+    "collections",
+];
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum LibFunctions {
     Append,
     Set,
     Len,
     Slice,
+    List(ListLibFunctions),
+    Range,
     Math(MathLibFunctions),
+    Gc(GcLibFunctions),
+    Fs(FsLibFunctions),
+    Sys(SysLibFunctions),
+    Json(JsonLibFunctions),
+    Time(TimeLibFunctions),
+    Random(RandomLibFunctions),
+    Regex(RegexLibFunctions),
+    Collections(CollectionsLibFunctions),
+    Host(HostFunction),
     Int,
     Float,
     String,
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListLibFunctions {
+    Map,
+    Filter,
+    Reduce,
+    Any,
+    All,
+    Find,
+    Sort,
+    Reverse,
+    Zip,
+    Enumerate,
+    Contains,
+    IndexOf,
+    Insert,
+    Remove,
+    Pop,
+}
+
+impl ListLibFunctions {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "map" => return Some(ListLibFunctions::Map),
+            "filter" => return Some(ListLibFunctions::Filter),
+            "reduce" => return Some(ListLibFunctions::Reduce),
+            "any" => return Some(ListLibFunctions::Any),
+            "all" => return Some(ListLibFunctions::All),
+            "find" => return Some(ListLibFunctions::Find),
+            "sort" => return Some(ListLibFunctions::Sort),
+            "reverse" => return Some(ListLibFunctions::Reverse),
+            "zip" => return Some(ListLibFunctions::Zip),
+            "enumerate" => return Some(ListLibFunctions::Enumerate),
+            "contains" => return Some(ListLibFunctions::Contains),
+            "index_of" => return Some(ListLibFunctions::IndexOf),
+            "insert" => return Some(ListLibFunctions::Insert),
+            "remove" => return Some(ListLibFunctions::Remove),
+            "pop" => return Some(ListLibFunctions::Pop),
+            _ => return None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ListLibFunctions::Map => "map",
+            ListLibFunctions::Filter => "filter",
+            ListLibFunctions::Reduce => "reduce",
+            ListLibFunctions::Any => "any",
+            ListLibFunctions::All => "all",
+            ListLibFunctions::Find => "find",
+            ListLibFunctions::Sort => "sort",
+            ListLibFunctions::Reverse => "reverse",
+            ListLibFunctions::Zip => "zip",
+            ListLibFunctions::Enumerate => "enumerate",
+            ListLibFunctions::Contains => "contains",
+            ListLibFunctions::IndexOf => "index_of",
+            ListLibFunctions::Insert => "insert",
+            ListLibFunctions::Remove => "remove",
+            ListLibFunctions::Pop => "pop",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum MathLibFunctions {
     Sin,
     Cos,
     Tan,
 }
 
-pub fn import_lib(import_name: &str) -> Environment {
+#[derive(Clone, Debug, PartialEq)]
+pub enum GcLibFunctions {
+    Collect,
+    Stats,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FsLibFunctions {
+    Read,
+    Write,
+    Append,
+    ReadBytes,
+    WriteBytes,
+    AppendBytes,
+    ListDir,
+    Exists,
+    Join,
+    Mkdir,
+    Remove,
+    Metadata,
+}
+
+impl FsLibFunctions {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FsLibFunctions::Read => "fs.read",
+            FsLibFunctions::Write => "fs.write",
+            FsLibFunctions::Append => "fs.append",
+            FsLibFunctions::ReadBytes => "fs.read_bytes",
+            FsLibFunctions::WriteBytes => "fs.write_bytes",
+            FsLibFunctions::AppendBytes => "fs.append_bytes",
+            FsLibFunctions::ListDir => "fs.list_dir",
+            FsLibFunctions::Exists => "fs.exists",
+            FsLibFunctions::Join => "fs.join",
+            FsLibFunctions::Mkdir => "fs.mkdir",
+            FsLibFunctions::Remove => "fs.remove",
+            FsLibFunctions::Metadata => "fs.metadata",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SysLibFunctions {
+    Args,
+    GetEnv,
+    ReadLine,
+    ReadAll,
+    Exit,
+}
+
+impl SysLibFunctions {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SysLibFunctions::Args => "sys.args",
+            SysLibFunctions::GetEnv => "sys.get_env",
+            SysLibFunctions::ReadLine => "sys.read_line",
+            SysLibFunctions::ReadAll => "sys.read_all",
+            SysLibFunctions::Exit => "sys.exit",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonLibFunctions {
+    Parse,
+    Stringify,
+}
+
+impl JsonLibFunctions {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JsonLibFunctions::Parse => "json.parse",
+            JsonLibFunctions::Stringify => "json.stringify",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TimeLibFunctions {
+    Now,
+    Monotonic,
+    Sleep,
+    Seconds,
+    Minutes,
+    Hours,
+    Days,
+    Format,
+    Parse,
+    Date,
+}
+
+impl TimeLibFunctions {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimeLibFunctions::Now => "time.now",
+            TimeLibFunctions::Monotonic => "time.monotonic",
+            TimeLibFunctions::Sleep => "time.sleep",
+            TimeLibFunctions::Seconds => "time.seconds",
+            TimeLibFunctions::Minutes => "time.minutes",
+            TimeLibFunctions::Hours => "time.hours",
+            TimeLibFunctions::Days => "time.days",
+            TimeLibFunctions::Format => "time.format",
+            TimeLibFunctions::Parse => "time.parse",
+            TimeLibFunctions::Date => "time.date",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RandomLibFunctions {
+    Seed,
+    Uniform,
+    Randint,
+    Choice,
+    Shuffle,
+    Sample,
+}
+
+impl RandomLibFunctions {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RandomLibFunctions::Seed => "random.seed",
+            RandomLibFunctions::Uniform => "random.uniform",
+            RandomLibFunctions::Randint => "random.randint",
+            RandomLibFunctions::Choice => "random.choice",
+            RandomLibFunctions::Shuffle => "random.shuffle",
+            RandomLibFunctions::Sample => "random.sample",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegexLibFunctions {
+    Compile,
+    IsMatch,
+    Find,
+    FindAll,
+    Split,
+    Replace,
+    Escape,
+}
+
+impl RegexLibFunctions {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RegexLibFunctions::Compile => "regex.compile",
+            RegexLibFunctions::IsMatch => "regex.is_match",
+            RegexLibFunctions::Find => "regex.find",
+            RegexLibFunctions::FindAll => "regex.find_all",
+            RegexLibFunctions::Split => "regex.split",
+            RegexLibFunctions::Replace => "regex.replace",
+            RegexLibFunctions::Escape => "regex.escape",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CollectionsLibFunctions {
+    Set,
+    Deque,
+    Add,
+    Remove,
+    Contains,
+    Union,
+    Intersection,
+    Difference,
+    PushFront,
+    PushBack,
+    PopFront,
+    PopBack,
+    Front,
+    Back,
+    ToList,
+}
+
+impl CollectionsLibFunctions {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CollectionsLibFunctions::Set => "collections.set",
+            CollectionsLibFunctions::Deque => "collections.deque",
+            CollectionsLibFunctions::Add => "collections.add",
+            CollectionsLibFunctions::Remove => "collections.remove",
+            CollectionsLibFunctions::Contains => "collections.contains",
+            CollectionsLibFunctions::Union => "collections.union",
+            CollectionsLibFunctions::Intersection => "collections.intersection",
+            CollectionsLibFunctions::Difference => "collections.difference",
+            CollectionsLibFunctions::PushFront => "collections.push_front",
+            CollectionsLibFunctions::PushBack => "collections.push_back",
+            CollectionsLibFunctions::PopFront => "collections.pop_front",
+            CollectionsLibFunctions::PopBack => "collections.pop_back",
+            CollectionsLibFunctions::Front => "collections.front",
+            CollectionsLibFunctions::Back => "collections.back",
+            CollectionsLibFunctions::ToList => "collections.to_list",
+        }
+    }
+}
+
+// The env of the builtin module with this name, if there is one
+pub fn import_lib(import_name: &str) -> Option<Environment> {
     match import_name {
         "math" => {
-            return Math::new();
+            return Some(Math::new());
+        }
+        "gc" => {
+            return Some(Collector::new());
+        }
+        "fs" => {
+            return Some(FileSystem::new());
         }
-        _ => todo!(),
+        "sys" => {
+            return Some(System::new());
+        }
+        "json" => {
+            return Some(Json::new());
+        }
+        "time" => {
+            return Some(Time::new());
+        }
+        "random" => {
+            return Some(Random::new());
+        }
+        "regex" => {
+            return Some(Regex::new());
+        }
+        "collections" => {
+            return Some(Collections::new());
+        }
+        _ => return None,
     }
 }
+
+// Runs a native function on already evaluated arguments. List methods find the list
+// they were called on through the "list" field of the env they're looked up in. Those
+// doing I/O check what they touch against the runtime's permissions.
+pub fn call_native(
+    func: &LibFunctions,
+    args: Vec<Primitive>,
+    runtime: &mut dyn Runtime,
+) -> Result<Primitive, InterpException> {
+    let env = runtime.globals();
+    match func {
+        LibFunctions::Append => {
+            if let Primitive::String(ident) = env.retrieve("list")? && let Primitive::List(list) = env.retrieve(&ident)? {
+                for arg in args.into_iter() {
+                    append(&mut list.borrow_mut(), arg);
+                }
+            };
+        }
+        LibFunctions::Set => {
+            if let Primitive::String(ident) = env.retrieve("list")? && let Primitive::List(list) = env.retrieve(&ident)? {
+                expect_args("set", &args, 2)?;
+                let idx: usize = argument("set", &args, 0)?;
+                set(&mut list.borrow_mut(), idx, args[1].clone())?;
+            };
+        }
+        LibFunctions::Len => {
+            if args.len() == 1 {
+                if let Some(list) = list_of(&args[0]) {
+                    return Ok(len(&list.borrow()));
+                }
+                match &args[0] {
+                    Primitive::Set(set) => return Ok(Primitive::Int(set.borrow().len() as isize)),
+                    Primitive::Deque(deque) => {
+                        return Ok(Primitive::Int(deque.borrow().items.len() as isize))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        LibFunctions::Slice => {
+            if args.len() == 2 && let Primitive::String(ident) = env.retrieve("list")? {
+                let list = env.retrieve(&ident)?;
+                if let Primitive::List(list_uw) = list && let Primitive::Int(idx1) = args[0] && let Primitive::Int(idx2) = args[1] {
+                    let vec = slice(&list_uw.borrow(), idx1 as usize, idx2 as usize);
+                    return Ok(Primitive::List(Gc::new(vec)));
+                }
+            }
+        }
+        LibFunctions::List(method) => {
+            let Primitive::String(ident) = env.retrieve("list")? else {
+                return Err(InterpException::IdentifierNoExist(String::from("list")));
+            };
+            let Some(list) = list_of(&env.retrieve(&ident)?) else {
+                return Err(InterpException::IdentifierNoExist(ident));
+            };
+            return call_method(method, list, args, runtime);
+        }
+        LibFunctions::Range => return range(args, runtime),
+        LibFunctions::Math(var) => {
+            return Math::do_func(var.clone(), args);
+        }
+        LibFunctions::Gc(var) => {
+            return Collector::do_func(var.clone());
+        }
+        LibFunctions::Fs(var) => {
+            return FileSystem::do_func(var.clone(), args, runtime.permissions());
+        }
+        LibFunctions::Sys(var) => {
+            return System::do_func(var.clone(), args, runtime);
+        }
+        LibFunctions::Json(var) => {
+            return Json::do_func(var.clone(), args);
+        }
+        LibFunctions::Time(var) => {
+            return Time::do_func(var.clone(), args, runtime);
+        }
+        LibFunctions::Random(var) => {
+            return Random::do_func(var.clone(), args, runtime);
+        }
+        LibFunctions::Regex(var) => {
+            return Regex::do_func(var.clone(), args);
+        }
+        LibFunctions::Collections(var) => {
+            return Collections::do_func(var.clone(), args);
+        }
+        LibFunctions::Host(func) => return func.call(args),
+        LibFunctions::Int => {
+            expect_args("int", &args, 1)?;
+            return Ok(cast_ops::int(args[0].clone()));
+        }
+        LibFunctions::String => {
+            expect_args("string", &args, 1)?;
+            return cast_ops::string(args[0].clone(), runtime);
+        }
+        LibFunctions::Float => {
+            expect_args("float", &args, 1)?;
+            return Ok(cast_ops::float(args[0].clone()));
+        }
+    }
+    return Ok(Primitive::None);
+}
+
+// The list a value holds, whether it's a bare list or one declared with `let`
+pub fn list_of(prim: &Primitive) -> Option<Gc<Vec<Primitive>>> {
+    match prim {
+        Primitive::List(list) => return Some(list.clone()),
+        Primitive::Env(env) => {
+            let env = env.borrow();
+            if let Ok(Primitive::String(ident)) = env.retrieve("list") && let Ok(Primitive::List(list)) = env.retrieve(&ident) {
+                return Some(list);
+            }
+            return None;
+        }
+        _ => return None,
+    }
+}
+
+// The values a for loop goes over, copied so the loop isn't thrown off by changes to them
+pub fn items_of(prim: &Primitive) -> Option<Vec<Primitive>> {
+    match prim {
+        Primitive::Set(set) => return Some(set.borrow().iter().cloned().collect()),
+        Primitive::Deque(deque) => return Some(deque.borrow().items.iter().cloned().collect()),
+        _ => return list_of(prim).map(|list| list.borrow().clone()),
+    }
+}
+
+// Lists are stored as an env holding the list itself plus its first few methods. The
+// rest are found by name when they're looked up, see `Environment::retrieve`.
+pub fn list_env(ident: &str, list: Gc<Vec<Primitive>>) -> Environment {
+    let mut vars = HashMap::new();
+    vars.insert(String::from("list"), Primitive::String(ident.to_string()));
+    vars.insert(ident.to_string(), Primitive::List(list));
+    vars.insert(
+        String::from("set"),
+        Primitive::NativeFunc(LibFunctions::Set),
+    );
+    vars.insert(
+        String::from("len"),
+        Primitive::NativeFunc(LibFunctions::Len),
+    );
+    vars.insert(
+        String::from("append"),
+        Primitive::NativeFunc(LibFunctions::Append),
+    );
+    vars.insert(
+        String::from("slice"),
+        Primitive::NativeFunc(LibFunctions::Slice),
+    );
+    return Environment::with_vars(vars);
+}
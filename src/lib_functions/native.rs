@@ -0,0 +1,171 @@
+use std::{fmt, rc::Rc};
+
+use crate::{
+    error_reporting::interp_err::InterpException, heap::heap::Gc, scanner::token::Primitive,
+};
+
+use super::{
+    convert::{FromPrimitive, IntoPrimitive},
+    list_of,
+};
+
+pub type NativeFn = Rc<dyn Fn(Vec<Primitive>) -> Result<Primitive, InterpException>>;
+
+// A Rust closure registered by the host, called like any other native function
+#[derive(Clone)]
+pub struct HostFunction {
+    pub name: String,
+    func: NativeFn,
+}
+
+impl HostFunction {
+    pub fn new<Args>(name: &str, func: impl IntoNative<Args>) -> Self {
+        return Self {
+            name: name.to_string(),
+            func: func.into_native(name),
+        };
+    }
+
+    // Takes the arguments as they are, however many the script passed
+    pub fn variadic(
+        name: &str,
+        func: impl Fn(Vec<Primitive>) -> Result<Primitive, InterpException> + 'static,
+    ) -> Self {
+        return Self {
+            name: name.to_string(),
+            func: Rc::new(func),
+        };
+    }
+
+    pub fn call(&self, args: Vec<Primitive>) -> Result<Primitive, InterpException> {
+        return (self.func)(args);
+    }
+}
+
+// Host functions are equal only if they're the same registration
+impl PartialEq for HostFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+    }
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HostFunction({})", self.name)
+    }
+}
+
+// What a host function can return: a value, or a value or an error
+pub trait NativeReturn {
+    fn into_result(self) -> Result<Primitive, InterpException>;
+}
+
+impl<T: IntoPrimitive> NativeReturn for T {
+    fn into_result(self) -> Result<Primitive, InterpException> {
+        return Ok(self.into_primitive());
+    }
+}
+
+impl<T: IntoPrimitive> NativeReturn for Result<T, InterpException> {
+    fn into_result(self) -> Result<Primitive, InterpException> {
+        return self.map(IntoPrimitive::into_primitive);
+    }
+}
+
+// Closures taking arguments that convert from primitives. `Args` is the tuple of
+// argument types, which only serves to tell the implementations for each arity apart.
+pub trait IntoNative<Args> {
+    fn into_native(self, name: &str) -> NativeFn;
+}
+
+macro_rules! into_native {
+    ($count:expr; $($arg:ident $var:ident),*) => {
+        impl<Func, Ret, $($arg),*> IntoNative<($($arg,)*)> for Func
+        where
+            Func: Fn($($arg),*) -> Ret + 'static,
+            Ret: NativeReturn,
+            $($arg: FromPrimitive,)*
+        {
+            #[allow(unused_mut, unused_variables)]
+            fn into_native(self, name: &str) -> NativeFn {
+                let name = name.to_string();
+                return Rc::new(move |args: Vec<Primitive>| {
+                    if args.len() != $count {
+                        return Err(InterpException::WrongArgumentCount(name.clone(), $count, args.len()));
+                    }
+                    let mut args = args.into_iter().enumerate();
+                    $(
+                        let (position, arg) = args.next().unwrap();
+                        let $var = $arg::from_primitive(arg)
+                            .map_err(|err| argument_error(&name, position, err))?;
+                    )*
+                    return (self)($($var),*).into_result();
+                });
+            }
+        }
+    };
+}
+
+into_native!(0;);
+into_native!(1; A1 a1);
+into_native!(2; A1 a1, A2 a2);
+into_native!(3; A1 a1, A2 a2, A3 a3);
+into_native!(4; A1 a1, A2 a2, A3 a3, A4 a4);
+into_native!(5; A1 a1, A2 a2, A3 a3, A4 a4, A5 a5);
+into_native!(6; A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6);
+
+// For natives that take their arguments as they come, converting each one themselves
+pub fn expect_args(name: &str, args: &[Primitive], count: usize) -> Result<(), InterpException> {
+    if args.len() != count {
+        return Err(InterpException::WrongArgumentCount(name.to_string(), count, args.len()));
+    }
+    return Ok(());
+}
+
+pub fn argument<T: FromPrimitive>(
+    name: &str,
+    args: &[Primitive],
+    position: usize,
+) -> Result<T, InterpException> {
+    let arg = args.get(position).cloned().unwrap_or(Primitive::None);
+    return T::from_primitive(arg).map_err(|err| argument_error(name, position, err));
+}
+
+// Lists are accepted bare or as declared with `let`, and are shared rather than copied
+pub fn list_argument(
+    name: &str,
+    args: &[Primitive],
+    position: usize,
+) -> Result<Gc<Vec<Primitive>>, InterpException> {
+    let arg = args.get(position).unwrap_or(&Primitive::None);
+    return list_of(arg).ok_or_else(|| {
+        InterpException::InvalidArgument(name.to_string(), position + 1, "list", arg.type_name())
+    });
+}
+
+// Script functions and native ones alike, for natives that call back into the script
+pub fn function_argument(
+    name: &str,
+    args: &[Primitive],
+    position: usize,
+) -> Result<Primitive, InterpException> {
+    match args.get(position) {
+        Some(func @ (Primitive::Func(_) | Primitive::NativeFunc(_))) => return Ok(func.clone()),
+        arg => {
+            let found = arg.map_or("null", Primitive::type_name);
+            return Err(InterpException::InvalidArgument(
+                name.to_string(),
+                position + 1,
+                "func",
+                found,
+            ));
+        }
+    }
+}
+
+fn argument_error(name: &str, position: usize, err: InterpException) -> InterpException {
+    if let InterpException::InvalidConversion(expected, found) = err {
+        return InterpException::InvalidArgument(name.to_string(), position + 1, expected, found);
+    }
+    return err;
+}
@@ -0,0 +1,200 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    error_reporting::interp_err::InterpException, heap::heap::Gc,
+    interpreter::environment::Environment, scanner::token::Primitive,
+};
+
+use super::{
+    native::{argument, expect_args, list_argument},
+    runtime::Runtime,
+    LibFunctions, RandomLibFunctions,
+};
+
+// How many draws uniform makes before settling for the low end of the range
+const UNIFORM_TRIES: usize = 64;
+
+// xoshiro256**, which only needs integer arithmetic, so a seed gives the same
+// numbers everywhere
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    pub fn seeded(seed: u64) -> Self {
+        // Spreads the seed over the whole state, which mustn't be all zeros
+        let mut mix = seed;
+        let mut state = [0; 4];
+        for word in state.iter_mut() {
+            mix = mix.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = mix;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            *word = z ^ (z >> 31);
+        }
+        return Self { state };
+    }
+
+    // Seeded from the clock, for when runs don't need to be repeatable
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_nanos() as u64)
+            .unwrap_or_default();
+        return Self::seeded(nanos);
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.state[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let shifted = self.state[1] << 17;
+        self.state[2] ^= self.state[0];
+        self.state[3] ^= self.state[1];
+        self.state[1] ^= self.state[2];
+        self.state[0] ^= self.state[3];
+        self.state[2] ^= shifted;
+        self.state[3] = self.state[3].rotate_left(45);
+        return result;
+    }
+
+    // Uniform in [0, 1), from the top 53 bits
+    pub fn next_f64(&mut self) -> f64 {
+        return (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+    }
+
+    // Uniform in [0, bound), without the bias taking the remainder would have
+    pub fn below(&mut self, bound: u64) -> u64 {
+        let limit = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < limit {
+                return value % bound;
+            }
+        }
+    }
+
+    // Uniform in [low, high], both ends included
+    pub fn between(&mut self, low: i64, high: i64) -> i64 {
+        let span = high.wrapping_sub(low) as u64;
+        if span == u64::MAX {
+            return self.next_u64() as i64;
+        }
+        return low.wrapping_add(self.below(span + 1) as i64);
+    }
+}
+
+pub struct Random;
+
+impl Random {
+    pub fn new() -> Environment {
+        let mut ret_env = Environment::new();
+        for (name, func) in [
+            ("seed", RandomLibFunctions::Seed),
+            ("uniform", RandomLibFunctions::Uniform),
+            ("randint", RandomLibFunctions::Randint),
+            ("choice", RandomLibFunctions::Choice),
+            ("shuffle", RandomLibFunctions::Shuffle),
+            ("sample", RandomLibFunctions::Sample),
+        ] {
+            ret_env.define(name, Primitive::NativeFunc(LibFunctions::Random(func)));
+        }
+        return ret_env;
+    }
+
+    pub fn do_func(
+        func_name: RandomLibFunctions,
+        args: Vec<Primitive>,
+        runtime: &mut dyn Runtime,
+    ) -> Result<Primitive, InterpException> {
+        let name = func_name.name();
+        let rng = runtime.rng();
+        match func_name {
+            RandomLibFunctions::Seed => {
+                expect_args(name, &args, 1)?;
+                let seed: i64 = argument(name, &args, 0)?;
+                *rng = Rng::seeded(seed as u64);
+            }
+            // Uniform in [low, high), or in [0, 1) without arguments
+            RandomLibFunctions::Uniform => {
+                if args.is_empty() {
+                    return Ok(Primitive::Float(rng.next_f64() as f32));
+                }
+                expect_args(name, &args, 2)?;
+                let low: f64 = argument(name, &args, 0)?;
+                let high: f64 = argument(name, &args, 1)?;
+                // Infinite ends would never give a number inside the range
+                if !(low < high) || !(high - low).is_finite() {
+                    return Err(range_error(name, low, high));
+                }
+                // Rounding to a float can land on high, which isn't in the range. A range
+                // too narrow to hit anything else gives low.
+                for _ in 0..UNIFORM_TRIES {
+                    let value = (low + rng.next_f64() * (high - low)) as f32;
+                    if (value as f64) < high {
+                        return Ok(Primitive::Float(value));
+                    }
+                }
+                return Ok(Primitive::Float(low as f32));
+            }
+            // Both ends are included
+            RandomLibFunctions::Randint => {
+                expect_args(name, &args, 2)?;
+                let low: i64 = argument(name, &args, 0)?;
+                let high: i64 = argument(name, &args, 1)?;
+                if low > high {
+                    return Err(range_error(name, low, high));
+                }
+                return Ok(Primitive::Int(rng.between(low, high) as isize));
+            }
+            RandomLibFunctions::Choice => {
+                expect_args(name, &args, 1)?;
+                let list = list_argument(name, &args, 0)?;
+                let list = list.borrow();
+                if list.is_empty() {
+                    return Err(InterpException::InvalidValue(
+                        name.to_string(),
+                        String::from("cannot choose from an empty list"),
+                    ));
+                }
+                return Ok(list[rng.below(list.len() as u64) as usize].clone());
+            }
+            // Shuffles the list itself, so every reference to it sees the new order
+            RandomLibFunctions::Shuffle => {
+                expect_args(name, &args, 1)?;
+                let list = list_argument(name, &args, 0)?;
+                let mut list = list.borrow_mut();
+                for idx in (1..list.len()).rev() {
+                    let other = rng.below(idx as u64 + 1) as usize;
+                    list.swap(idx, other);
+                }
+            }
+            // Picks count different positions of the list, in random order
+            RandomLibFunctions::Sample => {
+                expect_args(name, &args, 2)?;
+                let list = list_argument(name, &args, 0)?;
+                let count: usize = argument(name, &args, 1)?;
+                let mut items = list.borrow().clone();
+                if count > items.len() {
+                    return Err(InterpException::InvalidValue(
+                        name.to_string(),
+                        format!("cannot take {} items from a list of {}", count, items.len()),
+                    ));
+                }
+                for idx in 0..count {
+                    let other = idx + rng.below((items.len() - idx) as u64) as usize;
+                    items.swap(idx, other);
+                }
+                items.truncate(count);
+                return Ok(Primitive::List(Gc::new(items)));
+            }
+        }
+        return Ok(Primitive::None);
+    }
+}
+
+fn range_error<T: std::fmt::Display>(name: &str, low: T, high: T) -> InterpException {
+    return InterpException::InvalidValue(
+        name.to_string(),
+        format!("empty range from {} to {}", low, high),
+    );
+}
@@ -0,0 +1,912 @@
+use std::{mem, rc::Rc};
+
+use crate::{
+    error_reporting::interp_err::InterpException, heap::heap::Gc,
+    interpreter::environment::Environment, scanner::token::Primitive,
+};
+
+use super::{
+    native::{argument, expect_args},
+    LibFunctions, RegexLibFunctions,
+};
+
+// Counted repeats are compiled into a copy of what they repeat per count, so both
+// are bounded to keep a short pattern from taking up all of memory
+const MAX_REPEAT: u32 = 1000;
+const MAX_PROGRAM: usize = 20_000;
+
+pub struct Regex;
+
+impl Regex {
+    pub fn new() -> Environment {
+        let mut ret_env = Environment::new();
+        for (name, func) in [
+            ("compile", RegexLibFunctions::Compile),
+            ("is_match", RegexLibFunctions::IsMatch),
+            ("find", RegexLibFunctions::Find),
+            ("find_all", RegexLibFunctions::FindAll),
+            ("split", RegexLibFunctions::Split),
+            ("replace", RegexLibFunctions::Replace),
+            ("escape", RegexLibFunctions::Escape),
+        ] {
+            ret_env.define(name, Primitive::NativeFunc(LibFunctions::Regex(func)));
+        }
+        return ret_env;
+    }
+
+    // Every function but escape takes the pattern first. compile returns an env with the
+    // other functions bound to the compiled pattern, for patterns used more than once.
+    pub fn do_func(
+        func_name: RegexLibFunctions,
+        args: Vec<Primitive>,
+    ) -> Result<Primitive, InterpException> {
+        let name = func_name.name();
+        if let RegexLibFunctions::Escape = func_name {
+            expect_args(name, &args, 1)?;
+            let text: String = argument(name, &args, 0)?;
+            return Ok(Primitive::String(escape(&text)));
+        }
+        if args.is_empty() {
+            return Err(InterpException::WrongArgumentCount(name.to_string(), 1, 0));
+        }
+        let pattern: String = argument(name, &args, 0)?;
+        let regex = Rc::new(Pattern::new(&pattern)?);
+        if let RegexLibFunctions::Compile = func_name {
+            expect_args(name, &args, 1)?;
+            return Ok(Primitive::Env(Gc::new(compiled_env(regex))));
+        }
+        return apply(&func_name, &regex, &args, 1);
+    }
+}
+
+fn compiled_env(regex: Rc<Pattern>) -> Environment {
+    let mut ret_env = Environment::new();
+    ret_env.define("pattern", Primitive::String(regex.pattern.clone()));
+    for func in [
+        RegexLibFunctions::IsMatch,
+        RegexLibFunctions::Find,
+        RegexLibFunctions::FindAll,
+        RegexLibFunctions::Split,
+        RegexLibFunctions::Replace,
+    ] {
+        let regex = regex.clone();
+        let method = func.name().trim_start_matches("regex.");
+        ret_env.define_native_variadic(method, move |args| apply(&func, &regex, &args, 0));
+    }
+    return ret_env;
+}
+
+// Runs a function on the arguments after the pattern, which start at `first`
+fn apply(
+    func_name: &RegexLibFunctions,
+    regex: &Pattern,
+    args: &[Primitive],
+    first: usize,
+) -> Result<Primitive, InterpException> {
+    let name = func_name.name();
+    let count = match func_name {
+        RegexLibFunctions::Replace => 2,
+        _ => 1,
+    };
+    expect_args(name, args, first + count)?;
+    let text: String = argument(name, args, first)?;
+    let text: Vec<char> = text.chars().collect();
+    match func_name {
+        RegexLibFunctions::IsMatch => {
+            return Ok(Primitive::Bool(regex.captures_at(&text, 0).is_some()));
+        }
+        RegexLibFunctions::Find => match regex.captures_at(&text, 0) {
+            Some(caps) => return Ok(match_env(regex, &text, &caps)),
+            None => return Ok(Primitive::None),
+        },
+        RegexLibFunctions::FindAll => {
+            let found = regex
+                .captures_iter(&text)
+                .iter()
+                .map(|caps| match_env(regex, &text, caps))
+                .collect();
+            return Ok(Primitive::List(Gc::new(found)));
+        }
+        // Splits at the same matches replace replaces, empty ones included
+        RegexLibFunctions::Split => {
+            let mut pieces = Vec::new();
+            let mut last = 0;
+            for caps in regex.captures_iter(&text) {
+                pieces.push(Primitive::String(text[last..caps[0].unwrap()].iter().collect()));
+                last = caps[1].unwrap();
+            }
+            pieces.push(Primitive::String(text[last..].iter().collect()));
+            return Ok(Primitive::List(Gc::new(pieces)));
+        }
+        // $0 is the whole match, $1 and up the groups and ${name} a named group
+        RegexLibFunctions::Replace => {
+            let replacement: String = argument(name, args, first + 1)?;
+            let replacement: Vec<char> = replacement.chars().collect();
+            let mut replaced = String::new();
+            let mut last = 0;
+            for caps in regex.captures_iter(&text) {
+                let start = caps[0].unwrap();
+                replaced.extend(&text[last..start]);
+                expand(regex, &text, &caps, &replacement, &mut replaced);
+                last = caps[1].unwrap();
+            }
+            replaced.extend(&text[last..]);
+            return Ok(Primitive::String(replaced));
+        }
+        RegexLibFunctions::Compile | RegexLibFunctions::Escape => unreachable!(),
+    }
+}
+
+// What a match found: the matched text, where it starts and ends in characters, the
+// text of each group (null if it took no part in the match) and the named groups
+fn match_env(regex: &Pattern, text: &[char], caps: &[Option<usize>]) -> Primitive {
+    let mut ret_env = Environment::new();
+    ret_env.define("text", group_text(text, caps, 0));
+    ret_env.define("start", Primitive::Int(caps[0].unwrap() as isize));
+    ret_env.define("end", Primitive::Int(caps[1].unwrap() as isize));
+    let groups = (1..=regex.groups)
+        .map(|group| group_text(text, caps, group))
+        .collect();
+    ret_env.define("groups", Primitive::List(Gc::new(groups)));
+    let mut named = Environment::new();
+    for (group_name, group) in regex.names.iter() {
+        named.define(group_name, group_text(text, caps, *group));
+    }
+    ret_env.define("named", Primitive::Env(Gc::new(named)));
+    return Primitive::Env(Gc::new(ret_env));
+}
+
+fn group_text(text: &[char], caps: &[Option<usize>], group: usize) -> Primitive {
+    match (caps[2 * group], caps[2 * group + 1]) {
+        (Some(start), Some(end)) => return Primitive::String(text[start..end].iter().collect()),
+        _ => return Primitive::None,
+    }
+}
+
+// A $ that isn't followed by a group is kept as it is
+fn expand(
+    regex: &Pattern,
+    text: &[char],
+    caps: &[Option<usize>],
+    replacement: &[char],
+    out: &mut String,
+) {
+    let mut current = 0;
+    while current < replacement.len() {
+        let ch = replacement[current];
+        current += 1;
+        if ch != '$' {
+            out.push(ch);
+            continue;
+        }
+        let digits: String = replacement[current..]
+            .iter()
+            .take_while(|ch| ch.is_ascii_digit())
+            .collect();
+        let group = if !digits.is_empty() {
+            current += digits.len();
+            digits.parse().ok()
+        } else if replacement.get(current) == Some(&'{') && let Some(close) = replacement[current..].iter().position(|ch| *ch == '}') {
+            let group_name: String = replacement[current + 1..current + close].iter().collect();
+            current += close + 1;
+            regex.group_index(&group_name)
+        } else {
+            out.push('$');
+            continue;
+        };
+        if let Some(group) = group && group <= regex.groups && let Primitive::String(found) = group_text(text, caps, group) {
+            out.push_str(&found);
+        }
+    }
+}
+
+// Backslashes everything with a meaning in a pattern
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        if "\\.+*?()|[]{}^$-".contains(ch) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    return escaped;
+}
+
+// A compiled pattern. Matching runs every way through the pattern side by side
+// (a Pike VM), so it takes time linear in the text however the pattern is written,
+// and picks the same match a backtracking engine would.
+#[derive(Debug)]
+struct Pattern {
+    pattern: String,
+    program: Vec<Inst>,
+    groups: usize,
+    names: Vec<(String, usize)>,
+}
+
+impl Pattern {
+    fn new(pattern: &str) -> Result<Self, InterpException> {
+        let invalid = |(position, reason)| {
+            InterpException::InvalidPattern(pattern.to_string(), position, reason)
+        };
+        let mut parser = PatternParser {
+            chars: pattern.chars().collect(),
+            current: 0,
+            groups: 0,
+            names: Vec::new(),
+        };
+        let node = parser.alternation().map_err(invalid)?;
+        if parser.current < parser.chars.len() {
+            return Err(invalid((parser.current, "unmatched ')'")));
+        }
+
+        let mut compiler = PatternCompiler {
+            program: vec![Inst::Save(0)],
+        };
+        compiler.emit(&node).map_err(invalid)?;
+        compiler.program.push(Inst::Save(1));
+        compiler.program.push(Inst::Match);
+        return Ok(Self {
+            pattern: pattern.to_string(),
+            program: compiler.program,
+            groups: parser.groups,
+            names: parser.names,
+        });
+    }
+
+    fn group_index(&self, name: &str) -> Option<usize> {
+        return self
+            .names
+            .iter()
+            .find(|(group_name, _)| group_name == name)
+            .map(|(_, group)| *group);
+    }
+
+    // The start and end of the whole match and of every group, for the leftmost match
+    // at or after `start`
+    fn captures_at(&self, text: &[char], start: usize) -> Option<Vec<Option<usize>>> {
+        let slots = 2 * (self.groups + 1);
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut matched = None;
+        let mut pos = start;
+        loop {
+            // Threads started further left take precedence over this one
+            if matched.is_none() {
+                self.add(&mut current, 0, pos, vec![None; slots], text);
+            }
+            if current.list.is_empty() {
+                break;
+            }
+            let ch = text.get(pos).copied();
+            for (pc, caps) in current.list.drain(..) {
+                let advance = match &self.program[pc] {
+                    Inst::Char(expected) => ch == Some(*expected),
+                    Inst::Any => ch.is_some_and(|ch| ch != '\n'),
+                    Inst::Class(class) => ch.is_some_and(|ch| class.matches(ch)),
+                    // Threads after this one would only find less preferred matches
+                    Inst::Match => {
+                        matched = Some(caps);
+                        break;
+                    }
+                    _ => false,
+                };
+                if advance {
+                    self.add(&mut next, pc + 1, pos + 1, caps, text);
+                }
+            }
+            current.clear();
+            if pos >= text.len() {
+                break;
+            }
+            mem::swap(&mut current, &mut next);
+            pos += 1;
+        }
+        return matched;
+    }
+
+    // Every match that doesn't overlap the one before it. After an empty match the
+    // search moves on a character, so it doesn't find the same one again.
+    fn captures_iter(&self, text: &[char]) -> Vec<Vec<Option<usize>>> {
+        let mut found = Vec::new();
+        let mut start = 0;
+        while start <= text.len() && let Some(caps) = self.captures_at(text, start) {
+            let (match_start, match_end) = (caps[0].unwrap(), caps[1].unwrap());
+            start = if match_end == match_start {
+                match_end + 1
+            } else {
+                match_end
+            };
+            found.push(caps);
+        }
+        return found;
+    }
+
+    // Follows jumps, splits, saves and assertions from pc until reaching instructions
+    // that consume a character, in the order a backtracking engine would try them
+    fn add(
+        &self,
+        threads: &mut Threads,
+        pc: usize,
+        pos: usize,
+        caps: Vec<Option<usize>>,
+        text: &[char],
+    ) {
+        let mut stack = vec![(pc, caps)];
+        while let Some((pc, mut caps)) = stack.pop() {
+            if threads.seen[pc] {
+                continue;
+            }
+            threads.seen[pc] = true;
+            threads.marked.push(pc);
+            match &self.program[pc] {
+                Inst::Jmp(to) => stack.push((*to, caps)),
+                Inst::Split(first, second) => {
+                    stack.push((*second, caps.clone()));
+                    stack.push((*first, caps));
+                }
+                Inst::Save(slot) => {
+                    caps[*slot] = Some(pos);
+                    stack.push((pc + 1, caps));
+                }
+                Inst::Assert(assertion) => {
+                    if assertion.holds(text, pos) {
+                        stack.push((pc + 1, caps));
+                    }
+                }
+                _ => threads.list.push((pc, caps)),
+            }
+        }
+    }
+}
+
+struct Threads {
+    list: Vec<(usize, Vec<Option<usize>>)>,
+    seen: Vec<bool>,
+    marked: Vec<usize>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        return Self {
+            list: Vec::new(),
+            seen: vec![false; len],
+            marked: Vec::new(),
+        };
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        for pc in self.marked.drain(..) {
+            self.seen[pc] = false;
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    // Tries the first branch before the second
+    Split(usize, usize),
+    Jmp(usize),
+    Save(usize),
+    Match,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Assertion {
+    Start,
+    End,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+impl Assertion {
+    fn holds(&self, text: &[char], pos: usize) -> bool {
+        let at_boundary = || {
+            let before = pos > 0 && is_word(text[pos - 1]);
+            let after = pos < text.len() && is_word(text[pos]);
+            return before != after;
+        };
+        match self {
+            Assertion::Start => return pos == 0,
+            Assertion::End => return pos == text.len(),
+            Assertion::WordBoundary => return at_boundary(),
+            Assertion::NotWordBoundary => return !at_boundary(),
+        }
+    }
+}
+
+fn is_word(ch: char) -> bool {
+    return ch.is_alphanumeric() || ch == '_';
+}
+
+// \d, \w and \s
+#[derive(Clone, Copy, Debug)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(&self, ch: char) -> bool {
+        match self {
+            Perl::Digit => return ch.is_ascii_digit(),
+            Perl::Word => return is_word(ch),
+            Perl::Space => return ch.is_whitespace(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum ClassItem {
+    Range(char, char),
+    Perl(Perl, bool),
+}
+
+#[derive(Clone, Debug)]
+struct Class {
+    negated: bool,
+    items: Vec<ClassItem>,
+}
+
+impl Class {
+    fn matches(&self, ch: char) -> bool {
+        let found = self.items.iter().any(|item| match item {
+            ClassItem::Range(low, high) => (*low..=*high).contains(&ch),
+            ClassItem::Perl(perl, negated) => perl.matches(ch) != *negated,
+        });
+        return found != self.negated;
+    }
+}
+
+enum Node {
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+enum Escape {
+    Literal(char),
+    Perl(Perl, bool),
+    Boundary(bool),
+}
+
+// Errors are a position in characters and what's wrong there
+type PatternError = (usize, &'static str);
+
+struct PatternParser {
+    chars: Vec<char>,
+    current: usize,
+    groups: usize,
+    names: Vec<(String, usize)>,
+}
+
+impl PatternParser {
+    fn alternation(&mut self) -> Result<Node, PatternError> {
+        let mut branches = vec![self.concat()?];
+        while self.eat('|') {
+            branches.push(self.concat()?);
+        }
+        if branches.len() == 1 {
+            return Ok(branches.pop().unwrap());
+        }
+        return Ok(Node::Alt(branches));
+    }
+
+    fn concat(&mut self) -> Result<Node, PatternError> {
+        let mut nodes = Vec::new();
+        while let Some(ch) = self.peek() && ch != '|' && ch != ')' {
+            nodes.push(self.repeat()?);
+        }
+        return Ok(Node::Concat(nodes));
+    }
+
+    fn repeat(&mut self) -> Result<Node, PatternError> {
+        let atom = self.atom()?;
+        let Some((min, max)) = self.quantifier()? else {
+            return Ok(atom);
+        };
+        let greedy = !self.eat('?');
+        if let Some('*' | '+' | '?') = self.peek() {
+            return Err((self.current, "multiple repeat"));
+        }
+        return Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        });
+    }
+
+    // *, +, ? or a count like {2}, {2,} or {2,5}. Braces that aren't a count are
+    // taken literally.
+    fn quantifier(&mut self) -> Result<Option<(u32, Option<u32>)>, PatternError> {
+        let start = self.current;
+        let bounds = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.current += 1;
+                let min = self.number();
+                let bounds = match (min, self.peek()) {
+                    (Some(min), Some('}')) => Some((min, Some(min))),
+                    (Some(min), Some(',')) => {
+                        self.current += 1;
+                        let max = self.number();
+                        self.peek().filter(|ch| *ch == '}').map(|_| (min, max))
+                    }
+                    _ => None,
+                };
+                let Some((min, max)) = bounds else {
+                    self.current = start;
+                    return Ok(None);
+                };
+                if let Some(max) = max && max < min {
+                    return Err((start, "repeat count range is backwards"));
+                }
+                if min.max(max.unwrap_or(0)) > MAX_REPEAT {
+                    return Err((start, "repeat count is too large"));
+                }
+                (min, max)
+            }
+            _ => return Ok(None),
+        };
+        self.current += 1;
+        return Ok(Some(bounds));
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let digits: String = self.chars[self.current..]
+            .iter()
+            .take_while(|ch| ch.is_ascii_digit())
+            .collect();
+        if digits.is_empty() {
+            return None;
+        }
+        self.current += digits.len();
+        return Some(digits.parse().unwrap_or(u32::MAX));
+    }
+
+    fn atom(&mut self) -> Result<Node, PatternError> {
+        let start = self.current;
+        let ch = self.chars[self.current];
+        self.current += 1;
+        match ch {
+            '.' => return Ok(Node::Any),
+            '^' => return Ok(Node::Assert(Assertion::Start)),
+            '$' => return Ok(Node::Assert(Assertion::End)),
+            '(' => return self.group(start),
+            '[' => return Ok(Node::Class(self.class(start)?)),
+            '\\' => match self.escape()? {
+                Escape::Literal(ch) => return Ok(Node::Char(ch)),
+                Escape::Perl(perl, negated) => {
+                    return Ok(Node::Class(Class {
+                        negated: false,
+                        items: vec![ClassItem::Perl(perl, negated)],
+                    }))
+                }
+                Escape::Boundary(true) => return Ok(Node::Assert(Assertion::WordBoundary)),
+                Escape::Boundary(false) => return Ok(Node::Assert(Assertion::NotWordBoundary)),
+            },
+            '*' | '+' | '?' => return Err((start, "nothing to repeat")),
+            '{' => {
+                self.current = start;
+                if self.quantifier()?.is_some() {
+                    return Err((start, "nothing to repeat"));
+                }
+                self.current = start + 1;
+                return Ok(Node::Char('{'));
+            }
+            ch => return Ok(Node::Char(ch)),
+        }
+    }
+
+    // Groups capture unless they start with ?:, and can be named with ?<name> or ?P<name>
+    fn group(&mut self, start: usize) -> Result<Node, PatternError> {
+        let index = if self.eat_str("?:") {
+            None
+        } else if self.eat_str("?<") || self.eat_str("?P<") {
+            let name = self.group_name()?;
+            self.groups += 1;
+            self.names.push((name, self.groups));
+            Some(self.groups)
+        } else if self.peek() == Some('?') {
+            return Err((self.current, "unknown group flag"));
+        } else {
+            self.groups += 1;
+            Some(self.groups)
+        };
+        let inner = self.alternation()?;
+        if !self.eat(')') {
+            return Err((start, "missing ')'"));
+        }
+        return Ok(Node::Group(Box::new(inner), index));
+    }
+
+    fn group_name(&mut self) -> Result<String, PatternError> {
+        let start = self.current;
+        let name: String = self.chars[self.current..]
+            .iter()
+            .take_while(|ch| is_word(**ch))
+            .collect();
+        self.current += name.chars().count();
+        if name.is_empty() || !self.eat('>') {
+            return Err((start, "expected a group name followed by '>'"));
+        }
+        if self.names.iter().any(|(other, _)| *other == name) {
+            return Err((start, "duplicate group name"));
+        }
+        return Ok(name);
+    }
+
+    // A ']' right after the '[' or '[^' is part of the class
+    fn class(&mut self, start: usize) -> Result<Class, PatternError> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let Some(ch) = self.next() else {
+                return Err((start, "missing ']'"));
+            };
+            if ch == ']' && !first {
+                break;
+            }
+            first = false;
+            let low = match self.class_char(ch)? {
+                Escape::Literal(low) => low,
+                Escape::Perl(perl, negated) => {
+                    items.push(ClassItem::Perl(perl, negated));
+                    continue;
+                }
+                Escape::Boundary(_) => unreachable!(),
+            };
+            if self.peek() != Some('-')
+                || matches!(self.chars.get(self.current + 1), None | Some(']'))
+            {
+                items.push(ClassItem::Range(low, low));
+                continue;
+            }
+            self.current += 1;
+            let range_start = self.current;
+            let ch = self.next().unwrap();
+            let Escape::Literal(high) = self.class_char(ch)? else {
+                return Err((range_start, "invalid class range"));
+            };
+            if high < low {
+                return Err((range_start, "class range is backwards"));
+            }
+            items.push(ClassItem::Range(low, high));
+        }
+        return Ok(Class { negated, items });
+    }
+
+    fn class_char(&mut self, ch: char) -> Result<Escape, PatternError> {
+        if ch != '\\' {
+            return Ok(Escape::Literal(ch));
+        }
+        let start = self.current - 1;
+        match self.escape()? {
+            Escape::Boundary(_) => return Err((start, "word boundaries can't go in a class")),
+            escape => return Ok(escape),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Escape, PatternError> {
+        let start = self.current - 1;
+        let Some(ch) = self.next() else {
+            return Err((start, "pattern ends with a backslash"));
+        };
+        match ch {
+            'd' => return Ok(Escape::Perl(Perl::Digit, false)),
+            'D' => return Ok(Escape::Perl(Perl::Digit, true)),
+            'w' => return Ok(Escape::Perl(Perl::Word, false)),
+            'W' => return Ok(Escape::Perl(Perl::Word, true)),
+            's' => return Ok(Escape::Perl(Perl::Space, false)),
+            'S' => return Ok(Escape::Perl(Perl::Space, true)),
+            'b' => return Ok(Escape::Boundary(true)),
+            'B' => return Ok(Escape::Boundary(false)),
+            'n' => return Ok(Escape::Literal('\n')),
+            't' => return Ok(Escape::Literal('\t')),
+            'r' => return Ok(Escape::Literal('\r')),
+            ch if ch.is_alphanumeric() => return Err((start, "unknown escape")),
+            ch => return Ok(Escape::Literal(ch)),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        return self.chars.get(self.current).copied();
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.current += 1;
+        return Some(ch);
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.current += 1;
+            return true;
+        }
+        return false;
+    }
+
+    fn eat_str(&mut self, expected: &str) -> bool {
+        let len = expected.chars().count();
+        if self.chars[self.current..]
+            .iter()
+            .take(len)
+            .copied()
+            .eq(expected.chars())
+        {
+            self.current += len;
+            return true;
+        }
+        return false;
+    }
+}
+
+struct PatternCompiler {
+    program: Vec<Inst>,
+}
+
+impl PatternCompiler {
+    fn emit(&mut self, node: &Node) -> Result<(), PatternError> {
+        if self.program.len() > MAX_PROGRAM {
+            return Err((0, "pattern is too large"));
+        }
+        match node {
+            Node::Char(ch) => self.program.push(Inst::Char(*ch)),
+            Node::Any => self.program.push(Inst::Any),
+            Node::Class(class) => self.program.push(Inst::Class(class.clone())),
+            Node::Assert(assertion) => self.program.push(Inst::Assert(*assertion)),
+            Node::Group(inner, index) => {
+                if let Some(index) = index {
+                    self.program.push(Inst::Save(2 * index));
+                }
+                self.emit(inner)?;
+                if let Some(index) = index {
+                    self.program.push(Inst::Save(2 * index + 1));
+                }
+            }
+            Node::Concat(nodes) => {
+                for node in nodes.iter() {
+                    self.emit(node)?;
+                }
+            }
+            // Each branch but the last splits off to the next one, and jumps past the
+            // rest once it's matched
+            Node::Alt(branches) => {
+                let mut jumps = Vec::new();
+                for (idx, branch) in branches.iter().enumerate() {
+                    if idx + 1 == branches.len() {
+                        self.emit(branch)?;
+                        break;
+                    }
+                    let split = self.program.len();
+                    self.program.push(Inst::Split(split + 1, 0));
+                    self.emit(branch)?;
+                    jumps.push(self.program.len());
+                    self.program.push(Inst::Jmp(0));
+                    self.program[split] = Inst::Split(split + 1, self.program.len());
+                }
+                let end = self.program.len();
+                for jump in jumps {
+                    self.program[jump] = Inst::Jmp(end);
+                }
+            }
+            // The required copies, then either a loop or one optional copy per count
+            // left, each of which can skip to the end
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.emit(node)?;
+                }
+                let mut splits = Vec::new();
+                match max {
+                    None => {
+                        let split = self.program.len();
+                        splits.push(split);
+                        self.program.push(Inst::Split(0, 0));
+                        self.emit(node)?;
+                        self.program.push(Inst::Jmp(split));
+                    }
+                    Some(max) => {
+                        for _ in *min..*max {
+                            splits.push(self.program.len());
+                            self.program.push(Inst::Split(0, 0));
+                            self.emit(node)?;
+                        }
+                    }
+                }
+                let end = self.program.len();
+                for split in splits {
+                    self.program[split] = if *greedy {
+                        Inst::Split(split + 1, end)
+                    } else {
+                        Inst::Split(end, split + 1)
+                    };
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::testing::run_both;
+
+    #[test]
+    fn split_and_replace_agree_on_empty_matches() {
+        let source = r#"
+            import regex;
+            env regex {
+                println(split("", "abc"));
+                println(replace("", "abc", "-"));
+                println(split("x*", "axb"));
+                println(replace("x*", "axb", "-"));
+            }
+        "#;
+        assert_eq!(
+            run_both(source),
+            "[\"\", \"a\", \"b\", \"c\", \"\"]\n-a-b-c-\n[\"\", \"a\", \"\", \"b\", \"\"]\n-a--b-\n"
+        );
+    }
+
+    #[test]
+    fn groups_in_matches_and_replacements() {
+        let source = r#"
+            import regex;
+            env regex {
+                println(split(",\s*", "a, b,c"));
+                println(replace("(?<user>\w+)@(\w+)", "me@home you@work", "${user} at $2"));
+                let found = find("\d+", "a1b22c333");
+                env found {
+                    println(text);
+                    println(start);
+                    println(end);
+                }
+                println(len(find_all("\d+", "a1b22c333")));
+                let word = compile("[a-z]+");
+                env word {
+                    println(is_match("123"));
+                }
+            }
+        "#;
+        assert_eq!(
+            run_both(source),
+            "[\"a\", \"b\", \"c\"]\nme at home you at work\n1\n1\n2\n3\nfalse\n"
+        );
+    }
+
+    #[test]
+    fn invalid_pattern_is_an_error() {
+        let source = r#"
+            import regex;
+            env regex {
+                println(find("(", "abc"));
+            }
+        "#;
+        assert_eq!(run_both(source), "Invalid pattern '(' at position 0: missing ')'\n");
+    }
+}
@@ -0,0 +1,48 @@
+use std::{io::BufRead, mem};
+
+use crate::{
+    error_reporting::interp_err::InterpException,
+    heap::heap::Gc,
+    interpreter::{environment::Environment, permissions::Permissions, sandbox::Sandbox},
+    scanner::token::Primitive,
+};
+
+use super::random::Rng;
+
+// What native functions can reach of the engine running them, be it the tree-walker
+// or the vm
+pub trait Runtime {
+    fn globals(&mut self) -> &mut Environment;
+    fn permissions(&self) -> &Permissions;
+    // Arguments given to the script after its path
+    fn script_args(&self) -> &[String];
+    fn stdin(&mut self) -> &mut dyn BufRead;
+    // For natives that block, to stop when the sandbox's time is up
+    fn sandbox(&mut self) -> Option<&mut Sandbox>;
+    // Shared by everything the random module draws
+    fn rng(&mut self) -> &mut Rng;
+    // Calls a script or native function handed to a native, like the one map takes,
+    // leaving the scope as it was even if the function returned early
+    fn invoke(
+        &mut self,
+        name: &str,
+        callee: Primitive,
+        args: Vec<Primitive>,
+    ) -> Result<Primitive, InterpException>;
+
+    // Calls a function with an env's fields in scope, like `env name { func() }` would
+    fn invoke_in(
+        &mut self,
+        env: &Gc<Environment>,
+        name: &str,
+        callee: Primitive,
+        args: Vec<Primitive>,
+    ) -> Result<Primitive, InterpException> {
+        let globals = self.globals();
+        let scope = mem::replace(globals, Environment::shared(env));
+        globals.enclosing = Some(Box::new(scope));
+        let result = self.invoke(name, callee, args);
+        self.globals().unwind(1);
+        return result;
+    }
+}
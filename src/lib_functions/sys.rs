@@ -0,0 +1,85 @@
+use crate::{
+    error_reporting::interp_err::InterpException,
+    interpreter::{environment::Environment, permissions::Capability},
+    scanner::token::Primitive,
+};
+
+use super::{
+    convert::IntoPrimitive,
+    native::{argument, expect_args},
+    runtime::Runtime,
+    LibFunctions, SysLibFunctions,
+};
+
+// The process a script runs in: its arguments, environment and standard input
+pub struct System;
+
+impl System {
+    pub fn new() -> Environment {
+        let mut ret_env = Environment::new();
+        for (name, func) in [
+            ("args", SysLibFunctions::Args),
+            ("get_env", SysLibFunctions::GetEnv),
+            ("read_line", SysLibFunctions::ReadLine),
+            ("read_all", SysLibFunctions::ReadAll),
+            ("exit", SysLibFunctions::Exit),
+        ] {
+            ret_env.define(name, Primitive::NativeFunc(LibFunctions::Sys(func)));
+        }
+        return ret_env;
+    }
+
+    pub fn do_func(
+        func_name: SysLibFunctions,
+        args: Vec<Primitive>,
+        runtime: &mut dyn Runtime,
+    ) -> Result<Primitive, InterpException> {
+        let name = func_name.name();
+        let io_error = |err: std::io::Error| {
+            InterpException::IoFailed(name, String::from("stdin"), err.to_string())
+        };
+        match func_name {
+            SysLibFunctions::Args => {
+                expect_args(name, &args, 0)?;
+                return Ok(runtime.script_args().to_vec().into_primitive());
+            }
+            // Unset variables, and ones that aren't unicode, are null
+            SysLibFunctions::GetEnv => {
+                expect_args(name, &args, 1)?;
+                let var: String = argument(name, &args, 0)?;
+                runtime.permissions().check(Capability::Env, &var)?;
+                return Ok(std::env::var(var).ok().into_primitive());
+            }
+            // Returns the line without its line break, or null once the input has run out
+            SysLibFunctions::ReadLine => {
+                expect_args(name, &args, 0)?;
+                let mut line = String::new();
+                if runtime.stdin().read_line(&mut line).map_err(io_error)? == 0 {
+                    return Ok(Primitive::None);
+                }
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                return Ok(Primitive::String(line));
+            }
+            SysLibFunctions::ReadAll => {
+                expect_args(name, &args, 0)?;
+                let mut input = String::new();
+                runtime.stdin().read_to_string(&mut input).map_err(io_error)?;
+                return Ok(Primitive::String(input));
+            }
+            // Unwinds the script rather than ending the process, which may be a host's. A
+            // script ending itself isn't touching anything outside of it, so it needs no flag.
+            SysLibFunctions::Exit => {
+                if args.len() > 1 {
+                    return Err(InterpException::WrongArgumentCount(name.to_string(), 1, args.len()));
+                }
+                let code: i32 = if args.is_empty() { 0 } else { argument(name, &args, 0)? };
+                return Err(InterpException::Exit(code));
+            }
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    sync::OnceLock,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error_reporting::interp_err::InterpException, heap::heap::Gc,
+    interpreter::environment::Environment, scanner::token::Primitive,
+};
+
+use super::{
+    native::{argument, expect_args},
+    runtime::Runtime,
+    LibFunctions, TimeLibFunctions,
+};
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+// Sleeps are split up so a sandbox's deadline or cancel handle can cut them short
+const SLEEP_SLICE: Duration = Duration::from_millis(10);
+
+static MONOTONIC_START: OnceLock<Instant> = OnceLock::new();
+
+// Timestamps are milliseconds since the Unix epoch, in UTC, and durations are
+// milliseconds too, so they're added and subtracted like any other int
+pub struct Time;
+
+impl Time {
+    pub fn new() -> Environment {
+        let mut ret_env = Environment::new();
+        for (name, func) in [
+            ("now", TimeLibFunctions::Now),
+            ("monotonic", TimeLibFunctions::Monotonic),
+            ("sleep", TimeLibFunctions::Sleep),
+            ("seconds", TimeLibFunctions::Seconds),
+            ("minutes", TimeLibFunctions::Minutes),
+            ("hours", TimeLibFunctions::Hours),
+            ("days", TimeLibFunctions::Days),
+            ("format", TimeLibFunctions::Format),
+            ("parse", TimeLibFunctions::Parse),
+            ("date", TimeLibFunctions::Date),
+        ] {
+            ret_env.define(name, Primitive::NativeFunc(LibFunctions::Time(func)));
+        }
+        return ret_env;
+    }
+
+    pub fn do_func(
+        func_name: TimeLibFunctions,
+        args: Vec<Primitive>,
+        runtime: &mut dyn Runtime,
+    ) -> Result<Primitive, InterpException> {
+        let name = func_name.name();
+        match func_name {
+            TimeLibFunctions::Now => {
+                expect_args(name, &args, 0)?;
+                let millis = match SystemTime::now().duration_since(UNIX_EPOCH) {
+                    Ok(since) => since.as_millis() as isize,
+                    Err(before) => -(before.duration().as_millis() as isize),
+                };
+                return Ok(Primitive::Int(millis));
+            }
+            // Nanoseconds from a fixed but arbitrary point, for measuring how long things take
+            TimeLibFunctions::Monotonic => {
+                expect_args(name, &args, 0)?;
+                let start = MONOTONIC_START.get_or_init(Instant::now);
+                return Ok(Primitive::Int(start.elapsed().as_nanos() as isize));
+            }
+            TimeLibFunctions::Sleep => {
+                expect_args(name, &args, 1)?;
+                let millis: u64 = argument(name, &args, 0)?;
+                let until = Instant::now() + Duration::from_millis(millis);
+                loop {
+                    if let Some(sandbox) = runtime.sandbox() {
+                        sandbox.interrupted()?;
+                    }
+                    let left = until.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Ok(Primitive::None);
+                    }
+                    thread::sleep(left.min(SLEEP_SLICE));
+                }
+            }
+            TimeLibFunctions::Seconds
+            | TimeLibFunctions::Minutes
+            | TimeLibFunctions::Hours
+            | TimeLibFunctions::Days => {
+                expect_args(name, &args, 1)?;
+                let count: f64 = argument(name, &args, 0)?;
+                let unit = match func_name {
+                    TimeLibFunctions::Seconds => 1000.0,
+                    TimeLibFunctions::Minutes => 60.0 * 1000.0,
+                    TimeLibFunctions::Hours => 60.0 * 60.0 * 1000.0,
+                    _ => MILLIS_PER_DAY as f64,
+                };
+                return Ok(Primitive::Int((count * unit).round() as isize));
+            }
+            TimeLibFunctions::Format => {
+                expect_args(name, &args, 1)?;
+                let millis: i64 = argument(name, &args, 0)?;
+                return Ok(Primitive::String(format_iso(millis)));
+            }
+            TimeLibFunctions::Parse => {
+                expect_args(name, &args, 1)?;
+                let text: String = argument(name, &args, 0)?;
+                let millis = parse_iso(&text)
+                    .map_err(|reason| InterpException::InvalidDate(text.clone(), reason))?;
+                return Ok(Primitive::Int(millis as isize));
+            }
+            TimeLibFunctions::Date => {
+                expect_args(name, &args, 1)?;
+                let millis: i64 = argument(name, &args, 0)?;
+                let (days, time) = (
+                    millis.div_euclid(MILLIS_PER_DAY),
+                    millis.rem_euclid(MILLIS_PER_DAY),
+                );
+                let (year, month, day) = civil_from_days(days);
+                let mut ret_env = Environment::new();
+                ret_env.define("year", Primitive::Int(year as isize));
+                ret_env.define("month", Primitive::Int(month as isize));
+                ret_env.define("day", Primitive::Int(day as isize));
+                ret_env.define("hour", Primitive::Int((time / 3_600_000) as isize));
+                ret_env.define("minute", Primitive::Int((time / 60_000 % 60) as isize));
+                ret_env.define("second", Primitive::Int((time / 1000 % 60) as isize));
+                ret_env.define("millisecond", Primitive::Int((time % 1000) as isize));
+                // ISO weekdays run from 1 for Monday to 7 for Sunday, and the epoch was a Thursday
+                ret_env.define(
+                    "weekday",
+                    Primitive::Int(((days + 3).rem_euclid(7) + 1) as isize),
+                );
+                return Ok(Primitive::Env(Gc::new(ret_env)));
+            }
+        }
+    }
+}
+
+// Like 2024-03-09T14:05:00Z, with milliseconds only when there are some
+fn format_iso(millis: i64) -> String {
+    let (days, time) = (
+        millis.div_euclid(MILLIS_PER_DAY),
+        millis.rem_euclid(MILLIS_PER_DAY),
+    );
+    let (year, month, day) = civil_from_days(days);
+    let mut formatted = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3_600_000,
+        time / 60_000 % 60,
+        time / 1000 % 60
+    );
+    if time % 1000 != 0 {
+        formatted.push_str(&format!(".{:03}", time % 1000));
+    }
+    formatted.push('Z');
+    return formatted;
+}
+
+// Takes a date, optionally followed by a time and a UTC offset. Times without an
+// offset are taken to be in UTC.
+fn parse_iso(text: &str) -> Result<i64, &'static str> {
+    let mut cursor = Cursor { text, current: 0 };
+    let year = cursor.number(4, "expected a four digit year")?;
+    cursor.expect('-', "expected '-' after the year")?;
+    let month = cursor.number(2, "expected a two digit month")?;
+    cursor.expect('-', "expected '-' after the month")?;
+    let day = cursor.number(2, "expected a two digit day")?;
+    if !(1..=12).contains(&month) {
+        return Err("month out of range");
+    }
+    if day < 1 || day > days_in_month(year, month) {
+        return Err("day out of range");
+    }
+    let mut millis = days_from_civil(year, month, day) * MILLIS_PER_DAY;
+    if cursor.done() {
+        return Ok(millis);
+    }
+
+    if !cursor.eat('T') && !cursor.eat(' ') {
+        return Err("expected 'T' between the date and the time");
+    }
+    let hour = cursor.number(2, "expected a two digit hour")?;
+    cursor.expect(':', "expected ':' after the hour")?;
+    let minute = cursor.number(2, "expected two digit minutes")?;
+    let mut second = 0;
+    if cursor.eat(':') {
+        second = cursor.number(2, "expected two digit seconds")?;
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return Err("time out of range");
+    }
+    millis += ((hour * 60 + minute) * 60 + second) * 1000;
+    // Digits past milliseconds are dropped
+    if cursor.eat('.') {
+        let fraction = cursor.digits();
+        if fraction.is_empty() {
+            return Err("expected digits after '.'");
+        }
+        let padded = format!("{:0<3}", &fraction[..fraction.len().min(3)]);
+        millis += padded.parse::<i64>().unwrap();
+        cursor.current += fraction.len();
+    }
+
+    if cursor.eat('Z') || cursor.done() {
+        return cursor.finish(millis);
+    }
+    let sign = if cursor.eat('+') {
+        1
+    } else if cursor.eat('-') {
+        -1
+    } else {
+        return Err("expected 'Z' or an offset like +02:00");
+    };
+    let offset_hours = cursor.number(2, "expected a two digit offset")?;
+    cursor.eat(':');
+    let offset_minutes = cursor.number(2, "expected two digit offset minutes")?;
+    millis -= sign * (offset_hours * 60 + offset_minutes) * 60 * 1000;
+    return cursor.finish(millis);
+}
+
+struct Cursor<'a> {
+    text: &'a str,
+    current: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn number(&mut self, width: usize, msg: &'static str) -> Result<i64, &'static str> {
+        let digits = self
+            .text
+            .get(self.current..self.current + width)
+            .ok_or(msg)?;
+        if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(msg);
+        }
+        self.current += width;
+        return Ok(digits.parse().unwrap());
+    }
+
+    fn digits(&self) -> &'a str {
+        let rest = &self.text[self.current..];
+        let len = rest
+            .bytes()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count();
+        return &rest[..len];
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.text[self.current..].starts_with(expected) {
+            self.current += expected.len_utf8();
+            return true;
+        }
+        return false;
+    }
+
+    fn expect(&mut self, expected: char, msg: &'static str) -> Result<(), &'static str> {
+        if self.eat(expected) {
+            return Ok(());
+        }
+        return Err(msg);
+    }
+
+    fn done(&self) -> bool {
+        return self.current == self.text.len();
+    }
+
+    fn finish(&self, millis: i64) -> Result<i64, &'static str> {
+        if !self.done() {
+            return Err("unexpected text at the end");
+        }
+        return Ok(millis);
+    }
+}
+
+fn is_leap(year: i64) -> bool {
+    return year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap(year) => return 29,
+        2 => return 28,
+        4 | 6 | 9 | 11 => return 30,
+        _ => return 31,
+    }
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar. Years are counted from
+// March so the leap day falls at the end of one.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    return (year, month, day);
+}
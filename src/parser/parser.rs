@@ -1,8 +1,14 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::{
-    ast::expr_types::{Binary, ExprPossibilities, Grouping, Literal, Scope, Stmt, Ternary, Unary},
+    ast::expr_types::{
+        Binary, ExprPossibilities, Grouping, Literal, Param, Scope, Stmt, Ternary, Unary,
+    },
     error_reporting::{error_reporter::Unwindable, parsing_err::ParsingException},
+    lib_functions::GLOBAL_FUNCTIONS,
     scanner::token::{Primitive, Token, TokenType},
 };
 
@@ -11,28 +17,28 @@ pub struct Parser {
     pub current: usize,
     pub imports: HashSet<String>,
     tokens: Vec<Token>,
+    // Names declared in each scope being parsed, mapped to whether they are constant.
+    // Opaque scopes (function bodies and env calls) can see names only known at runtime.
+    scopes: Vec<(bool, HashMap<String, bool>)>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
+        let mut globals = HashMap::new();
+        for (builtin, _) in GLOBAL_FUNCTIONS.iter() {
+            globals.insert(builtin.to_string(), true);
+        }
+
         return Self {
             current: 0,
             tokens,
             imports: HashSet::new(),
+            scopes: vec![(false, globals)],
         };
     }
 
     pub fn parse(&mut self) -> Result<ExprPossibilities, ParsingException> {
-        let expr_wrapped = self.import();
-        if let Ok(expr) = expr_wrapped {
-            // println!("{:?}", expr);
-            return Ok(expr);
-        } else if let Err(err) = expr_wrapped {
-            println!("{}", err.get_value());
-            return Err(ParsingException::InvalidTernaryExpr(self.peek().clone()));
-        } else {
-            return Err(ParsingException::PlaceHolder);
-        }
+        return self.import();
     }
 
     fn import(&mut self) -> Result<ExprPossibilities, ParsingException> {
@@ -57,25 +63,94 @@ impl Parser {
                 .consume(&[TokenType::IDENTIFIER], ParsingException::PlaceHolder)?
                 .clone();
             self.consume(&[TokenType::LEFT_PAREN], ParsingException::PlaceHolder)?;
-            let mut ident_vec = Vec::new();
+            let params = self.params()?;
+            let mut annotation = None;
+            if self.match_tok(&[TokenType::MINUS]) {
+                self.consume(
+                    &[TokenType::GREATER],
+                    ParsingException::InvalidAnnotation(self.previous().clone()),
+                )?;
+                annotation = Some(self.type_annotation()?);
+            }
+            self.consume(&[TokenType::LEFT_BRACE], ParsingException::PlaceHolder)?;
+            let func = self.scope(TokenType::FUNC, Some(ident), None, Some(params))?;
+            if let ExprPossibilities::Scope(mut scope) = func {
+                Rc::make_mut(&mut scope).annotation = annotation;
+                return Ok(ExprPossibilities::Scope(scope));
+            }
+            return Ok(func);
+        }
 
-            while let Ok(tok) = self.consume(
-                &[TokenType::COMMA, TokenType::IDENTIFIER],
-                ParsingException::PlaceHolder,
-            ) {
-                match tok.tok {
-                    TokenType::IDENTIFIER => ident_vec.push(tok.clone()),
-                    TokenType::COMMA => {}
-                    _ => return Err(ParsingException::PlaceHolder),
+        return self.while_loop();
+    }
+
+    // Type names are checked by the type checker, so any identifier is accepted here
+    fn type_annotation(&mut self) -> Result<Token, ParsingException> {
+        let tok = self.consume(
+            &[
+                TokenType::IDENTIFIER,
+                TokenType::NIL,
+                TokenType::FUNC,
+                TokenType::CLOSCALL,
+            ],
+            ParsingException::InvalidAnnotation(self.peek().clone()),
+        )?;
+        return Ok(tok.clone());
+    }
+
+    fn params(&mut self) -> Result<Vec<Param>, ParsingException> {
+        let mut params: Vec<Param> = Vec::new();
+        while !self.match_tok(&[TokenType::RIGHT_PAREN]) {
+            if let Some(last) = params.last() && last.variadic {
+                return Err(ParsingException::InvalidParams(self.peek().clone()));
+            }
+
+            let variadic = self.match_tok(&[TokenType::DOT]);
+            if variadic {
+                for _ in 0..2 {
+                    self.consume(
+                        &[TokenType::DOT],
+                        ParsingException::InvalidParams(self.previous().clone()),
+                    )?;
                 }
             }
 
-            self.consume(&[TokenType::RIGHT_PAREN], ParsingException::PlaceHolder)?;
-            self.consume(&[TokenType::LEFT_BRACE], ParsingException::PlaceHolder)?;
-            return self.scope(TokenType::FUNC, Some(ident), None, Some(ident_vec));
+            let ident = self
+                .consume(
+                    &[TokenType::IDENTIFIER],
+                    ParsingException::InvalidParams(self.peek().clone()),
+                )?
+                .clone();
+
+            let mut annotation = None;
+            if self.match_tok(&[TokenType::TERNARYFALSE]) {
+                annotation = Some(self.type_annotation()?);
+            }
+
+            let mut default = None;
+            if !variadic && self.match_tok(&[TokenType::EQUAL]) {
+                default = Some(Box::new(self.chain_bool()?));
+            } else if let Some(last) = params.last() && let Some(_) = last.default && !variadic {
+                // Required parameters can't follow ones with a default value
+                return Err(ParsingException::InvalidParams(ident));
+            }
+
+            params.push(Param {
+                ident,
+                default,
+                variadic,
+                annotation,
+            });
+
+            if self.peek().tok != TokenType::RIGHT_PAREN {
+                self.consume(
+                    &[TokenType::COMMA],
+                    ParsingException::InvalidParams(self.peek().clone()),
+                )?;
+            }
         }
 
-        return self.while_loop();
+        return Ok(params);
     }
 
     fn while_loop(&mut self) -> Result<ExprPossibilities, ParsingException> {
@@ -93,6 +168,19 @@ impl Parser {
 
     fn for_loop(&mut self) -> Result<ExprPossibilities, ParsingException> {
         while self.match_tok(&[TokenType::FOR]) {
+            // `for item in items {` goes over a list, set or deque
+            if self.check(&TokenType::IDENTIFIER)
+                && self.tokens[self.current + 1].tok == TokenType::IN
+            {
+                let ident = self.advance().clone();
+                self.advance();
+                let items = self.chain_bool()?;
+                self.consume(
+                    &[TokenType::LEFT_BRACE],
+                    ParsingException::InvalidLoop(self.previous().clone()),
+                )?;
+                return self.scope(TokenType::IN, Some(ident), Some(Box::new(items)), None);
+            }
             if !self.match_tok(&[TokenType::LEFT_BRACE]) {
                 let declaration = self.declaration()?;
                 return self.scope(TokenType::FOR, None, Some(Box::new(declaration)), None);
@@ -145,22 +233,30 @@ impl Parser {
                     inner: Vec::new(),
                     condition: None,
                     params: None,
+                    annotation: None,
                 };
 
+                self.declare(&ident, false)?;
+                self.scopes.push((true, HashMap::new()));
                 while !self.match_tok(&[TokenType::RIGHT_BRACE]) {
-                    if self.match_tok(&[TokenType::LET]) {
-                        let var = self.var_declaration(TokenType::LET)?;
+                    if self.match_tok(&[TokenType::LET, TokenType::CONST]) {
+                        let var = self.var_declaration(self.previous().tok.clone())?;
                         if let ExprPossibilities::Stmt(stmt) = var && let Some(ref _expr) = stmt.inner {
                             env.inner.push(ExprPossibilities::Stmt(stmt));
                         } else {
                             return Err(ParsingException::InvalidEnv(env))
                         }
+                    } else if self.check(&TokenType::FUNC) {
+                        // Functions become fields too, like a `to_string` to print the env with
+                        let func = self.func_def()?;
+                        env.inner.push(func);
                     } else {
                         return Err(ParsingException::InvalidEnvAssign(ident));
                     }
                 }
+                self.scopes.pop();
                 self.consume(&[TokenType::SEMICOLON], ParsingException::PlaceHolder);
-                return Ok(ExprPossibilities::Scope(env));
+                return Ok(ExprPossibilities::Scope(Rc::new(env)));
             }
             return Err(ParsingException::InvalidEnvAssign(ident));
         }
@@ -171,26 +267,43 @@ impl Parser {
         scope_type: TokenType,
         ident: Option<Token>,
         condition: Option<Box<ExprPossibilities>>,
-        params: Option<Vec<Token>>,
+        params: Option<Vec<Param>>,
     ) -> Result<ExprPossibilities, ParsingException> {
         let mut expr_list: Vec<ExprPossibilities> = Vec::new();
+        let opaque = scope_type == TokenType::FUNC || scope_type == TokenType::CLOSCALL;
+        let mut names = HashMap::new();
+        for param in params.iter().flatten() {
+            names.insert(param.ident.lexeme.clone(), false);
+        }
+        if scope_type == TokenType::IN && let Some(ident) = &ident {
+            names.insert(ident.lexeme.clone(), false);
+        }
+
+        self.scopes.push((opaque, names));
         while !self.match_tok(&[TokenType::RIGHT_BRACE]) {
-            let expr = self.func_def()?;
-            expr_list.push(expr)
+            match self.func_def() {
+                Ok(expr) => expr_list.push(expr),
+                Err(err) => {
+                    self.scopes.pop();
+                    return Err(err);
+                }
+            }
         }
+        self.scopes.pop();
 
-        return Ok(ExprPossibilities::Scope(Scope {
+        return Ok(ExprPossibilities::Scope(Rc::new(Scope {
             stmt: scope_type,
             ident,
             inner: expr_list,
             condition,
             params,
-        }));
+            annotation: None,
+        })));
     }
 
     fn declaration(&mut self) -> Result<ExprPossibilities, ParsingException> {
-        if self.match_tok(&[TokenType::LET]) {
-            return self.var_declaration(TokenType::LET);
+        if self.match_tok(&[TokenType::LET, TokenType::CONST]) {
+            return self.var_declaration(self.previous().tok.clone());
         };
 
         return self.statement();
@@ -206,12 +319,21 @@ impl Parser {
         }
         unsafe {
             let ident = name.unwrap_unchecked().clone();
+            let mut annotation = None;
+            if self.match_tok(&[TokenType::TERNARYFALSE]) {
+                annotation = Some(self.type_annotation()?);
+            }
             if self.match_tok(&[TokenType::EQUAL]) {
+                self.declare(&ident, stmt_type == TokenType::CONST)?;
                 let initializer = ExprPossibilities::Stmt(Stmt {
                     stmt: stmt_type.clone(),
                     inner: Some(Box::new(self.func_def()?)),
                     ident: Some(ident),
                     params: None,
+                    annotation,
+                    depth: None,
+                    slot: None,
+                    tail: false,
                 });
                 if self.previous().tok != TokenType::SEMICOLON
                     && self.peek().tok != TokenType::RIGHT_PAREN
@@ -240,6 +362,10 @@ impl Parser {
                         }),
                         inner: None,
                         params: Some(Box::new(vec![idx, self.func_def()?])),
+                        annotation: None,
+                        depth: None,
+                        slot: None,
+                        tail: false,
                     }));
                 }
             }
@@ -247,6 +373,27 @@ impl Parser {
         return Err(ParsingException::InvalidAssign(self.previous().clone()));
     }
 
+    fn declare(&mut self, ident: &Token, is_const: bool) -> Result<(), ParsingException> {
+        let (_, names) = self.scopes.last_mut().unwrap();
+        if let Some(true) = names.get(&ident.lexeme) {
+            return Err(ParsingException::AssignToConstant(ident.clone()));
+        }
+        names.insert(ident.lexeme.clone(), is_const);
+        return Ok(());
+    }
+
+    fn is_constant(&self, name: &str) -> bool {
+        for (opaque, names) in self.scopes.iter().rev() {
+            if let Some(is_const) = names.get(name) {
+                return *is_const;
+            } else if *opaque {
+                return false;
+            }
+        }
+
+        return self.imports.contains(name);
+    }
+
     fn check_line(&self, tok_type: TokenType) -> bool {
         let mut offset = 1;
         let line = self.peek().line;
@@ -281,6 +428,10 @@ impl Parser {
                     ident: None,
                     inner: Some(Box::new(return_expr)),
                     params: None,
+                    annotation: None,
+                    depth: None,
+                    slot: None,
+                    tail: false,
                 }));
             }
             if self.previous().tok == TokenType::IF {
@@ -356,6 +507,10 @@ impl Parser {
                 inner: Some(Box::new(ExprPossibilities::Grouping(expr))),
                 ident: None,
                 params: None,
+                annotation: None,
+                depth: None,
+                slot: None,
+                tail: false,
             }));
         } else {
             return Err(ParsingException::InvalidPrint(self.previous().clone()));
@@ -582,22 +737,43 @@ impl Parser {
                 &[TokenType::SEMICOLON],
                 ParsingException::InvalidExpr(self.peek().clone()),
             );
-            return Ok(ExprPossibilities::Scope(Scope {
+            return Ok(ExprPossibilities::Scope(Rc::new(Scope {
                 stmt: TokenType::LEFT_SQUARE,
                 ident: None,
                 condition: None,
                 params: None,
+                annotation: None,
                 inner: scope_vec,
-            }));
+            })));
         }
 
         if self.match_tok(&[TokenType::IDENTIFIER]) {
             let ident = self.previous().clone();
             if self.match_tok(&[TokenType::LEFT_PAREN]) {
                 let mut arg_vec: Vec<ExprPossibilities> = Vec::new();
+                let mut has_keyword = false;
                 while !self.match_tok(&[TokenType::RIGHT_PAREN]) {
-                    let curr = self.expression()?;
-                    arg_vec.push(curr);
+                    // Keyword arguments are stored as EQUAL statements holding the parameter name
+                    if self.check(&TokenType::IDENTIFIER) && self.peek_next().tok == TokenType::EQUAL {
+                        let name = self.advance().clone();
+                        self.advance();
+                        has_keyword = true;
+                        arg_vec.push(ExprPossibilities::Stmt(Stmt {
+                            stmt: TokenType::EQUAL,
+                            ident: Some(name),
+                            inner: Some(Box::new(self.expression()?)),
+                            params: None,
+                            annotation: None,
+                            depth: None,
+                            slot: None,
+                            tail: false,
+                        }));
+                    } else if has_keyword {
+                        return Err(ParsingException::PositionalAfterKeyword(self.peek().clone()));
+                    } else {
+                        let curr = self.expression()?;
+                        arg_vec.push(curr);
+                    }
 
                     let res = self.consume(&[TokenType::COMMA], ParsingException::PlaceHolder);
 
@@ -612,8 +788,15 @@ impl Parser {
                     ident: Some(ident),
                     inner: None,
                     params: Some(Box::new(arg_vec)),
+                    annotation: None,
+                    depth: None,
+                    slot: None,
+                    tail: false,
                 }));
             } else if self.match_tok(&[TokenType::EQUAL]) {
+                if self.is_constant(&ident.lexeme) {
+                    return Err(ParsingException::AssignToConstant(ident));
+                }
                 let expr = self.while_loop()?;
                 self.match_tok(&[TokenType::SEMICOLON]);
                 return Ok(ExprPossibilities::Stmt(Stmt {
@@ -621,6 +804,10 @@ impl Parser {
                     ident: Some(ident),
                     inner: Some(Box::new(expr)),
                     params: None,
+                    annotation: None,
+                    depth: None,
+                    slot: None,
+                    tail: false,
                 }));
             } else if self.match_tok(&[TokenType::LEFT_SQUARE]) {
                 let index = self.chain_bool()?;
@@ -643,6 +830,10 @@ impl Parser {
                         }),
                         inner: None,
                         params: Some(Box::new(vec![index, self.func_def()?])),
+                        annotation: None,
+                        depth: None,
+                        slot: None,
+                        tail: false,
                     }));
                 }
                 return Ok(ExprPossibilities::Stmt(Stmt {
@@ -650,6 +841,10 @@ impl Parser {
                     ident: Some(ident),
                     inner: Some(Box::new(index)),
                     params: None,
+                    annotation: None,
+                    depth: None,
+                    slot: None,
+                    tail: false,
                 }));
             }
             return Ok(ExprPossibilities::Stmt(Stmt {
@@ -657,6 +852,10 @@ impl Parser {
                 ident: Some(ident),
                 inner: None,
                 params: None,
+                annotation: None,
+                depth: None,
+                slot: None,
+                tail: false,
             }));
         }
 
@@ -728,6 +927,13 @@ impl Parser {
         return &self.tokens[self.current];
     }
 
+    fn peek_next(&self) -> &Token {
+        if self.current + 1 >= self.tokens.len() {
+            return self.peek();
+        }
+        return &self.tokens[self.current + 1];
+    }
+
     fn consume(
         &mut self,
         tok_type: &[TokenType],
@@ -0,0 +1,465 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{
+    ast::expr_types::{ExprPossibilities, Scope, Stmt},
+    error_reporting::resolve_err::ResolverWarning,
+    lib_functions::GLOBAL_FUNCTIONS,
+    scanner::token::{Token, TokenType},
+};
+
+const LIST_FIELDS: [&str; 20] = [
+    "list", "set", "len", "append", "slice", "map", "filter", "reduce", "any", "all", "find",
+    "sort", "reverse", "zip", "enumerate", "contains", "index_of", "insert", "remove", "pop",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum ScopeKind {
+    Global,
+    Block,
+    Func,
+    EnvCall,
+}
+
+struct Variable {
+    ident: Token,
+    used: bool,
+    // Position in its scope, in the order names are first declared there
+    slot: usize,
+    is_func: bool,
+}
+
+struct ResolverScope {
+    kind: ScopeKind,
+    vars: HashMap<String, Variable>,
+    // Names declared further down the same block, used to catch reads before the declaration
+    pending: HashSet<String>,
+}
+
+// Runs between the parser and the interpreter. Identifiers that can be bound statically get
+// the number of scopes to their declaration, anything crossing a function body or env call
+// is left for the interpreter to look up, since both can bring in names only known at runtime.
+pub struct Resolver {
+    scopes: Vec<ResolverScope>,
+    known_names: HashSet<String>,
+    warnings: Vec<ResolverWarning>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        return Self {
+            scopes: Vec::new(),
+            known_names: HashSet::new(),
+            warnings: Vec::new(),
+        };
+    }
+
+    pub fn resolve_program(
+        &mut self,
+        exprs: &mut [ExprPossibilities],
+        imports: &HashSet<String>,
+    ) -> Vec<ResolverWarning> {
+        for expr in exprs.iter() {
+            self.collect_names(expr);
+        }
+        self.known_names.extend(
+            GLOBAL_FUNCTIONS
+                .iter()
+                .map(|(name, _)| name)
+                .chain(LIST_FIELDS.iter())
+                .map(|name| name.to_string()),
+        );
+        self.known_names.extend(imports.iter().cloned());
+
+        self.begin_scope(ScopeKind::Global, exprs);
+        for name in GLOBAL_FUNCTIONS
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .chain(imports.iter().cloned())
+        {
+            self.scopes[0].vars.insert(
+                name.clone(),
+                Variable {
+                    ident: Token::new(TokenType::IDENTIFIER, name, 0, None),
+                    used: true,
+                    slot: 0,
+                    is_func: false,
+                },
+            );
+        }
+
+        for expr in exprs.iter_mut() {
+            self.resolve_expr(expr);
+        }
+        self.scopes.pop();
+        return self.warnings.drain(..).collect();
+    }
+
+    // Every name declared anywhere in the program, so dynamically scoped lookups
+    // of names that do exist somewhere aren't reported as undefined
+    fn collect_names(&mut self, expr: &ExprPossibilities) {
+        match expr {
+            ExprPossibilities::Binary(bin) => {
+                self.collect_names(&bin.left);
+                self.collect_names(&bin.right);
+            }
+            ExprPossibilities::Grouping(group) => {
+                for expr in group.expr.iter() {
+                    self.collect_names(expr);
+                }
+            }
+            ExprPossibilities::Ternary(ternary) => {
+                for expr in ternary.true_cond.iter().chain(ternary.false_cond.iter()) {
+                    self.collect_names(expr);
+                }
+            }
+            ExprPossibilities::Stmt(stmt) => {
+                if let TokenType::LET | TokenType::CONST = stmt.stmt {
+                    self.known_names
+                        .insert(stmt.ident.as_ref().unwrap().lexeme.clone());
+                }
+            }
+            ExprPossibilities::Scope(scope) => {
+                if let TokenType::FUNC | TokenType::CLOS | TokenType::IN = scope.stmt {
+                    self.known_names
+                        .insert(scope.ident.as_ref().unwrap().lexeme.clone());
+                }
+                for param in scope.params.iter().flatten() {
+                    self.known_names.insert(param.ident.lexeme.clone());
+                }
+                for expr in scope
+                    .condition
+                    .iter()
+                    .map(|cond| cond.as_ref())
+                    .chain(scope.inner.iter())
+                {
+                    self.collect_names(expr);
+                }
+            }
+            ExprPossibilities::Literal(_) | ExprPossibilities::Unary(_) => {}
+        }
+    }
+
+    fn begin_scope(&mut self, kind: ScopeKind, inner: &[ExprPossibilities]) {
+        let mut pending = HashSet::new();
+        for expr in inner.iter() {
+            if let ExprPossibilities::Stmt(stmt) = expr && let TokenType::LET | TokenType::CONST = stmt.stmt {
+                pending.insert(stmt.ident.as_ref().unwrap().lexeme.clone());
+            } else if let ExprPossibilities::Scope(scope) = expr && let TokenType::CLOS = scope.stmt {
+                pending.insert(scope.ident.as_ref().unwrap().lexeme.clone());
+            }
+        }
+
+        self.scopes.push(ResolverScope {
+            kind,
+            vars: HashMap::new(),
+            pending,
+        });
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        // Names declared in an env call become fields of the env, so they outlive the block
+        if let ScopeKind::Block | ScopeKind::Func = scope.kind {
+            let mut unused: Vec<Variable> =
+                scope.vars.into_values().filter(|var| !var.used).collect();
+            unused.sort_by_key(|var| var.ident.line);
+            for var in unused {
+                self.warnings.push(ResolverWarning::Unused(var.ident));
+            }
+        }
+    }
+
+    // A function defined under a name that already holds one is added to it as an overload,
+    // so it neither declares anything nor shadows the function it's added to
+    fn declare_func(&mut self, ident: &Token) {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(var) = scope.vars.get_mut(&ident.lexeme) {
+                if var.is_func {
+                    var.used = true;
+                    return;
+                }
+                break;
+            } else if let ScopeKind::Func | ScopeKind::EnvCall = scope.kind {
+                break;
+            }
+        }
+        self.declare(ident, true);
+        let scope = self.scopes.last_mut().unwrap();
+        scope.vars.get_mut(&ident.lexeme).unwrap().is_func = true;
+    }
+
+    fn declare(&mut self, ident: &Token, used: bool) {
+        let kind = self.scopes.last().unwrap().kind;
+        if let ScopeKind::Block | ScopeKind::Func = kind {
+            for scope in self.scopes.iter().rev().skip(1) {
+                if scope.vars.contains_key(&ident.lexeme) {
+                    self.warnings.push(ResolverWarning::Shadowed(ident.clone()));
+                    break;
+                } else if scope.kind != ScopeKind::Block {
+                    break;
+                }
+            }
+        }
+
+        let scope = self.scopes.last_mut().unwrap();
+        scope.pending.remove(&ident.lexeme);
+        let slot = match scope.vars.get(&ident.lexeme) {
+            Some(var) => var.slot,
+            None => scope.vars.len(),
+        };
+        scope.vars.insert(
+            ident.lexeme.clone(),
+            Variable {
+                ident: ident.clone(),
+                used,
+                slot,
+                is_func: false,
+            },
+        );
+    }
+
+    // The depth of the name's declaration, and its slot there. Globals aren't given a slot,
+    // since the host and imports declare them in an order the resolver doesn't see.
+    fn resolve(&mut self, ident: &Token, is_read: bool) -> Option<(usize, Option<usize>)> {
+        let mut depth = 0;
+        let mut used_early = false;
+        let mut boundary = None;
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(var) = scope.vars.get_mut(&ident.lexeme) {
+                var.used |= is_read;
+                if used_early {
+                    self.warnings
+                        .push(ResolverWarning::UsedBeforeDefinition(ident.clone()));
+                }
+                let slot = (scope.kind != ScopeKind::Global).then_some(var.slot);
+                return Some((depth, slot));
+            }
+
+            used_early |= scope.pending.contains(&ident.lexeme);
+            if let ScopeKind::Func | ScopeKind::EnvCall = scope.kind {
+                boundary = Some(scope.kind);
+                break;
+            }
+            depth += 1;
+        }
+
+        if used_early {
+            self.warnings
+                .push(ResolverWarning::UsedBeforeDefinition(ident.clone()));
+        } else if boundary != Some(ScopeKind::EnvCall) && !self.known_names.contains(&ident.lexeme)
+        {
+            self.warnings
+                .push(ResolverWarning::Undefined(ident.clone()));
+        }
+        return None;
+    }
+
+    fn resolve_stmt(&mut self, stmt: &mut Stmt, is_read: bool) {
+        let resolved = self.resolve(stmt.ident.as_ref().unwrap(), is_read);
+        stmt.depth = resolved.map(|(depth, _)| depth);
+        stmt.slot = resolved.and_then(|(_, slot)| slot);
+    }
+
+    fn resolve_block(&mut self, kind: ScopeKind, scope: &mut Scope) {
+        self.begin_scope(kind, &scope.inner);
+        if let TokenType::WHILE | TokenType::FOR = scope.stmt && let Some(condition) = &mut scope.condition {
+            self.resolve_expr(condition);
+        }
+        for expr in scope.inner.iter_mut() {
+            self.resolve_expr(expr);
+        }
+        self.end_scope();
+    }
+
+    fn resolve_func(&mut self, scope: &mut Scope) {
+        self.begin_scope(ScopeKind::Func, &scope.inner);
+        for param in scope.params.iter_mut().flatten() {
+            if let Some(default) = &mut param.default {
+                self.resolve_expr(default);
+            }
+            self.declare(&param.ident, true);
+        }
+        for expr in scope.inner.iter_mut() {
+            self.resolve_expr(expr);
+        }
+        if let Some(last) = scope.inner.last_mut() {
+            Self::mark_tail(last);
+        }
+        self.end_scope();
+    }
+
+    // A `return` whose value doesn't end the function carries on with the statements after it,
+    // so only a call returned by the function's last statement, or the last statement of an
+    // if there, can take the function's place
+    fn mark_tail(expr: &mut ExprPossibilities) {
+        match expr {
+            ExprPossibilities::Stmt(stmt) if stmt.stmt == TokenType::RETURN => {
+                if let Some(ExprPossibilities::Stmt(call)) = stmt.inner.as_deref() && call.stmt == TokenType::FUNC {
+                    stmt.tail = true;
+                }
+            }
+            ExprPossibilities::Ternary(ternary) => {
+                for branch in ternary
+                    .true_cond
+                    .iter_mut()
+                    .chain(ternary.false_cond.iter_mut())
+                {
+                    Self::mark_tail(branch);
+                }
+            }
+            ExprPossibilities::Scope(scope) if scope.stmt == TokenType::IF => {
+                if let Some(last) = Rc::make_mut(scope).inner.last_mut() {
+                    Self::mark_tail(last);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Fills in the depth of every identifier in place, so the tree is never rebuilt
+    fn resolve_expr(&mut self, expr: &mut ExprPossibilities) {
+        match expr {
+            ExprPossibilities::Binary(bin) => {
+                self.resolve_expr(&mut bin.left);
+                self.resolve_expr(&mut bin.right);
+            }
+            ExprPossibilities::Grouping(group) => {
+                for expr in group.expr.iter_mut() {
+                    self.resolve_expr(expr);
+                }
+            }
+            ExprPossibilities::Literal(_) => {}
+            ExprPossibilities::Ternary(ternary) => {
+                self.resolve_expr(&mut ternary.condition);
+                for expr in ternary
+                    .true_cond
+                    .iter_mut()
+                    .chain(ternary.false_cond.iter_mut())
+                {
+                    self.resolve_expr(expr);
+                }
+            }
+            ExprPossibilities::Unary(unary) => self.resolve_expr(&mut unary.right),
+            ExprPossibilities::Stmt(stmt) => {
+                if let Some(inner) = &mut stmt.inner {
+                    self.resolve_expr(inner);
+                }
+                for param in stmt.params.iter_mut().flat_map(|params| params.iter_mut()) {
+                    self.resolve_expr(param);
+                }
+
+                match stmt.stmt {
+                    TokenType::LET | TokenType::CONST => {
+                        self.declare(stmt.ident.as_ref().unwrap(), false);
+                    }
+                    TokenType::IDENTIFIER => {
+                        let is_read = stmt.inner.is_none();
+                        self.resolve_stmt(stmt, is_read);
+                    }
+                    TokenType::FUNC | TokenType::LEFT_SQUARE => self.resolve_stmt(stmt, true),
+                    _ => {}
+                }
+            }
+            ExprPossibilities::Scope(scope) => {
+                // Nothing else holds the tree yet, so this doesn't copy the scope
+                let scope = Rc::make_mut(scope);
+                match scope.stmt {
+                    TokenType::FUNC => {
+                        self.declare_func(scope.ident.as_ref().unwrap());
+                        self.resolve_func(scope);
+                    }
+                    TokenType::CLOS => {
+                        // Fields are evaluated in the declaring scope, but don't become variables in it
+                        for field in scope.inner.iter_mut() {
+                            if let ExprPossibilities::Stmt(field) = field && let Some(inner) = &mut field.inner {
+                                self.resolve_expr(inner);
+                            } else if let ExprPossibilities::Scope(method) = field {
+                                self.resolve_func(Rc::make_mut(method));
+                            }
+                        }
+                        self.declare(scope.ident.as_ref().unwrap(), true);
+                    }
+                    TokenType::CLOSCALL => {
+                        self.resolve(scope.ident.as_ref().unwrap(), true);
+                        self.resolve_block(ScopeKind::EnvCall, scope);
+                    }
+                    TokenType::IF | TokenType::WHILE | TokenType::FOR => {
+                        self.resolve_block(ScopeKind::Block, scope);
+                    }
+                    // The items are evaluated before the loop's scope is entered
+                    TokenType::IN => {
+                        self.resolve_expr(scope.condition.as_mut().unwrap());
+                        self.begin_scope(ScopeKind::Block, &scope.inner);
+                        self.declare(scope.ident.as_ref().unwrap(), true);
+                        for expr in scope.inner.iter_mut() {
+                            self.resolve_expr(expr);
+                        }
+                        self.end_scope();
+                    }
+                    _ => {
+                        for expr in scope.inner.iter_mut() {
+                            self.resolve_expr(expr);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        engine::testing::run_both, error_reporting::error_reporter::Unwindable, Engine,
+    };
+
+    fn warnings(source: &str) -> Vec<String> {
+        let mut engine = Engine::new();
+        engine.eval_str(source).unwrap();
+        return engine.warnings().iter().map(Unwindable::get_value).collect();
+    }
+
+    #[test]
+    fn overload_in_a_block_doesnt_shadow() {
+        let source = "
+            func g(a) { return a; }
+            if true {
+                func g(a, b) { return a + b; }
+            }
+            g(1, 2);
+        ";
+        assert!(warnings(source).is_empty());
+        assert_eq!(
+            warnings("func f() { let x = 1; if true { let x = 2; println(x); } println(x); }"),
+            ["Warning: 'x' on line 1 shadows a variable from an enclosing scope"]
+        );
+    }
+
+    #[test]
+    fn resolved_variables_keep_their_slots() {
+        let source = "
+            func g(a) { return a; }
+            func f(a, b) {
+                let c = a + b;
+                if true {
+                    func g(a, b) { return a * b; }
+                    let d = c + 1;
+                    c = d * 2;
+                }
+                let e = c;
+                return g(e, b);
+            }
+            println(f(1, 2));
+            let n = 0;
+            while n < 3 {
+                let m = n * 10;
+                n = n + 1;
+                println(m);
+            }
+        ";
+        assert_eq!(run_both(source), "16\n0\n10\n20\n");
+    }
+}
@@ -25,7 +25,7 @@ pub struct Scanner {
 
 impl ErrorReport for Scanner {
     fn print_error<E: Unwindable, T: Display + Literal + Clone>(error: E, literal: Option<T>) {
-        println!(
+        eprintln!(
             "{} on token: {:#} on line {}",
             error.get_value(),
             literal.clone().unwrap(),
@@ -74,15 +74,14 @@ impl Scanner {
     }
 
     // Shell mode
-    pub fn accept_input() {
-        let mut interpreter = Interpreter::new();
-
+    pub fn accept_input(mut interpreter: Interpreter) {
         loop {
             print!("-> ");
             std::io::stdout().flush().unwrap();
             let mut line: String = String::new();
-            if let Ok(_) = std::io::stdin().read_line(&mut line) {
-                if line.contains("exit()") {
+            // Lines come from the same input scripts read with sys.read_line
+            if let Ok(read) = interpreter.stdin.read_line(&mut line) {
+                if read == 0 || line.contains("exit()") {
                     break;
                 } else {
                     let mut lexer = Self::start_scanner();
@@ -102,8 +101,12 @@ impl Scanner {
                         let expr = parser.parse();
                         if let Ok(expr_uw) = expr {
                             interpreter.interpret(&expr_uw);
+                            if let Some(code) = interpreter.exit_code {
+                                let _ = interpreter.stdout.flush();
+                                std::process::exit(code);
+                            }
                         } else if let Err(err) = expr {
-                            println!("{:?}", err);
+                            eprintln!("{}", err.get_value());
                             parser.current += 1;
                         }
                     }
@@ -111,7 +114,7 @@ impl Scanner {
                     lexer.start = 0;
                 }
             } else {
-                println!("Invalid line format")
+                eprintln!("Invalid line format")
             }
         }
     }
@@ -121,7 +124,14 @@ impl Scanner {
     }
 
     pub fn tokenize_buff(&mut self) {
-        let buff_len_idx = self.buff.len() - 1;
+        for (err, tok) in self.tokenize() {
+            Self::print_error(err, Some(tok));
+        }
+    }
+
+    // Tokenizes the buffer, handing back the errors instead of printing them
+    pub fn tokenize(&mut self) -> Vec<(ScanningException, Token)> {
+        let buff_len_idx = self.buff.len().saturating_sub(1);
         let cloned_ref = self.clone();
         let bytes = cloned_ref.buff.as_bytes();
 
@@ -136,15 +146,13 @@ impl Scanner {
             self.start = self.curr_char;
         }
 
-        for (err, tok) in self.error.drain(0..) {
-            Self::print_error(err, Some(tok));
-        }
+        return self.error.drain(0..).collect();
     }
 
     fn match_tok(&mut self, tok_type: Result<(TokenType, usize), ScanningException>, buff: &[u8]) {
         if let Ok(tok_type_uw) = tok_type {
-            self.add_token(tok_type_uw.0, None);
             self.advance_by(tok_type_uw.1);
+            self.add_token(tok_type_uw.0, None);
         } else if let Err(ScanningException::Newline) = tok_type {
             self.advance_by(1);
             self.curr_line += 1;
@@ -181,7 +189,11 @@ impl Scanner {
         let mut literal_val = String::new();
         loop {
             self.advance_by(1);
-            let curr_char = buff[self.curr_char] as char;
+            let Some(curr_char) = buff.get(self.curr_char).map(|c| *c as char) else {
+                self.curr_char = buff.len();
+                self.has_error = true;
+                return Some(ScanningException::UnterminatedString);
+            };
             if curr_char == '"' {
                 self.add_token(TokenType::STRING, Some(Primitive::String(literal_val)));
                 return None;
@@ -204,9 +216,10 @@ impl Scanner {
         let mut digit_count = 0;
 
         loop {
-            let curr_char = buff[self.curr_char] as char;
+            // A number can run up to the end of the source
+            let curr_char = buff.get(self.curr_char).map_or('\0', |c| *c as char);
             if curr_char == '.' && !has_decimal {
-                let next_char = buff[self.curr_char + 1] as char;
+                let next_char = buff.get(self.curr_char + 1).map_or('\0', |c| *c as char);
                 if next_char >= '0' && next_char <= '9' {
                     has_decimal = true;
                     digit_count = 1;
@@ -1,15 +1,24 @@
 use std::{
+    cmp::Ordering,
     collections::{HashMap, HashSet},
     convert::TryInto,
     fmt::Display,
     ops::Add,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering as AtomicOrdering},
 };
 
 use crate::{
-    ast::expr_types::{Scope, Stmt},
-    error_reporting::{error_reporter::Literal, scanning_err::ScanningException},
-    interpreter::environment::Environment,
-    lib_functions::LibFunctions,
+    ast::expr_types::{Param, Scope, Stmt},
+    error_reporting::{
+        error_reporter::Literal, interp_err::InterpException, scanning_err::ScanningException,
+    },
+    heap::heap::Gc,
+    interpreter::{display, environment::Environment},
+    lib_functions::{
+        collections::{Deque, Set},
+        LibFunctions,
+    },
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -56,6 +65,7 @@ pub enum TokenType {
     ELSE,
     FALSE,
     FOR,
+    IN,
     IF,
     NIL,
     OR,
@@ -65,6 +75,7 @@ pub enum TokenType {
     SELF,
     TRUE,
     LET,
+    CONST,
     WHILE,
     CLOS,
     CLOSCALL,
@@ -152,6 +163,7 @@ impl TokenType {
             "else" => TokenType::ELSE,
             "false" => TokenType::FALSE,
             "for" => TokenType::FOR,
+            "in" => TokenType::IN,
             "if" => TokenType::IF,
             "null" => TokenType::NIL,
             "or" | "||" => TokenType::OR,
@@ -160,6 +172,7 @@ impl TokenType {
             "self" => TokenType::SELF,
             "true" => TokenType::TRUE,
             "let" => TokenType::LET,
+            "const" => TokenType::CONST,
             "while" => TokenType::WHILE,
             "decenv" => TokenType::CLOS,
             "env" => TokenType::CLOSCALL,
@@ -180,47 +193,251 @@ impl TokenType {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone)]
 pub enum Primitive {
     Float(f32),
     Int(isize),
     String(String),
     Bool(bool),
-    Env(Environment),
+    // Envs, lists, sets and deques live on the heap, so every copy of one refers to the
+    // same object
+    Env(Gc<Environment>),
     Func(Func),
     NativeFunc(LibFunctions),
-    List(Vec<Primitive>),
+    List(Gc<Vec<Primitive>>),
+    Set(Gc<Set>),
+    Deque(Gc<Deque>),
     None,
 }
 
+static NEXT_FUNC_ID: AtomicUsize = AtomicUsize::new(0);
+
 #[derive(Debug, Clone)]
 pub struct Func {
-    pub func_map: HashMap<usize, (Vec<Token>, Box<Scope>)>,
+    pub id: usize,
+    // Bodies are shared, so copying a function value never copies its code
+    pub overloads: Vec<Rc<Scope>>,
 }
 
+impl Func {
+    pub fn new(overload: Rc<Scope>) -> Self {
+        return Self {
+            id: Self::next_id(),
+            overloads: vec![overload],
+        };
+    }
+
+    fn next_id() -> usize {
+        return NEXT_FUNC_ID.fetch_add(1, AtomicOrdering::Relaxed);
+    }
+
+    // Overloads may not accept the same number of arguments, so a call
+    // can never match more than one of them. The function gets a new id, since
+    // copies of it made before hold a different set of overloads.
+    pub fn add_overload(&mut self, overload: Rc<Scope>) -> Result<(), InterpException> {
+        let (new_min, new_max) = Self::arity(&overload);
+        for existing in self.overloads.iter() {
+            let (min, max) = Self::arity(existing);
+            if new_min <= max.unwrap_or(usize::MAX) && min <= new_max.unwrap_or(usize::MAX) {
+                let ident = overload.ident.clone().unwrap();
+                return Err(InterpException::AmbiguousOverload(ident.lexeme, ident.line));
+            }
+        }
+
+        self.overloads.push(overload);
+        self.id = Self::next_id();
+        return Ok(());
+    }
+
+    pub fn resolve(&self, arg_count: usize) -> Option<&Scope> {
+        return self
+            .resolve_index(arg_count)
+            .map(|idx| self.overloads[idx].as_ref());
+    }
+
+    pub fn resolve_index(&self, arg_count: usize) -> Option<usize> {
+        for (idx, overload) in self.overloads.iter().enumerate() {
+            let (min, max) = Self::arity(overload);
+            if arg_count >= min && arg_count <= max.unwrap_or(usize::MAX) {
+                return Some(idx);
+            }
+        }
+
+        return None;
+    }
+
+    // Matches evaluated arguments to parameters. Parameters left as None fall back
+    // to their default value, which has to be evaluated in the function's scope.
+    pub fn bind_args(
+        func_ident: &Token,
+        params: &[Param],
+        positional: Vec<Primitive>,
+        keywords: Vec<(Token, Primitive)>,
+    ) -> Result<Vec<Option<Primitive>>, InterpException> {
+        let mut bound: Vec<Option<Primitive>> = vec![None; params.len()];
+        let fixed_len = params.iter().filter(|param| !param.variadic).count();
+        let mut positional = positional;
+        let rest = positional.split_off(positional.len().min(fixed_len));
+
+        for (idx, value) in positional.into_iter().enumerate() {
+            bound[idx] = Some(value);
+        }
+
+        for (name, value) in keywords.into_iter() {
+            let idx = params
+                .iter()
+                .position(|param| !param.variadic && param.ident.lexeme == name.lexeme);
+            match idx {
+                Some(idx) if bound[idx].is_none() => bound[idx] = Some(value),
+                _ => return Err(InterpException::InvalidKeywordArg(name)),
+            }
+        }
+
+        for (idx, param) in params.iter().enumerate() {
+            if param.variadic {
+                bound[idx] = Some(Primitive::List(Gc::new(rest.clone())));
+            } else if let None = bound[idx] && let None = param.default {
+                return Err(InterpException::MissingArgument(
+                    param.ident.lexeme.clone(),
+                    func_ident.line,
+                ));
+            }
+        }
+
+        return Ok(bound);
+    }
+
+    // Minimum and maximum number of arguments, None meaning unbounded
+    pub fn arity(overload: &Scope) -> (usize, Option<usize>) {
+        let mut min = 0;
+        let mut max = Some(0);
+        for param in overload.params.iter().flatten() {
+            if param.variadic {
+                max = None;
+            } else {
+                max = max.map(|max| max + 1);
+                if let None = param.default {
+                    min += 1;
+                }
+            }
+        }
+
+        return (min, max);
+    }
+}
+
+// Functions are equal only if they come from the same definition, with the same overloads
 impl PartialEq for Func {
     fn eq(&self, other: &Self) -> bool {
-        self.func_map.len() == other.func_map.len()
+        self.id == other.id
+    }
+}
+
+// The int a float holds exactly, if it's whole and an isize can hold it
+pub fn exact_int(flt: f32) -> Option<isize> {
+    // isize::MAX rounds up to 2^63 as a float, so it's the first float out of range
+    if flt.fract() == 0.0 && flt >= isize::MIN as f32 && flt < isize::MAX as f32 {
+        return Some(flt as isize);
     }
+    return None;
 }
 
-impl PartialOrd for Func {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let method_len = self.func_map.len();
-        let other_len = other.func_map.len();
+// Casting either one to the other's type would round, making 2^24 + 1 equal 2^24 as a float
+fn compare_int_float(int: isize, flt: f32) -> Option<Ordering> {
+    if flt.is_nan() {
+        return None;
+    } else if flt >= isize::MAX as f32 {
+        return Some(Ordering::Less);
+    } else if flt < isize::MIN as f32 {
+        return Some(Ordering::Greater);
+    }
+    let whole = flt.trunc();
+    return Some(int.cmp(&(whole as isize)).then(whole.partial_cmp(&flt)?));
+}
 
-        return Some(method_len.cmp(&other_len));
+impl PartialEq for Primitive {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Primitive::Int(num1), Primitive::Int(num2)) => num1 == num2,
+            (Primitive::Float(num1), Primitive::Float(num2)) => num1 == num2,
+            (Primitive::Int(int), Primitive::Float(flt))
+            | (Primitive::Float(flt), Primitive::Int(int)) => {
+                compare_int_float(*int, *flt) == Some(Ordering::Equal)
+            }
+            (Primitive::String(str1), Primitive::String(str2)) => str1 == str2,
+            (Primitive::Bool(bool1), Primitive::Bool(bool2)) => bool1 == bool2,
+            (Primitive::Env(env1), Primitive::Env(env2)) => env1 == env2,
+            (Primitive::Func(func1), Primitive::Func(func2)) => func1 == func2,
+            (Primitive::NativeFunc(func1), Primitive::NativeFunc(func2)) => func1 == func2,
+            (Primitive::List(list1), Primitive::List(list2)) => list1 == list2,
+            (Primitive::Set(set1), Primitive::Set(set2)) => set1 == set2,
+            (Primitive::Deque(deque1), Primitive::Deque(deque2)) => deque1 == deque2,
+            (Primitive::None, Primitive::None) => true,
+            _ => false,
+        }
     }
 }
 
 impl Primitive {
+    // Returns None when the two values have no defined ordering
+    pub fn compare(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Primitive::Int(num1), Primitive::Int(num2)) => Some(num1.cmp(num2)),
+            (Primitive::Float(num1), Primitive::Float(num2)) => num1.partial_cmp(num2),
+            (Primitive::Int(num1), Primitive::Float(num2)) => compare_int_float(*num1, *num2),
+            (Primitive::Float(num1), Primitive::Int(num2)) => {
+                compare_int_float(*num2, *num1).map(Ordering::reverse)
+            }
+            (Primitive::String(str1), Primitive::String(str2)) => Some(str1.cmp(str2)),
+            (Primitive::Bool(bool1), Primitive::Bool(bool2)) => Some(bool1.cmp(bool2)),
+            (Primitive::List(list1), Primitive::List(list2)) => {
+                let (list1, list2) = (list1.borrow(), list2.borrow());
+                for (item1, item2) in list1.iter().zip(list2.iter()) {
+                    match item1.compare(item2)? {
+                        Ordering::Equal => {}
+                        ord => return Some(ord),
+                    }
+                }
+                return Some(list1.len().cmp(&list2.len()));
+            }
+            (Primitive::Deque(deque1), Primitive::Deque(deque2)) => {
+                let (deque1, deque2) = (deque1.borrow(), deque2.borrow());
+                for (item1, item2) in deque1.items.iter().zip(deque2.items.iter()) {
+                    match item1.compare(item2)? {
+                        Ordering::Equal => {}
+                        ord => return Some(ord),
+                    }
+                }
+                return Some(deque1.items.len().cmp(&deque2.items.len()));
+            }
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Primitive::Float(_) => "float",
+            Primitive::Int(_) => "int",
+            Primitive::String(_) => "string",
+            Primitive::Bool(_) => "bool",
+            Primitive::Env(_) => "env",
+            Primitive::Func(_) | Primitive::NativeFunc(_) => "func",
+            Primitive::List(_) => "list",
+            Primitive::Set(_) => "set",
+            Primitive::Deque(_) => "deque",
+            Primitive::None => "null",
+        }
+    }
+
     pub fn get_value_as_str(&self) -> Option<String> {
         match self {
             Primitive::Float(float) => Some(float.to_string()),
             Primitive::Int(int) => Some(int.to_string()),
             Primitive::String(strng) => Some(strng.to_string()),
             Primitive::Bool(boolean) => Some(boolean.to_string()),
-            Primitive::Env(env) => Some(format!("{:?}", env)),
+            Primitive::Env(_) | Primitive::Set(_) | Primitive::Deque(_) => {
+                Some(display::plain(self))
+            }
             _ => None,
         }
     }
@@ -260,3 +477,26 @@ impl Literal for Token {
         return self.line;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::Primitive;
+
+    #[test]
+    fn ints_and_floats_compare_exactly() {
+        let (big, rounded) = (Primitive::Int(16_777_217), Primitive::Float(16_777_216.0));
+        assert!(big != rounded);
+        assert_eq!(big.compare(&rounded), Some(Ordering::Greater));
+        assert_eq!(rounded.compare(&big), Some(Ordering::Less));
+        assert!(Primitive::Int(16_777_216) == rounded);
+        assert_eq!(Primitive::Int(2).compare(&Primitive::Float(2.5)), Some(Ordering::Less));
+        assert_eq!(Primitive::Int(-2).compare(&Primitive::Float(-2.5)), Some(Ordering::Greater));
+        assert_eq!(
+            Primitive::Int(isize::MAX).compare(&Primitive::Float(isize::MAX as f32)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(Primitive::Int(0).compare(&Primitive::Float(f32::NAN)), None);
+    }
+}
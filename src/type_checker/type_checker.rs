@@ -0,0 +1,551 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{
+    ast::{
+        ast_traits::Interperable,
+        expr_types::{ExprPossibilities, Param, Scope, Stmt},
+    },
+    error_reporting::type_err::TypeException,
+    lib_functions::GLOBAL_FUNCTIONS,
+    scanner::token::{Func, Primitive, Token, TokenType},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    String,
+    Bool,
+    List,
+    Set,
+    Deque,
+    Env,
+    Func,
+    Null,
+    Any,
+}
+
+impl Type {
+    pub fn of(prim: &Primitive) -> Self {
+        match prim {
+            Primitive::Float(_) => Type::Float,
+            Primitive::Int(_) => Type::Int,
+            Primitive::String(_) => Type::String,
+            Primitive::Bool(_) => Type::Bool,
+            Primitive::Env(_) => Type::Env,
+            Primitive::Func(_) | Primitive::NativeFunc(_) => Type::Func,
+            Primitive::List(_) => Type::List,
+            Primitive::Set(_) => Type::Set,
+            Primitive::Deque(_) => Type::Deque,
+            Primitive::None => Type::Null,
+        }
+    }
+
+    pub fn from_annotation(tok: &Token) -> Result<Self, TypeException> {
+        match tok.lexeme.as_str() {
+            "int" => Ok(Type::Int),
+            "float" => Ok(Type::Float),
+            "string" | "str" => Ok(Type::String),
+            "bool" => Ok(Type::Bool),
+            "list" => Ok(Type::List),
+            "set" => Ok(Type::Set),
+            "deque" => Ok(Type::Deque),
+            "env" => Ok(Type::Env),
+            "func" => Ok(Type::Func),
+            "null" => Ok(Type::Null),
+            "any" => Ok(Type::Any),
+            _ => Err(TypeException::UnknownType(tok.clone())),
+        }
+    }
+
+    // null may be stored anywhere, since it is how vmod marks an empty value
+    fn accepts(&self, other: Type) -> bool {
+        return *self == other || *self == Type::Any || other == Type::Any || other == Type::Null;
+    }
+
+    fn is_numeric(&self) -> bool {
+        return *self == Type::Int || *self == Type::Float;
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::String => "string",
+            Type::Bool => "bool",
+            Type::List => "list",
+            Type::Set => "set",
+            Type::Deque => "deque",
+            Type::Env => "env",
+            Type::Func => "func",
+            Type::Null => "null",
+            Type::Any => "any",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Binding {
+    ty: Type,
+    annotated: bool,
+    fields: Option<HashMap<String, Binding>>,
+}
+
+impl Binding {
+    fn new(ty: Type, annotated: bool) -> Self {
+        return Self {
+            ty,
+            annotated,
+            fields: None,
+        };
+    }
+}
+
+// Checks annotated code before it runs. Unannotated bindings take the type of their
+// first value and fall back to `any` once they are assigned something else.
+pub struct TypeChecker {
+    scopes: Vec<HashMap<String, Binding>>,
+    funcs: HashMap<String, Vec<Scope>>,
+    current_func: Option<(Token, Option<Type>)>,
+    errors: Vec<TypeException>,
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        let mut globals = HashMap::new();
+        for (native, _) in GLOBAL_FUNCTIONS.iter() {
+            globals.insert(native.to_string(), Binding::new(Type::Func, true));
+        }
+
+        return Self {
+            scopes: vec![globals],
+            funcs: HashMap::new(),
+            current_func: None,
+            errors: Vec::new(),
+        };
+    }
+
+    pub fn check_program(&mut self, exprs: &[ExprPossibilities]) -> Vec<TypeException> {
+        // Functions are hoisted, so their signatures are known before any call is checked
+        for expr in exprs.iter() {
+            if let ExprPossibilities::Scope(scope) = expr && let TokenType::FUNC = scope.stmt {
+                self.declare_func(scope);
+            }
+        }
+
+        for expr in exprs.iter() {
+            self.check(expr);
+        }
+
+        return self.errors.drain(..).collect();
+    }
+
+    fn check(&mut self, expr: &ExprPossibilities) -> Type {
+        return self.visit_expr(expr);
+    }
+
+    fn annotation(&mut self, annotation: &Option<Token>) -> Option<Type> {
+        match annotation {
+            Some(tok) => match Type::from_annotation(tok) {
+                Ok(ty) => Some(ty),
+                Err(err) => {
+                    self.errors.push(err);
+                    Some(Type::Any)
+                }
+            },
+            None => None,
+        }
+    }
+
+    fn declare_func(&mut self, scope: &Scope) {
+        let name = scope.ident.clone().unwrap().lexeme;
+        self.funcs
+            .entry(name.clone())
+            .or_default()
+            .push(scope.clone());
+        self.define(&name, Binding::new(Type::Func, false));
+    }
+
+    fn define(&mut self, name: &str, binding: Binding) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), binding);
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut Binding> {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(binding) = scope.get_mut(name) {
+                return Some(binding);
+            }
+        }
+        return None;
+    }
+
+    fn check_block(
+        &mut self,
+        scope: HashMap<String, Binding>,
+        inner: &[ExprPossibilities],
+    ) -> HashMap<String, Binding> {
+        self.scopes.push(scope);
+        for line in inner.iter() {
+            self.check(line);
+        }
+        return self.scopes.pop().unwrap();
+    }
+
+    fn check_let(&mut self, stmt: &Stmt) -> (String, Binding) {
+        let ident = stmt.ident.clone().unwrap();
+        let found = match &stmt.inner {
+            Some(inner) => self.check(inner),
+            None => Type::Any,
+        };
+
+        match self.annotation(&stmt.annotation) {
+            Some(expected) => {
+                if !expected.accepts(found) {
+                    self.errors
+                        .push(TypeException::Mismatch(ident.clone(), expected, found));
+                }
+                return (ident.lexeme, Binding::new(expected, true));
+            }
+            None => return (ident.lexeme, Binding::new(found, false)),
+        }
+    }
+
+    fn check_assign(&mut self, ident: &Token, found: Type) {
+        let mut mismatch = None;
+        if let Some(binding) = self.lookup(&ident.lexeme) {
+            if binding.annotated && !binding.ty.accepts(found) {
+                mismatch = Some(binding.ty);
+            } else if !binding.annotated && binding.ty != found {
+                binding.ty = Type::Any;
+                binding.fields = None;
+            }
+        }
+
+        if let Some(expected) = mismatch {
+            self.errors
+                .push(TypeException::Mismatch(ident.clone(), expected, found));
+        }
+    }
+
+    fn check_call(&mut self, ident: &Token, args: &[ExprPossibilities]) -> Type {
+        let mut positional: Vec<Type> = Vec::new();
+        let mut keywords: Vec<(Token, Type)> = Vec::new();
+        for arg in args.iter() {
+            if let ExprPossibilities::Stmt(kwarg) = arg && let TokenType::EQUAL = kwarg.stmt {
+                let ty = self.check(kwarg.inner.as_ref().unwrap());
+                keywords.push((kwarg.ident.clone().unwrap(), ty));
+            } else {
+                positional.push(self.check(arg));
+            }
+        }
+
+        let overload = self.funcs.get(&ident.lexeme).and_then(|overloads| {
+            overloads.iter().find(|overload| {
+                let (min, max) = Func::arity(overload);
+                let count = args.len();
+                count >= min && count <= max.unwrap_or(usize::MAX)
+            })
+        });
+
+        match overload.cloned() {
+            Some(overload) => {
+                let params: Vec<Param> = overload.params.clone().unwrap_or_default();
+                for (param, found) in params
+                    .iter()
+                    .filter(|param| !param.variadic)
+                    .zip(positional)
+                {
+                    self.check_arg(param, found, ident.line);
+                }
+                for (name, found) in keywords {
+                    if let Some(param) = params
+                        .iter()
+                        .find(|param| param.ident.lexeme == name.lexeme)
+                    {
+                        self.check_arg(param, found, ident.line);
+                    }
+                }
+                return self.annotation(&overload.annotation).unwrap_or(Type::Any);
+            }
+            None => match ident.lexeme.as_str() {
+                "len" | "int" => Type::Int,
+                "float" => Type::Float,
+                "str" => Type::String,
+                "range" => Type::List,
+                _ => Type::Any,
+            },
+        }
+    }
+
+    fn check_arg(&mut self, param: &Param, found: Type, line: usize) {
+        if let Some(expected) = self.annotation(&param.annotation) && !expected.accepts(found) {
+            self.errors.push(TypeException::ArgumentMismatch(
+                param.ident.clone(),
+                expected,
+                found,
+                line,
+            ));
+        }
+    }
+
+    fn check_func(&mut self, scope: &Scope) {
+        let mut params = HashMap::new();
+        for param in scope.params.iter().flatten() {
+            let annotation = self.annotation(&param.annotation);
+            if let Some(default) = &param.default {
+                let found = self.check(default);
+                self.check_arg(param, found, param.ident.line);
+            }
+
+            let binding = match annotation {
+                Some(ty) => Binding::new(ty, true),
+                None if param.variadic => Binding::new(Type::List, true),
+                None => Binding::new(Type::Any, false),
+            };
+            params.insert(param.ident.lexeme.clone(), binding);
+        }
+
+        let ret = self.annotation(&scope.annotation);
+        let enclosing_func = self
+            .current_func
+            .replace((scope.ident.clone().unwrap(), ret));
+        self.check_block(params, &scope.inner);
+        self.current_func = enclosing_func;
+    }
+
+    fn binary_type(operator: &TokenType, left: Type, right: Type) -> Option<Type> {
+        match operator {
+            TokenType::EQUAL_EQUAL | TokenType::BANG_EQUAL => return Some(Type::Bool),
+            _ if left == Type::Any || right == Type::Any => match operator {
+                TokenType::GREATER
+                | TokenType::GREATER_EQUAL
+                | TokenType::LESS
+                | TokenType::LESS_EQUAL
+                | TokenType::AND
+                | TokenType::OR => Some(Type::Bool),
+                _ => Some(Type::Any),
+            },
+            TokenType::AND | TokenType::OR => {
+                (left == Type::Bool && right == Type::Bool).then_some(Type::Bool)
+            }
+            TokenType::GREATER
+            | TokenType::GREATER_EQUAL
+            | TokenType::LESS
+            | TokenType::LESS_EQUAL => {
+                let comparable = (left.is_numeric() && right.is_numeric())
+                    || (left == right
+                        && matches!(left, Type::String | Type::Bool | Type::List | Type::Deque));
+                comparable.then_some(Type::Bool)
+            }
+            TokenType::PLUS => match (left, right) {
+                (Type::Int, Type::Int | Type::Bool) => Some(Type::Int),
+                (Type::Int, Type::Float) | (Type::Float, Type::Float | Type::Int | Type::Bool) => {
+                    Some(Type::Float)
+                }
+                (Type::Int | Type::Float, Type::String) => Some(Type::String),
+                (Type::String, Type::Int | Type::Float | Type::String | Type::Bool) => {
+                    Some(Type::String)
+                }
+                // Shown the way print would show them
+                (Type::String, Type::List | Type::Env | Type::Set | Type::Deque)
+                | (Type::List | Type::Env | Type::Set | Type::Deque, Type::String) => {
+                    Some(Type::String)
+                }
+                _ => None,
+            },
+            TokenType::STAR => match (left, right) {
+                (Type::Int, Type::Int | Type::Bool) => Some(Type::Int),
+                (Type::Int, Type::Float) | (Type::Float, Type::Float) => Some(Type::Float),
+                _ => None,
+            },
+            TokenType::MINUS | TokenType::SLASH | TokenType::MODULO => {
+                (left == right && left.is_numeric()).then_some(left)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Interperable<Type> for TypeChecker {
+    fn visit_expr(&mut self, expr: &ExprPossibilities) -> Type {
+        match expr {
+            ExprPossibilities::Binary(bin) => {
+                let left = self.check(&bin.left);
+                let right = self.check(&bin.right);
+                match Self::binary_type(&bin.operator.tok, left, right) {
+                    Some(ty) => return ty,
+                    None => {
+                        self.errors
+                            .push(TypeException::InvalidOperands(bin.operator.clone(), left, right));
+                        return Type::Any;
+                    }
+                }
+            }
+            ExprPossibilities::Grouping(group) => {
+                let mut ty = Type::Null;
+                for (idx, expr) in group.expr.iter().enumerate() {
+                    let expr_ty = self.check(expr);
+                    if idx == 0 {
+                        ty = expr_ty;
+                    }
+                }
+                return ty;
+            }
+            ExprPossibilities::Literal(lit) => return Type::of(&lit.literal),
+            ExprPossibilities::Ternary(ternary) => {
+                self.check(&ternary.condition);
+                if let Some(expr) = &ternary.true_cond {
+                    self.check(expr);
+                }
+                if let Some(expr) = &ternary.false_cond {
+                    self.check(expr);
+                }
+                return Type::Null;
+            }
+            ExprPossibilities::Unary(unary) => {
+                let right = self.check(&unary.right);
+                match right {
+                    Type::Int | Type::Float | Type::Bool | Type::Any => return right,
+                    Type::Null => return Type::Bool,
+                    _ => {
+                        self.errors
+                            .push(TypeException::InvalidUnary(unary.operator.clone(), right));
+                        return Type::Any;
+                    }
+                }
+            }
+            ExprPossibilities::Stmt(stmt) => match stmt.stmt {
+                TokenType::LET | TokenType::CONST => {
+                    let (name, binding) = self.check_let(stmt);
+                    self.define(&name, binding);
+                    return Type::Null;
+                }
+                TokenType::IDENTIFIER => {
+                    let ident = stmt.ident.clone().unwrap();
+                    match &stmt.inner {
+                        Some(inner) => {
+                            let found = self.check(inner);
+                            self.check_assign(&ident, found);
+                            return Type::Null;
+                        }
+                        None => match self.lookup(&ident.lexeme) {
+                            Some(binding) => return binding.ty,
+                            None => return Type::Any,
+                        },
+                    }
+                }
+                TokenType::RETURN => {
+                    let found = self.check(stmt.inner.as_ref().unwrap());
+                    if let Some((func, Some(expected))) = self.current_func.clone() && !expected.accepts(found) {
+                        self.errors
+                            .push(TypeException::ReturnMismatch(func, expected, found));
+                    }
+                    return Type::Null;
+                }
+                TokenType::FUNC => {
+                    let args = stmt.params.as_deref().map(Vec::as_slice).unwrap_or_default();
+                    return self.check_call(stmt.ident.as_ref().unwrap(), args);
+                }
+                _ => {
+                    if let Some(inner) = &stmt.inner {
+                        self.check(inner);
+                    }
+                    for param in stmt.params.iter().flat_map(|params| params.iter()) {
+                        self.check(param);
+                    }
+                    if let TokenType::LEFT_SQUARE = stmt.stmt {
+                        return Type::Any;
+                    }
+                    return Type::Null;
+                }
+            },
+            ExprPossibilities::Scope(scope) => match scope.stmt {
+                TokenType::LEFT_SQUARE => {
+                    for item in scope.inner.iter() {
+                        self.check(item);
+                    }
+                    return Type::List;
+                }
+                TokenType::FUNC => {
+                    // Top level functions were already declared by check_program
+                    if self.scopes.len() > 1 {
+                        self.declare_func(scope);
+                    }
+                    self.check_func(scope);
+                    return Type::Null;
+                }
+                TokenType::CLOS => {
+                    let mut fields = HashMap::new();
+                    for var in scope.inner.iter() {
+                        if let ExprPossibilities::Stmt(var) = var {
+                            let (name, binding) = self.check_let(var);
+                            fields.insert(name, binding);
+                        } else if let ExprPossibilities::Scope(method) = var {
+                            self.check_func(method);
+                            let name = method.ident.clone().unwrap().lexeme;
+                            fields.insert(name, Binding::new(Type::Func, false));
+                        }
+                    }
+
+                    let mut binding = Binding::new(Type::Env, false);
+                    binding.fields = Some(fields);
+                    self.define(&scope.ident.as_ref().unwrap().lexeme, binding);
+                    return Type::Null;
+                }
+                TokenType::CLOSCALL => {
+                    let ident = scope.ident.clone().unwrap();
+                    let fields = self
+                        .lookup(&ident.lexeme)
+                        .and_then(|binding| binding.fields.clone());
+                    let checked =
+                        self.check_block(fields.clone().unwrap_or_default(), &scope.inner);
+                    if let Some(fields) = fields && let Some(binding) = self.lookup(&ident.lexeme) {
+                        binding.fields = Some(
+                            checked
+                                .into_iter()
+                                .filter(|(name, _)| fields.contains_key(name))
+                                .collect(),
+                        );
+                    }
+                    return Type::Null;
+                }
+                TokenType::IN => {
+                    let items = self.check(scope.condition.as_ref().unwrap());
+                    if !matches!(items, Type::List | Type::Set | Type::Deque | Type::Any) {
+                        self.errors.push(TypeException::NotIterable(
+                            scope.ident.clone().unwrap(),
+                            items,
+                        ));
+                    }
+                    self.scopes.push(HashMap::new());
+                    self.define(
+                        &scope.ident.as_ref().unwrap().lexeme,
+                        Binding::new(Type::Any, false),
+                    );
+                    for line in scope.inner.iter() {
+                        self.check(line);
+                    }
+                    self.scopes.pop();
+                    return Type::Null;
+                }
+                _ => {
+                    self.scopes.push(HashMap::new());
+                    if let Some(condition) = &scope.condition {
+                        self.check(condition);
+                    }
+                    for line in scope.inner.iter() {
+                        self.check(line);
+                    }
+                    self.scopes.pop();
+                    return Type::Null;
+                }
+            },
+        }
+    }
+}
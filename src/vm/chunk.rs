@@ -0,0 +1,138 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    ast::expr_types::Scope,
+    scanner::token::{Primitive, Token},
+};
+
+// Operands index into the tables of the chunk the instruction belongs to
+#[derive(Clone, Copy, Debug)]
+pub enum OpCode {
+    Constant(usize),
+    Nil,
+    Pop,
+    // Name, and the scope depth and slot found by the resolver if any
+    GetVar(usize, Option<usize>, Option<usize>),
+    SetVar(usize, Option<usize>, Option<usize>),
+    // Name, and whether the binding is const
+    Declare(usize, bool),
+    // Pops an index and the list it indexes, keeping the list's token for errors
+    Index(usize),
+    MakeList(usize),
+    Binary(usize),
+    Unary(usize),
+    Jump(usize),
+    // Pops the condition of an if
+    JumpIfFalse(usize),
+    // Pops the condition of a loop, which only continues on true
+    JumpUnlessTrue(usize),
+    Print(bool),
+    Call(usize),
+    // A call in return position, which replaces the function it's in
+    TailCall(usize),
+    // Any statement evaluating to a value ends the function it's in
+    ReturnIfSome,
+    EnterScope,
+    ExitScope,
+    // Leaving a while loop copies its variables back into the enclosing scope
+    ExitScopeWriteBack,
+    // Pops what a for loop goes over, pushing a copy of its items and the position
+    // reached, keeping the loop variable's token for errors
+    Iterate(usize),
+    // Defines the loop variable as the next item, or jumps out once there are none left
+    NextItem(usize, usize),
+    DefineFunc(usize),
+    DeclareEnv(usize),
+    EnterEnv(usize),
+    // Writes the fields back into the env last entered
+    ExitEnv,
+    Unsupported,
+}
+
+#[derive(Clone, Debug)]
+pub struct CallInfo {
+    pub ident: Token,
+    pub depth: Option<usize>,
+    pub slot: Option<usize>,
+    pub arg_count: usize,
+    // Keyword arguments are pushed after the positional ones, in this order
+    pub keywords: Vec<Token>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EnvInfo {
+    pub ident: usize,
+    pub fields: Vec<(usize, bool)>,
+    // Functions declared in the env, defined after its fields
+    pub methods: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub constants: Vec<Primitive>,
+    pub names: Vec<String>,
+    pub tokens: Vec<Token>,
+    pub calls: Vec<CallInfo>,
+    pub functions: Vec<Rc<Scope>>,
+    pub envs: Vec<EnvInfo>,
+    name_indices: HashMap<String, usize>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn emit(&mut self, op: OpCode) -> usize {
+        self.code.push(op);
+        return self.code.len() - 1;
+    }
+
+    // Points a previously emitted jump at the next instruction
+    pub fn patch_jump(&mut self, at: usize) {
+        let target = self.code.len();
+        match self.code[at] {
+            OpCode::Jump(_) => self.code[at] = OpCode::Jump(target),
+            OpCode::JumpIfFalse(_) => self.code[at] = OpCode::JumpIfFalse(target),
+            OpCode::JumpUnlessTrue(_) => self.code[at] = OpCode::JumpUnlessTrue(target),
+            OpCode::NextItem(name, _) => self.code[at] = OpCode::NextItem(name, target),
+            _ => {}
+        }
+    }
+
+    pub fn add_constant(&mut self, value: Primitive) -> usize {
+        self.constants.push(value);
+        return self.constants.len() - 1;
+    }
+
+    pub fn add_name(&mut self, name: &str) -> usize {
+        if let Some(idx) = self.name_indices.get(name) {
+            return *idx;
+        }
+        self.names.push(name.to_string());
+        self.name_indices
+            .insert(name.to_string(), self.names.len() - 1);
+        return self.names.len() - 1;
+    }
+
+    pub fn add_token(&mut self, token: Token) -> usize {
+        self.tokens.push(token);
+        return self.tokens.len() - 1;
+    }
+
+    pub fn add_call(&mut self, call: CallInfo) -> usize {
+        self.calls.push(call);
+        return self.calls.len() - 1;
+    }
+
+    pub fn add_function(&mut self, scope: Rc<Scope>) -> usize {
+        self.functions.push(scope);
+        return self.functions.len() - 1;
+    }
+
+    pub fn add_env(&mut self, env: EnvInfo) -> usize {
+        self.envs.push(env);
+        return self.envs.len() - 1;
+    }
+}
@@ -0,0 +1,274 @@
+use std::rc::Rc;
+
+use crate::{
+    ast::{
+        ast_traits::Interperable,
+        expr_types::{ExprPossibilities, Scope, Stmt},
+    },
+    scanner::token::TokenType,
+};
+
+use super::chunk::{CallInfo, Chunk, EnvInfo, OpCode};
+
+// A compiled function body. Default values get their own chunk, since they are
+// only run when the call leaves their parameter out.
+pub struct Function {
+    pub defaults: Vec<Option<Chunk>>,
+    pub body: Chunk,
+}
+
+// Every expression compiles to code leaving exactly one value on the stack,
+// statements leaving null
+pub struct Compiler {
+    chunk: Chunk,
+}
+
+impl Compiler {
+    fn new() -> Self {
+        return Self {
+            chunk: Chunk::new(),
+        };
+    }
+
+    // Top level statements run one after another, each in a chunk of its own
+    pub fn compile(expr: &ExprPossibilities) -> Chunk {
+        let mut compiler = Self::new();
+        compiler.visit_expr(expr);
+        compiler.chunk.emit(OpCode::Pop);
+        return compiler.chunk;
+    }
+
+    // Function bodies are compiled the first time the vm calls them
+    pub fn compile_function(scope: &Scope) -> Function {
+        let mut defaults = Vec::new();
+        for param in scope.params.iter().flatten() {
+            defaults.push(param.default.as_ref().map(|default| {
+                let mut compiler = Self::new();
+                compiler.visit_expr(default);
+                compiler.chunk
+            }));
+        }
+
+        let mut compiler = Self::new();
+        compiler.block(&scope.inner);
+        return Function {
+            defaults,
+            body: compiler.chunk,
+        };
+    }
+
+    fn block(&mut self, inner: &[ExprPossibilities]) {
+        for line in inner.iter() {
+            self.visit_expr(line);
+            self.chunk.emit(OpCode::ReturnIfSome);
+        }
+    }
+
+    // Pushes the arguments of a call, returning the index of its call info
+    fn call(&mut self, stmt: &Stmt) -> usize {
+        let mut keywords = Vec::new();
+        let args = stmt.params.as_deref().map(Vec::as_slice).unwrap_or_default();
+        for arg in args.iter() {
+            if let ExprPossibilities::Stmt(kwarg) = arg && let TokenType::EQUAL = kwarg.stmt {
+                keywords.push(kwarg.ident.clone().unwrap());
+                self.visit_expr(kwarg.inner.as_ref().unwrap());
+            } else {
+                self.visit_expr(arg);
+            }
+        }
+
+        return self.chunk.add_call(CallInfo {
+            ident: stmt.ident.clone().unwrap(),
+            depth: stmt.depth,
+            slot: stmt.slot,
+            arg_count: args.len(),
+            keywords,
+        });
+    }
+
+    fn scope(&mut self, scope: &Rc<Scope>) {
+        match scope.stmt {
+            TokenType::LEFT_SQUARE => {
+                for var in scope.inner.iter() {
+                    self.visit_expr(var);
+                }
+                self.chunk.emit(OpCode::MakeList(scope.inner.len()));
+            }
+            TokenType::FUNC => {
+                let idx = self.chunk.add_function(scope.clone());
+                self.chunk.emit(OpCode::DefineFunc(idx));
+            }
+            TokenType::CLOS => {
+                let ident = self.chunk.add_name(&scope.ident.as_ref().unwrap().lexeme);
+                let mut fields = Vec::new();
+                let mut methods = Vec::new();
+                for var in scope.inner.iter() {
+                    if let ExprPossibilities::Stmt(var) = var {
+                        let field = self.chunk.add_name(&var.ident.as_ref().unwrap().lexeme);
+                        fields.push((field, var.stmt == TokenType::CONST));
+                        self.visit_expr(var.inner.as_ref().unwrap());
+                    } else if let ExprPossibilities::Scope(method) = var {
+                        methods.push(self.chunk.add_function(method.clone()));
+                    }
+                }
+                let idx = self.chunk.add_env(EnvInfo {
+                    ident,
+                    fields,
+                    methods,
+                });
+                self.chunk.emit(OpCode::DeclareEnv(idx));
+            }
+            TokenType::CLOSCALL => {
+                let ident = self.chunk.add_name(&scope.ident.as_ref().unwrap().lexeme);
+                self.chunk.emit(OpCode::EnterEnv(ident));
+                self.block(&scope.inner);
+                self.chunk.emit(OpCode::ExitEnv);
+                self.chunk.emit(OpCode::Nil);
+            }
+            TokenType::IF => {
+                self.chunk.emit(OpCode::EnterScope);
+                self.block(&scope.inner);
+                self.chunk.emit(OpCode::ExitScope);
+                self.chunk.emit(OpCode::Nil);
+            }
+            TokenType::WHILE => {
+                self.chunk.emit(OpCode::EnterScope);
+                let loop_start = self.chunk.code.len();
+                self.visit_expr(scope.condition.as_ref().unwrap());
+                let exit = self.chunk.emit(OpCode::JumpUnlessTrue(0));
+                self.block(&scope.inner);
+                self.chunk.emit(OpCode::Jump(loop_start));
+                self.chunk.patch_jump(exit);
+                self.chunk.emit(OpCode::ExitScopeWriteBack);
+                self.chunk.emit(OpCode::Nil);
+            }
+            TokenType::FOR => {
+                self.chunk.emit(OpCode::EnterScope);
+                if let Some(ExprPossibilities::Grouping(group)) = scope.condition.as_deref() {
+                    self.visit_expr(&group.expr[0]);
+                    self.chunk.emit(OpCode::Pop);
+                    let loop_start = self.chunk.code.len();
+                    self.visit_expr(&group.expr[1]);
+                    let exit = self.chunk.emit(OpCode::JumpUnlessTrue(0));
+                    self.block(&scope.inner);
+                    self.visit_expr(&group.expr[2]);
+                    self.chunk.emit(OpCode::Pop);
+                    self.chunk.emit(OpCode::Jump(loop_start));
+                    self.chunk.patch_jump(exit);
+                }
+                self.chunk.emit(OpCode::ExitScope);
+                self.chunk.emit(OpCode::Nil);
+            }
+            TokenType::IN => {
+                let ident = scope.ident.clone().unwrap();
+                let name = self.chunk.add_name(&ident.lexeme);
+                self.visit_expr(scope.condition.as_ref().unwrap());
+                let token = self.chunk.add_token(ident);
+                self.chunk.emit(OpCode::Iterate(token));
+                self.chunk.emit(OpCode::EnterScope);
+                let loop_start = self.chunk.code.len();
+                let exit = self.chunk.emit(OpCode::NextItem(name, 0));
+                self.block(&scope.inner);
+                self.chunk.emit(OpCode::Jump(loop_start));
+                self.chunk.patch_jump(exit);
+                self.chunk.emit(OpCode::ExitScope);
+                self.chunk.emit(OpCode::Pop);
+                self.chunk.emit(OpCode::Pop);
+                self.chunk.emit(OpCode::Nil);
+            }
+            _ => {
+                self.chunk.emit(OpCode::Unsupported);
+            }
+        }
+    }
+}
+
+impl Interperable<()> for Compiler {
+    fn visit_expr(&mut self, expr: &ExprPossibilities) {
+        match expr {
+            ExprPossibilities::Binary(bin) => {
+                self.visit_expr(&bin.left);
+                self.visit_expr(&bin.right);
+                let operator = self.chunk.add_token(bin.operator.clone());
+                self.chunk.emit(OpCode::Binary(operator));
+            }
+            ExprPossibilities::Grouping(group) => self.visit_expr(&group.expr[0]),
+            ExprPossibilities::Literal(lit) => {
+                let idx = self.chunk.add_constant(lit.literal.clone());
+                self.chunk.emit(OpCode::Constant(idx));
+            }
+            ExprPossibilities::Ternary(ternary) => {
+                self.visit_expr(&ternary.condition);
+                let else_jump = self.chunk.emit(OpCode::JumpIfFalse(0));
+                match &ternary.true_cond {
+                    Some(expr) => self.visit_expr(expr),
+                    None => {
+                        self.chunk.emit(OpCode::Nil);
+                    }
+                }
+                let end_jump = self.chunk.emit(OpCode::Jump(0));
+                self.chunk.patch_jump(else_jump);
+                match &ternary.false_cond {
+                    Some(expr) => self.visit_expr(expr),
+                    None => {
+                        self.chunk.emit(OpCode::Nil);
+                    }
+                }
+                self.chunk.patch_jump(end_jump);
+            }
+            ExprPossibilities::Unary(unary) => {
+                self.visit_expr(&unary.right);
+                let operator = self.chunk.add_token(unary.operator.clone());
+                self.chunk.emit(OpCode::Unary(operator));
+            }
+            ExprPossibilities::Stmt(stmt) => match stmt.stmt {
+                TokenType::LEFT_SQUARE => {
+                    let ident = stmt.ident.as_ref().unwrap();
+                    let name = self.chunk.add_name(&ident.lexeme);
+                    self.chunk.emit(OpCode::GetVar(name, stmt.depth, stmt.slot));
+                    self.visit_expr(stmt.inner.as_ref().unwrap());
+                    let ident = self.chunk.add_token(ident.clone());
+                    self.chunk.emit(OpCode::Index(ident));
+                }
+                TokenType::RETURN => match stmt.inner.as_deref().unwrap() {
+                    ExprPossibilities::Stmt(call) if stmt.tail => {
+                        let idx = self.call(call);
+                        self.chunk.emit(OpCode::TailCall(idx));
+                    }
+                    inner => self.visit_expr(inner),
+                },
+                TokenType::PRINT | TokenType::PRINTLN => {
+                    self.visit_expr(stmt.inner.as_ref().unwrap());
+                    self.chunk
+                        .emit(OpCode::Print(stmt.stmt == TokenType::PRINTLN));
+                }
+                TokenType::LET | TokenType::CONST => {
+                    self.visit_expr(stmt.inner.as_ref().unwrap());
+                    let name = self.chunk.add_name(&stmt.ident.as_ref().unwrap().lexeme);
+                    self.chunk
+                        .emit(OpCode::Declare(name, stmt.stmt == TokenType::CONST));
+                }
+                TokenType::IDENTIFIER => {
+                    let name = self.chunk.add_name(&stmt.ident.as_ref().unwrap().lexeme);
+                    match &stmt.inner {
+                        Some(value) => {
+                            self.visit_expr(value);
+                            self.chunk.emit(OpCode::SetVar(name, stmt.depth, stmt.slot));
+                        }
+                        None => {
+                            self.chunk.emit(OpCode::GetVar(name, stmt.depth, stmt.slot));
+                        }
+                    }
+                }
+                TokenType::FUNC => {
+                    let idx = self.call(stmt);
+                    self.chunk.emit(OpCode::Call(idx));
+                }
+                _ => {
+                    self.chunk.emit(OpCode::Unsupported);
+                }
+            },
+            ExprPossibilities::Scope(scope) => self.scope(scope),
+        }
+    }
+}
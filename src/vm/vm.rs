@@ -0,0 +1,569 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+    mem,
+    rc::Rc,
+};
+
+use crate::{
+    ast::expr_types::Scope,
+    error_reporting::{error_reporter::Unwindable, interp_err::InterpException},
+    heap::heap::Gc,
+    interpreter::{
+        display,
+        environment::Environment,
+        interpreter::{Interpreter, DEFAULT_MAX_DEPTH, DEFAULT_MAX_STACK},
+        operations::{self, TailFrames},
+        permissions::Permissions,
+        sandbox::Sandbox,
+    },
+    lib_functions::{call_native, random::Rng, runtime::Runtime},
+    scanner::token::{Func, Primitive, Token, TokenType},
+};
+
+use super::{
+    chunk::{CallInfo, Chunk, OpCode},
+    compiler::{Compiler, Function},
+};
+
+const SWEEP_MIN: usize = 64;
+
+enum Completion {
+    // A statement evaluated to a value, ending the function
+    Return(Primitive),
+    // Ran off the end of the chunk, with whatever was left on the stack
+    End(Primitive),
+    // Left the function to make a call in its place
+    TailCall(Func, Token, Vec<Primitive>, Vec<(Token, Primitive)>),
+}
+
+// Stack based alternative to the tree-walking interpreter. Variables still live in
+// the same environments, so both engines behave the same down to how scopes are left.
+pub struct Vm {
+    pub globals: Environment,
+    pub max_depth: usize,
+    pub max_stack: usize,
+    pub stdout: Box<dyn Write>,
+    pub stderr: Box<dyn Write>,
+    pub stdin: Box<dyn BufRead>,
+    pub script_args: Vec<String>,
+    pub exit_code: Option<i32>,
+    pub failed: bool,
+    pub sandbox: Option<Sandbox>,
+    pub permissions: Permissions,
+    pub rng: Rng,
+    depth: usize,
+    // Where the stack was when the outermost call running was made
+    stack_base: usize,
+    // Compiled bodies by the address of the overload they came from, which each entry keeps
+    // alive so the address can't be reused while it's cached
+    functions: HashMap<*const Scope, (Rc<Scope>, Rc<Function>)>,
+    // How many bodies can be cached before dropping the ones no function holds anymore
+    sweep_at: usize,
+    // Value stacks of runs that have finished, kept so each call doesn't allocate its own
+    stacks: Vec<Vec<Primitive>>,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        return Self::with_globals(Interpreter::new().globals);
+    }
+
+    pub fn with_globals(globals: Environment) -> Self {
+        return Self {
+            globals,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_stack: DEFAULT_MAX_STACK,
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            stdin: Box::new(BufReader::new(io::stdin())),
+            script_args: Vec::new(),
+            exit_code: None,
+            failed: false,
+            sandbox: None,
+            permissions: Permissions::none(),
+            rng: Rng::from_time(),
+            depth: 0,
+            stack_base: 0,
+            functions: HashMap::new(),
+            sweep_at: SWEEP_MIN,
+            stacks: Vec::new(),
+        };
+    }
+
+    // Picks up where an interpreter left off, with the same input, output and limits
+    pub fn from_interpreter(interpreter: Interpreter) -> Self {
+        let mut vm = Self::with_globals(interpreter.globals);
+        vm.max_depth = interpreter.max_depth;
+        vm.max_stack = interpreter.max_stack;
+        vm.stdout = interpreter.stdout;
+        vm.stderr = interpreter.stderr;
+        vm.stdin = interpreter.stdin;
+        vm.script_args = interpreter.script_args;
+        vm.failed = interpreter.failed;
+        vm.sandbox = interpreter.sandbox;
+        vm.permissions = interpreter.permissions;
+        vm.rng = interpreter.rng;
+        return vm;
+    }
+
+    pub fn interpret(&mut self, chunk: &Chunk) {
+        let _metering = self.sandbox.as_ref().map(Sandbox::metering);
+        match self.run(chunk) {
+            Err(InterpException::Exit(code)) => self.exit_code = Some(code),
+            Err(err) => {
+                self.failed = true;
+                let _ = writeln!(self.stderr, "{}", err.get_value());
+            }
+            Ok(_) => (),
+        }
+    }
+
+    fn run(&mut self, chunk: &Chunk) -> Result<Completion, InterpException> {
+        let mut stack = self.stacks.pop().unwrap_or_default();
+        let result = self.execute(chunk, &mut stack);
+        stack.clear();
+        self.stacks.push(stack);
+        return result;
+    }
+
+    fn execute(&mut self, chunk: &Chunk, stack: &mut Vec<Primitive>) -> Result<Completion, InterpException> {
+        let mut ip = 0;
+
+        while ip < chunk.code.len() {
+            if let Some(sandbox) = &mut self.sandbox {
+                sandbox.step()?;
+            }
+            let op = chunk.code[ip];
+            ip += 1;
+            match op {
+                OpCode::Constant(idx) => stack.push(chunk.constants[idx].clone()),
+                OpCode::Nil => stack.push(Primitive::None),
+                OpCode::Pop => {
+                    stack.pop();
+                }
+                OpCode::GetVar(name, depth, slot) => {
+                    stack.push(self.lookup(&chunk.names[name], depth, slot)?);
+                }
+                OpCode::SetVar(name, depth, slot) => {
+                    let value = stack.pop().unwrap();
+                    match depth {
+                        Some(depth) => {
+                            self.globals.redefine_at(depth, slot, &chunk.names[name], value)?
+                        }
+                        None => self.globals.redefine(&chunk.names[name], value)?,
+                    }
+                    stack.push(Primitive::None);
+                }
+                OpCode::Declare(name, is_const) => {
+                    let value = stack.pop().unwrap();
+                    operations::declare(
+                        &mut self.globals,
+                        chunk.names[name].clone(),
+                        value,
+                        is_const,
+                    )?;
+                    stack.push(Primitive::None);
+                }
+                OpCode::Index(ident) => {
+                    let idx = stack.pop().unwrap();
+                    let Primitive::List(vec) = stack.pop().unwrap() else {
+                        return Err(InterpException::IdentifierNoExist(
+                            chunk.tokens[ident].to_string(),
+                        ));
+                    };
+                    let vec = vec.borrow();
+                    if let Primitive::Int(int) = idx && (int as usize) < vec.len() {
+                        stack.push(vec[int as usize].clone());
+                    } else {
+                        stack.push(Primitive::None);
+                    }
+                }
+                OpCode::MakeList(len) => {
+                    let list = stack.split_off(stack.len() - len);
+                    stack.push(Primitive::List(Gc::new(list)));
+                }
+                OpCode::Binary(operator) => {
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    let operator = &chunk.tokens[operator];
+                    let (left, right) = display::concat_operands(self, operator, left, right)?;
+                    let value = operations::binary(operator, left, right)?;
+                    if let Some(sandbox) = &self.sandbox {
+                        sandbox.check_value(&value)?;
+                    }
+                    stack.push(value);
+                }
+                OpCode::Unary(operator) => {
+                    let right = stack.pop().unwrap();
+                    stack.push(operations::unary(&chunk.tokens[operator], right)?);
+                }
+                OpCode::Jump(target) => ip = target,
+                OpCode::JumpIfFalse(target) => {
+                    if !operations::truthy(stack.pop().unwrap())? {
+                        ip = target;
+                    }
+                }
+                OpCode::JumpUnlessTrue(target) => {
+                    if Some(Primitive::Bool(true)) != stack.pop() {
+                        ip = target;
+                    }
+                }
+                OpCode::Print(newline) => {
+                    let output = display::to_string(self, &stack.pop().unwrap())?;
+                    operations::print(&mut *self.stdout, &output, newline)?;
+                    stack.push(Primitive::None);
+                }
+                OpCode::Call(idx) => {
+                    let (callee, positional, keywords) = self.pop_args(&chunk.calls[idx], stack)?;
+                    let value = self.call(callee, &chunk.calls[idx].ident, positional, keywords)?;
+                    stack.push(value);
+                }
+                OpCode::TailCall(idx) => {
+                    let call = &chunk.calls[idx];
+                    let (callee, positional, keywords) = self.pop_args(call, stack)?;
+                    if let Primitive::Func(func) = callee {
+                        return Ok(Completion::TailCall(func, call.ident.clone(), positional, keywords));
+                    }
+                    stack.push(self.call(callee, &call.ident, positional, keywords)?);
+                }
+                OpCode::ReturnIfSome => {
+                    let value = stack.pop().unwrap();
+                    if Primitive::None != value {
+                        return Ok(Completion::Return(value));
+                    }
+                }
+                OpCode::EnterScope => self.enclose(),
+                OpCode::ExitScope => self.globals.unwind(1),
+                OpCode::ExitScopeWriteBack => operations::leave_scope(&mut self.globals),
+                OpCode::Iterate(ident) => {
+                    let items = operations::iterate(&chunk.tokens[ident], stack.pop().unwrap())?;
+                    stack.push(Primitive::List(Gc::new(items)));
+                    stack.push(Primitive::Int(0));
+                }
+                OpCode::NextItem(name, exit) => {
+                    let Some(Primitive::Int(position)) = stack.pop() else {
+                        unreachable!()
+                    };
+                    let Some(Primitive::List(items)) = stack.last() else {
+                        unreachable!()
+                    };
+                    let item = items.borrow().get(position as usize).cloned();
+                    stack.push(Primitive::Int(position + 1));
+                    match item {
+                        Some(item) => self.globals.define(&chunk.names[name], item),
+                        None => ip = exit,
+                    }
+                }
+                OpCode::DefineFunc(idx) => {
+                    operations::define_func(&mut self.globals, chunk.functions[idx].clone())?;
+                    stack.push(Primitive::None);
+                }
+                OpCode::DeclareEnv(idx) => {
+                    let info = &chunk.envs[idx];
+                    let values = stack.split_off(stack.len() - info.fields.len());
+                    let mut clos_data = Environment::new();
+                    for ((field, is_const), value) in info.fields.iter().zip(values) {
+                        if *is_const {
+                            clos_data.define_const(&chunk.names[*field], value);
+                        } else {
+                            clos_data.define(&chunk.names[*field], value);
+                        }
+                    }
+                    for method in info.methods.iter() {
+                        operations::define_func(&mut clos_data, chunk.functions[*method].clone())?;
+                    }
+                    self.globals.define_env(&chunk.names[info.ident], clos_data);
+                    stack.push(Primitive::None);
+                }
+                OpCode::EnterEnv(name) => {
+                    let Primitive::Env(env) = self.globals.retrieve(&chunk.names[name])? else {
+                        return Err(InterpException::PlaceHolder);
+                    };
+                    operations::enter_env(&mut self.globals, &env);
+                }
+                OpCode::ExitEnv => operations::leave_env(&mut self.globals),
+                OpCode::Unsupported => return Err(InterpException::PlaceHolder),
+            }
+        }
+
+        return Ok(Completion::End(stack.pop().unwrap_or(Primitive::None)));
+    }
+
+    // Pops the arguments of a call off the stack, along with looking up what's called
+    fn pop_args(
+        &mut self,
+        call: &CallInfo,
+        stack: &mut Vec<Primitive>,
+    ) -> Result<(Primitive, Vec<Primitive>, Vec<(Token, Primitive)>), InterpException> {
+        let mut positional = stack.split_off(stack.len() - call.arg_count);
+        let keyword_values = positional.split_off(positional.len() - call.keywords.len());
+        let keywords: Vec<(Token, Primitive)> =
+            call.keywords.iter().cloned().zip(keyword_values).collect();
+        let callee = self.lookup(&call.ident.lexeme, call.depth, call.slot)?;
+        return Ok((callee, positional, keywords));
+    }
+
+    fn call(
+        &mut self,
+        callee: Primitive,
+        ident: &Token,
+        positional: Vec<Primitive>,
+        keywords: Vec<(Token, Primitive)>,
+    ) -> Result<Primitive, InterpException> {
+        match callee {
+            Primitive::Func(func) => {
+                let position = operations::stack_position();
+                if self.depth == 0 {
+                    self.stack_base = position;
+                }
+                if self.depth >= self.max_depth || self.stack_base.abs_diff(position) > self.max_stack {
+                    return Err(InterpException::StackOverflow(
+                        ident.lexeme.clone(),
+                        ident.line,
+                        self.depth,
+                    ));
+                }
+                self.depth += 1;
+                let result = self.run_call(func, Cow::Borrowed(ident), positional, keywords);
+                self.depth -= 1;
+                return result;
+            }
+            Primitive::NativeFunc(func) => {
+                if !keywords.is_empty() {
+                    return Err(InterpException::PlaceHolder);
+                }
+                return call_native(&func, positional, self);
+            }
+            _ => return Err(InterpException::PlaceHolder),
+        }
+    }
+
+    // Tail calls loop here instead of recursing, so they run in constant stack space
+    fn run_call(
+        &mut self,
+        mut func: Func,
+        mut ident: Cow<Token>,
+        mut positional: Vec<Primitive>,
+        mut keywords: Vec<(Token, Primitive)>,
+    ) -> Result<Primitive, InterpException> {
+        let base = self.globals.depth();
+        let mut frames = TailFrames::new();
+        // Depth of the scope of the function that made the tail call being run
+        let mut caller = None;
+        loop {
+            let arg_count = positional.len() + keywords.len();
+            let Some(overload) = func.resolve_index(arg_count) else {
+                return Err(InterpException::NoMatchingOverload(ident.lexeme.clone(), arg_count, ident.line));
+            };
+            let params = func.overloads[overload]
+                .params
+                .as_deref()
+                .unwrap_or_default();
+            // Nothing to match up when every parameter is given in order
+            let in_order =
+                keywords.is_empty() && positional.len() == params.len() && !params.iter().any(|param| param.variadic);
+            let bound = if in_order {
+                positional.into_iter().map(Some).collect()
+            } else {
+                Func::bind_args(&ident, params, positional, keywords)?
+            };
+            let code = self.function(&func, overload);
+            if let Some(caller) = caller {
+                frames.enter(&mut self.globals, caller, params);
+            }
+
+            // Parameters go straight into the entered scope, so a default sees the ones before it
+            self.enclose();
+            // Only a tail call's frames can have moved the scope away from right above the base
+            let scope = match caller {
+                Some(_) => self.globals.depth(),
+                None => base + 1,
+            };
+            for ((param, prim), default) in params.iter().zip(bound).zip(code.defaults.iter()) {
+                let prim = match prim {
+                    Some(prim) => prim,
+                    None => match self.run(default.as_ref().unwrap())? {
+                        Completion::Return(prim) | Completion::End(prim) => prim,
+                        Completion::TailCall(..) => unreachable!(),
+                    },
+                };
+                self.globals.define(&param.ident.lexeme, prim);
+            }
+
+            match self.run(&code.body)? {
+                // Returning a value leaves the function's scope, and any block it returned from
+                Completion::Return(prim) => {
+                    let depth = self.globals.depth();
+                    self.globals.unwind(depth - base);
+                    return Ok(prim);
+                }
+                Completion::End(_) => {
+                    operations::leave_scope(&mut self.globals);
+                    frames.leave(&mut self.globals);
+                    return Ok(Primitive::None);
+                }
+                Completion::TailCall(next, next_ident, next_positional, next_keywords) => {
+                    caller = Some(scope);
+                    func = next;
+                    ident = Cow::Owned(next_ident);
+                    positional = next_positional;
+                    keywords = next_keywords;
+                }
+            }
+        }
+    }
+
+    // Compiled bodies are cached per overload body, so functions that share one share its code
+    fn function(&mut self, func: &Func, overload: usize) -> Rc<Function> {
+        let scope = &func.overloads[overload];
+        if let Some((_, function)) = self.functions.get(&Rc::as_ptr(scope)) {
+            return function.clone();
+        }
+        if self.functions.len() >= self.sweep_at {
+            self.functions.retain(|_, (scope, _)| Rc::strong_count(scope) > 1);
+            self.sweep_at = SWEEP_MIN.max(self.functions.len() * 2);
+        }
+        let function = Rc::new(Compiler::compile_function(scope));
+        self.functions.insert(Rc::as_ptr(scope), (scope.clone(), function.clone()));
+        return function;
+    }
+
+    fn lookup(
+        &self,
+        ident: &str,
+        depth: Option<usize>,
+        slot: Option<usize>,
+    ) -> Result<Primitive, InterpException> {
+        match depth {
+            Some(depth) => return self.globals.retrieve_at(depth, slot, ident),
+            None => return self.globals.retrieve(ident),
+        }
+    }
+
+    fn enclose(&mut self) {
+        // Moves the current scope in rather than copying the whole chain
+        let enclosing = mem::replace(&mut self.globals, Environment::new());
+        self.globals.enclosing = Some(Box::new(enclosing));
+    }
+}
+
+impl Runtime for Vm {
+    fn globals(&mut self) -> &mut Environment {
+        return &mut self.globals;
+    }
+
+    fn permissions(&self) -> &Permissions {
+        return &self.permissions;
+    }
+
+    fn script_args(&self) -> &[String] {
+        return &self.script_args;
+    }
+
+    fn stdin(&mut self) -> &mut dyn BufRead {
+        return &mut *self.stdin;
+    }
+
+    fn sandbox(&mut self) -> Option<&mut Sandbox> {
+        return self.sandbox.as_mut();
+    }
+
+    fn rng(&mut self) -> &mut Rng {
+        return &mut self.rng;
+    }
+
+    fn invoke(
+        &mut self,
+        name: &str,
+        callee: Primitive,
+        args: Vec<Primitive>,
+    ) -> Result<Primitive, InterpException> {
+        if !matches!(callee, Primitive::Func(_) | Primitive::NativeFunc(_)) {
+            return Err(InterpException::NotCallable(name.to_string()));
+        }
+        let ident = Token::new(TokenType::IDENTIFIER, name.to_string(), 0, None);
+        let depth = self.globals.depth();
+        let result = self.call(callee, &ident, args, Vec::new());
+        let left_behind = self.globals.depth() - depth;
+        self.globals.unwind(left_behind);
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::testing::run_both;
+
+    #[test]
+    fn calls_bind_overloads_defaults_keywords_and_rest() {
+        let source = "
+            func area(w, h = w) {
+                return w * h;
+            }
+            func area(w, h, d) {
+                return w * h * d;
+            }
+            func total(first, ...rest) {
+                let sum = first;
+                for x in rest {
+                    sum = sum + x;
+                }
+                return sum;
+            }
+            println(area(3));
+            println(area(2, h = 5));
+            println(area(2, 3, 4));
+            println(total(1, 2, 3, 4));
+        ";
+        assert_eq!(run_both(source), "9\n10\n24\n10\n");
+    }
+
+    #[test]
+    fn loops_blocks_and_env_methods() {
+        let source = "
+            let i = 0;
+            let evens = [];
+            while i < 6 {
+                if i % 2 == 0 {
+                    env evens {
+                        append(i);
+                    }
+                }
+                i = i + 1;
+            }
+            println(evens);
+            decenv counter = {
+                let count = 0;
+                func bump(by) {
+                    count = count + by;
+                }
+            }
+            for (let n = 1; n <= 3; n = n + 1) {
+                env counter {
+                    bump(n);
+                }
+            }
+            env counter {
+                println(count);
+            }
+        ";
+        assert_eq!(run_both(source), "[0, 2, 4]\n6\n");
+    }
+
+    #[test]
+    fn deep_tail_recursion_and_runtime_errors() {
+        let source = "
+            func down(n) {
+                if n == 0 {
+                    return \"done\";
+                }
+                return down(n - 1);
+            }
+            println(down(100000));
+            println(1 / 0);
+        ";
+        assert_eq!(run_both(source), "done\nDivision by zero on line 9\n");
+    }
+}
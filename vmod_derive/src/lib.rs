@@ -0,0 +1,139 @@
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+// Derives for the conversion traits in vmod::lib_functions::convert. A struct becomes
+// an env with a variable per field, and is read back from any env holding them.
+
+#[proc_macro_derive(IntoPrimitive)]
+pub fn derive_into_primitive(input: TokenStream) -> TokenStream {
+    let (name, fields) = match parse_struct(input) {
+        Ok(parsed) => parsed,
+        Err(msg) => return compile_error(&msg),
+    };
+    let defines: String = fields
+        .iter()
+        .map(|field| {
+            format!(
+                "env.define(\"{0}\", ::vmod::lib_functions::convert::IntoPrimitive::into_primitive(self.{0}));",
+                field
+            )
+        })
+        .collect();
+    let code = format!(
+        "impl ::vmod::lib_functions::convert::IntoPrimitive for {name} {{
+            fn into_primitive(self) -> ::vmod::scanner::token::Primitive {{
+                let mut env = ::vmod::interpreter::environment::Environment::new();
+                {defines}
+                return ::vmod::lib_functions::convert::IntoPrimitive::into_primitive(env);
+            }}
+        }}"
+    );
+    code.parse().unwrap()
+}
+
+// Fields missing from the env are read as None, so only Option fields may be left out
+#[proc_macro_derive(FromPrimitive)]
+pub fn derive_from_primitive(input: TokenStream) -> TokenStream {
+    let (name, fields) = match parse_struct(input) {
+        Ok(parsed) => parsed,
+        Err(msg) => return compile_error(&msg),
+    };
+    let inits: String = fields
+        .iter()
+        .map(|field| {
+            format!(
+                "{field}: ::vmod::lib_functions::convert::FromPrimitive::from_primitive(
+                    env.vars.get(\"{field}\").cloned().unwrap_or(::vmod::scanner::token::Primitive::None),
+                ).map_err(|err| ::vmod::lib_functions::convert::field_error(\"{name}\", \"{field}\", err))?,"
+            )
+        })
+        .collect();
+    let code = format!(
+        "impl ::vmod::lib_functions::convert::FromPrimitive for {name} {{
+            fn from_primitive(
+                prim: ::vmod::scanner::token::Primitive,
+            ) -> ::std::result::Result<Self, ::vmod::error_reporting::interp_err::InterpException> {{
+                let ::vmod::scanner::token::Primitive::Env(env) = prim else {{
+                    return Err(::vmod::error_reporting::interp_err::InterpException::InvalidConversion(
+                        \"{name}\",
+                        prim.type_name(),
+                    ));
+                }};
+                let env = env.borrow();
+                return Ok(Self {{ {inits} }});
+            }}
+        }}"
+    );
+    code.parse().unwrap()
+}
+
+// Pulls the name and field names out of a struct with named fields
+fn parse_struct(input: TokenStream) -> Result<(String, Vec<String>), String> {
+    let mut tokens = input.into_iter();
+    let mut name = None;
+    while let Some(token) = tokens.next() {
+        let TokenTree::Ident(ident) = token else {
+            continue;
+        };
+        match ident.to_string().as_str() {
+            "struct" => {
+                if let Some(TokenTree::Ident(ident)) = tokens.next() {
+                    name = Some(ident.to_string());
+                }
+                break;
+            }
+            "enum" | "union" => break,
+            _ => (),
+        }
+    }
+    let Some(name) = name else {
+        return Err(String::from("Only structs can be converted to and from primitives"));
+    };
+
+    for token in tokens {
+        match token {
+            TokenTree::Punct(punct) if punct.as_char() == '<' => {
+                return Err(format!("{} cannot be generic to convert to and from primitives", name));
+            }
+            TokenTree::Group(group) if group.delimiter() == Delimiter::Brace => {
+                return Ok((name, parse_fields(group.stream())));
+            }
+            _ => (),
+        }
+    }
+    Err(format!("{} needs named fields to convert to and from primitives", name))
+}
+
+fn parse_fields(body: TokenStream) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut last_ident = None;
+    let mut in_type = false;
+    // Commas inside a type's angle brackets don't end the field
+    let mut angle_depth = 0;
+    let mut prev = ' ';
+    for token in body {
+        match token {
+            TokenTree::Punct(punct) if in_type => {
+                match punct.as_char() {
+                    '<' => angle_depth += 1,
+                    '>' if prev != '-' => angle_depth -= 1,
+                    ',' if angle_depth == 0 => in_type = false,
+                    _ => (),
+                }
+                prev = punct.as_char();
+            }
+            TokenTree::Punct(punct) if punct.as_char() == ':' => {
+                if let Some(field) = last_ident.take() {
+                    fields.push(field);
+                }
+                in_type = true;
+            }
+            TokenTree::Ident(ident) if !in_type => last_ident = Some(ident.to_string()),
+            _ => prev = ' ',
+        }
+    }
+    fields
+}
+
+fn compile_error(msg: &str) -> TokenStream {
+    format!("compile_error!({:?});", msg).parse().unwrap()
+}